@@ -0,0 +1,137 @@
+//! Alerts for classification anomalies, sent to a Slack-style webhook.
+use crate::classify::{Classification, Verdict};
+use eth2::types::Slot;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// Timeout for requests to the webhook.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertWebhookConfig {
+    /// URL to POST alerts to as `{"text": "..."}`, e.g. a Slack incoming webhook.
+    pub url: String,
+    /// Alert when this many consecutive classified slots are too close to call. Default: 10.
+    ///
+    /// Set to 0 to disable.
+    #[serde(default = "default_too_close_to_call_slots")]
+    pub too_close_to_call_slots: u64,
+    /// Alert when a node's distance to the canonical block exceeds this. Default: disabled.
+    #[serde(default)]
+    pub distance_threshold: Option<usize>,
+    /// Minimum time between repeats of the same alert, in seconds. Default: 3600.
+    ///
+    /// Each kind of alert is limited separately, and distance alerts are limited per node.
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+/// Decides which alerts to fire for each classified slot, and sends them.
+pub struct Alerter {
+    config: AlertWebhookConfig,
+    client: reqwest::Client,
+    /// Number of consecutive classified slots that were too close to call.
+    too_close_to_call_streak: u64,
+    /// Time at which each alert was last fired, by alert key.
+    last_fired: HashMap<String, Instant>,
+}
+
+impl Alerter {
+    pub fn new(config: AlertWebhookConfig) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Unable to build HTTP client for alerts: {e}"))?;
+        Ok(Self {
+            config,
+            client,
+            too_close_to_call_streak: 0,
+            last_fired: HashMap::new(),
+        })
+    }
+
+    /// Update the alert state with the classification of the canonical block at `slot` and each
+    /// node's distance to it, returning the alerts that are due at `now`.
+    pub fn check(
+        &mut self,
+        slot: Slot,
+        classification: &Classification,
+        distances: &[(&str, usize)],
+        now: Instant,
+    ) -> Vec<String> {
+        let mut alerts = vec![];
+
+        if matches!(classification.verdict, Verdict::TooCloseToCall { .. }) {
+            self.too_close_to_call_streak += 1;
+        } else {
+            self.too_close_to_call_streak = 0;
+        }
+        let streak = self.too_close_to_call_streak;
+        if self.config.too_close_to_call_slots > 0
+            && streak >= self.config.too_close_to_call_slots
+            && self.due("too_close_to_call", now)
+        {
+            alerts.push(format!(
+                "slot {slot}: {streak} consecutive slots too close to call ({})",
+                classification.verdict
+            ));
+        }
+
+        if let Some(threshold) = self.config.distance_threshold {
+            for (name, distance) in distances {
+                if *distance > threshold && self.due(&format!("distance:{name}"), now) {
+                    alerts.push(format!(
+                        "slot {slot}: {name} distance to canonical is {distance} \
+                         (threshold {threshold})"
+                    ));
+                }
+            }
+        }
+
+        alerts
+    }
+
+    /// Whether the alert identified by `key` may fire at `now`, recording it as fired if so.
+    fn due(&mut self, key: &str, now: Instant) -> bool {
+        let min_interval = Duration::from_secs(self.config.min_interval_secs);
+        if self
+            .last_fired
+            .get(key)
+            .is_some_and(|last| now.saturating_duration_since(*last) < min_interval)
+        {
+            return false;
+        }
+        self.last_fired.insert(key.to_string(), now);
+        true
+    }
+
+    /// Send an alert in the background, logging any errors.
+    pub fn send(&self, text: String) {
+        warn!("Alert: {text}");
+        let request = self
+            .client
+            .post(&self.config.url)
+            .json(&json!({ "text": text }));
+        tokio::spawn(async move {
+            if let Err(e) = request
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                error!("Unable to send alert: {e}");
+            }
+        });
+    }
+}
+
+fn default_too_close_to_call_slots() -> u64 {
+    10
+}
+
+fn default_min_interval_secs() -> u64 {
+    3600
+}
@@ -0,0 +1,69 @@
+use serde::Deserialize;
+use std::time::Duration;
+
+/// Exponential backoff between retries, shared by all retry mechanisms.
+///
+/// Each retry mechanism may override `base_ms` with its own setting, e.g. a node's
+/// `retry_backoff_ms`, keeping the rest of the policy.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackoffPolicy {
+    /// Delay before the first retry in milliseconds. Default: 500.
+    pub base_ms: u64,
+    /// Factor to multiply the delay by after each retry, at least 1. Default: 2.0.
+    pub multiplier: f64,
+    /// Upper limit on the delay in milliseconds, including jitter. Default: 30000.
+    pub max_ms: u64,
+    /// Fraction of each delay to randomly add or subtract, e.g. 0.1 for ±10%. Default: 0.0.
+    pub jitter: f64,
+}
+
+impl Default for BackoffPolicy {
+    fn default() -> Self {
+        Self {
+            base_ms: 500,
+            multiplier: 2.0,
+            max_ms: 30_000,
+            jitter: 0.0,
+        }
+    }
+}
+
+impl BackoffPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.multiplier >= 1.0 && self.multiplier.is_finite()) {
+            return Err(format!(
+                "retry_backoff.multiplier must be at least 1.0, got {}",
+                self.multiplier
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "retry_backoff.jitter must be between 0.0 and 1.0, got {}",
+                self.jitter
+            ));
+        }
+        Ok(())
+    }
+
+    /// The same policy starting from `base_ms` instead, if it is set.
+    pub fn with_base(self, base_ms: Option<u64>) -> Self {
+        Self {
+            base_ms: base_ms.unwrap_or(self.base_ms),
+            ..self
+        }
+    }
+
+    /// Delay before retry number `retry` (starting from 0).
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.jittered_delay(retry, rand::random())
+    }
+
+    /// Delay before retry number `retry`, using `random` in `[0, 1)` for the jitter.
+    pub fn jittered_delay(&self, retry: u32, random: f64) -> Duration {
+        let max_ms = self.max_ms as f64;
+        let delay_ms = (self.base_ms as f64 * self.multiplier.powf(retry as f64)).min(max_ms);
+        let jitter_ms = self.jitter * delay_ms * (2.0 * random - 1.0);
+        Duration::from_millis((delay_ms + jitter_ms).clamp(0.0, max_ms).round() as u64)
+    }
+}
@@ -0,0 +1,44 @@
+//! Export of dream blocks as pretty-printed JSON, for inspection without decoding SSZ.
+use eth2::types::{BlindedBeaconBlock, EthSpec, Slot};
+use std::path::{Path, PathBuf};
+use tokio::fs::{create_dir_all, write};
+use tracing::error;
+
+/// Directory that each dream block is written to as `{node_name}_{slot}.json`.
+#[derive(Debug)]
+pub struct BlockJsonDir {
+    dir: PathBuf,
+}
+
+impl BlockJsonDir {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    pub fn path(&self, node_name: &str, slot: Slot) -> PathBuf {
+        self.dir.join(format!("{node_name}_{slot}.json"))
+    }
+
+    /// Write `block` from `node_name` at `slot`, logging any errors.
+    pub async fn save<E: EthSpec>(
+        &self,
+        node_name: &str,
+        slot: Slot,
+        block: &BlindedBeaconBlock<E>,
+    ) {
+        let path = self.path(node_name, slot);
+        if let Err(e) = self.write(&path, block).await {
+            error!("unable to write block JSON {}: {}", path.display(), e);
+        }
+    }
+
+    async fn write<E: EthSpec>(
+        &self,
+        path: &Path,
+        block: &BlindedBeaconBlock<E>,
+    ) -> Result<(), String> {
+        let json = serde_json::to_vec_pretty(block).map_err(|e| e.to_string())?;
+        create_dir_all(&self.dir).await.map_err(|e| e.to_string())?;
+        write(path, json).await.map_err(|e| e.to_string())
+    }
+}
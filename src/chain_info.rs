@@ -0,0 +1,35 @@
+use eth2::types::{ChainSpec, ForkName};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Chain parameters resolved at startup, for tools that need to convert slots to timestamps.
+#[derive(Debug, Serialize)]
+pub struct ChainInfo {
+    pub genesis_time: u64,
+    pub genesis_slot: u64,
+    pub seconds_per_slot: u64,
+    /// Activation epoch of each fork, or `null` if it isn't scheduled.
+    pub fork_epochs: BTreeMap<String, Option<u64>>,
+}
+
+impl ChainInfo {
+    pub fn new(genesis_time: u64, spec: &ChainSpec) -> Self {
+        let fork_epochs = ForkName::list_all()
+            .into_iter()
+            .map(|fork| (fork.to_string(), spec.fork_epoch(fork).map(|e| e.as_u64())))
+            .collect();
+        Self {
+            genesis_time,
+            genesis_slot: spec.genesis_slot.as_u64(),
+            seconds_per_slot: spec.seconds_per_slot,
+            fork_epochs,
+        }
+    }
+
+    pub fn write_to_file(&self, path: &Path) -> Result<(), String> {
+        let bytes = serde_json::to_vec_pretty(self).map_err(|e| format!("JSON error: {}", e))?;
+        std::fs::write(path, bytes)
+            .map_err(|e| format!("unable to write {}: {}", path.display(), e))
+    }
+}
@@ -0,0 +1,37 @@
+/// Tracks consecutive request failures to a node, and trips once they reach a threshold.
+///
+/// Once tripped the breaker stays open until a request succeeds again.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: Option<u32>,
+    consecutive_failures: u32,
+}
+
+impl CircuitBreaker {
+    /// Create a breaker that opens after `threshold` consecutive failures, or never if `None`.
+    pub fn new(threshold: Option<u32>) -> Self {
+        Self {
+            threshold,
+            consecutive_failures: 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.threshold
+            .is_some_and(|threshold| self.consecutive_failures >= threshold)
+    }
+
+    /// Record a successful request, returning `true` if this closed the breaker.
+    pub fn record_success(&mut self) -> bool {
+        let was_open = self.is_open();
+        self.consecutive_failures = 0;
+        was_open
+    }
+
+    /// Record a failed request, returning `true` if this opened the breaker.
+    pub fn record_failure(&mut self) -> bool {
+        let was_open = self.is_open();
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+        !was_open && self.is_open()
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Verdict on which client produced the canonical block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// The two closest dream blocks have the same label.
+    TwoClosestMatch { label: String, distance: usize },
+    /// The closest dream block is significantly closer than the second closest.
+    SignificantlyCloser {
+        label: String,
+        distance: usize,
+        second_label: String,
+        second_distance: usize,
+    },
+    /// The two closest dream blocks are too close to separate.
+    TooCloseToCall {
+        name: String,
+        distance: usize,
+        second_name: String,
+        second_distance: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Classification {
+    pub verdict: Verdict,
+    /// Confidence in the verdict from 0.0 (toss-up) to 1.0 (certain), see `confidence`.
+    pub confidence: f64,
+}
+
+impl Verdict {
+    /// Short machine-readable name for the kind of verdict.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Verdict::TwoClosestMatch { .. } => "two_closest_match",
+            Verdict::SignificantlyCloser { .. } => "significantly_closer",
+            Verdict::TooCloseToCall { .. } => "too_close_to_call",
+        }
+    }
+
+    /// The label the canonical block is attributed to, or `None` if it is too close to call.
+    pub fn label(&self) -> Option<&str> {
+        match self {
+            Verdict::TwoClosestMatch { label, .. } | Verdict::SignificantlyCloser { label, .. } => {
+                Some(label)
+            }
+            Verdict::TooCloseToCall { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Verdict::TwoClosestMatch { label, distance } => write!(
+                f,
+                "canonical block is likely {label}@{distance} (two closest match)"
+            ),
+            Verdict::SignificantlyCloser {
+                label,
+                distance,
+                second_label,
+                second_distance,
+            } => write!(
+                f,
+                "canonical block is likely {label} \
+                 (significantly closer @{distance} than 2nd place {second_label}@{second_distance})"
+            ),
+            Verdict::TooCloseToCall {
+                name,
+                distance,
+                second_name,
+                second_distance,
+            } => write!(
+                f,
+                "canonical block is too close to call \
+                 ({name}@{distance} vs {second_name}@{second_distance})"
+            ),
+        }
+    }
+}
+
+/// Confidence that the closest block is the right one, given the two smallest distances.
+///
+/// The formula is `1 - (closest / second_closest) ^ exponent`, which is 0.0 when the two
+/// distances are equal and 1.0 when the closest distance is 0 and the second is not. Exponents
+/// greater than 1 give more confidence to small margins, exponents less than 1 give less.
+pub fn confidence(closest: usize, second_closest: usize, exponent: f64) -> f64 {
+    if second_closest == 0 {
+        return 0.0;
+    }
+    let ratio = closest as f64 / second_closest as f64;
+    (1.0 - ratio.powf(exponent)).clamp(0.0, 1.0)
+}
+
+/// Classify the canonical block using its distance to each dream block.
+///
+/// `distances` must be sorted by ascending distance. An error is returned if `distances` is empty
+/// or one of the closest nodes has no label (e.g. because it was removed from the config).
+///
+/// The closest block is significantly closer if the second closest is at least
+/// `significance_ratio` times as far away.
+pub fn classify(
+    distances: &[(&str, usize)],
+    labels: &HashMap<String, String>,
+    significance_ratio: f64,
+    confidence_exponent: f64,
+) -> Result<Classification, String> {
+    let (closest_name, closest_distance) = *distances.first().ok_or("no dream blocks")?;
+    let (second_closest_name, second_closest_distance) = *distances.get(1).unwrap_or(&distances[0]);
+
+    let get_label = |name: &str| {
+        labels
+            .get(name)
+            .ok_or_else(|| format!("no label for node {name}"))
+    };
+    let closest_label = get_label(closest_name)?;
+    let second_closest_label = get_label(second_closest_name)?;
+
+    let verdict = if closest_label == second_closest_label {
+        Verdict::TwoClosestMatch {
+            label: closest_label.clone(),
+            distance: closest_distance,
+        }
+    } else if second_closest_distance as f64 >= closest_distance as f64 * significance_ratio {
+        Verdict::SignificantlyCloser {
+            label: closest_label.clone(),
+            distance: closest_distance,
+            second_label: second_closest_label.clone(),
+            second_distance: second_closest_distance,
+        }
+    } else {
+        Verdict::TooCloseToCall {
+            name: closest_name.to_string(),
+            distance: closest_distance,
+            second_name: second_closest_name.to_string(),
+            second_distance: second_closest_distance,
+        }
+    };
+
+    Ok(Classification {
+        verdict,
+        confidence: confidence(
+            closest_distance,
+            second_closest_distance,
+            confidence_exponent,
+        ),
+    })
+}
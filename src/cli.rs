@@ -1,5 +1,7 @@
-use clap::Parser;
+use clap::{Parser, ValueEnum};
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
+use tracing_subscriber::filter::LevelFilter;
 
 #[derive(Parser, Debug)]
 #[command(author = "Blockprint Collective", version, long_about = None)]
@@ -8,7 +10,48 @@ pub struct CliConfig {
     /// Path to a TOML configuration file. See docs for examples
     #[arg(long, value_name = "PATH")]
     pub config: PathBuf,
+    /// Override the `canonical_bn` URL from the config file.
+    #[arg(long, value_name = "URL")]
+    pub canonical_bn: Option<String>,
     /// Timeout for genesis state download (if required).
     #[arg(long, value_name = "SECONDS", default_value = "180")]
     pub genesis_state_timeout: u64,
+    /// Number of times to retry the genesis state download if it fails.
+    #[arg(long, value_name = "N", default_value = "5")]
+    pub genesis_state_retries: u32,
+    /// Delay before the first genesis state retry. Later retries follow `retry_backoff`.
+    /// Default: `retry_backoff.base_ms`.
+    #[arg(long, value_name = "SECONDS")]
+    pub genesis_state_retry_delay: Option<u64>,
+    /// Exit at startup if any node or the canonical node is unreachable.
+    #[arg(long)]
+    pub require_all_nodes: bool,
+    /// Number of tokio worker threads for I/O and coordination. Default: one per CPU core.
+    #[arg(long, value_name = "N", env = "BLOCKDREAMER_WORKER_THREADS")]
+    pub worker_threads: Option<NonZeroUsize>,
+    /// Number of threads in the pool that computes block distances. Default: one per CPU core.
+    ///
+    /// Distances are computed on this pool while the worker thread that requested them waits,
+    /// so it bounds the CPU used by distance work independently of `--worker-threads`.
+    #[arg(long, value_name = "N", env = "BLOCKDREAMER_DISTANCE_THREADS")]
+    pub distance_threads: Option<NonZeroUsize>,
+    /// Minimum level of messages to log: off, error, warn, info, debug or trace.
+    #[arg(
+        long,
+        value_name = "LEVEL",
+        default_value = "info",
+        env = "BLOCKDREAMER_LOG_LEVEL"
+    )]
+    pub log_level: LevelFilter,
+    /// Format of log messages.
+    #[arg(long, value_name = "FORMAT", value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable lines.
+    Text,
+    /// One JSON object per line, with the message and fields such as `slot` and `node`.
+    Json,
 }
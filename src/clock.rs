@@ -0,0 +1,130 @@
+use eth2::types::Slot;
+use futures::future::{BoxFuture, FutureExt};
+#[cfg(test)]
+use slot_clock::ManualSlotClock;
+use slot_clock::{SlotClock, SystemTimeSlotClock};
+#[cfg(test)]
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(test)]
+use tokio::sync::Semaphore;
+use tracing::warn;
+
+/// The current slot, or an error if the clock reads before genesis.
+///
+/// This can happen mid-run if the system clock jumps backwards, e.g. after an NTP step or a VM
+/// resuming from suspend.
+pub fn current_slot<S: SlotClock>(slot_clock: &S) -> Result<Slot, String> {
+    slot_clock
+        .now()
+        .ok_or_else(|| "slot clock reads before genesis".to_string())
+}
+
+/// Time elapsed since the start of the current slot, or an error if the clock reads before genesis.
+pub fn time_into_slot<S: SlotClock>(slot_clock: &S) -> Result<Duration, String> {
+    slot_clock
+        .seconds_from_current_slot_start()
+        .ok_or_else(|| "slot clock reads before genesis".to_string())
+}
+
+/// Time from now until `offset` into the current slot, zero if that has already passed.
+pub fn time_until_offset<S: SlotClock>(
+    slot_clock: &S,
+    offset: Duration,
+) -> Result<Duration, String> {
+    Ok(offset.saturating_sub(time_into_slot(slot_clock)?))
+}
+
+/// Source of slot ticks that drives the main loop.
+///
+/// Abstracts over wall-clock time so that tests can step through slots on demand.
+pub trait Ticker: Send + Sync + 'static {
+    type Clock: SlotClock + Clone + Send + Sync + 'static;
+
+    /// Create the slot clock once the genesis time is known.
+    fn slot_clock(
+        &self,
+        genesis_slot: Slot,
+        genesis_duration: Duration,
+        slot_duration: Duration,
+    ) -> Self::Clock;
+
+    /// Wait until the start of the next slot.
+    fn tick<'a>(&'a self, slot_clock: &'a Self::Clock) -> BoxFuture<'a, ()>;
+}
+
+/// Ticker that follows the system time.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemTicker;
+
+impl Ticker for SystemTicker {
+    type Clock = SystemTimeSlotClock;
+
+    fn slot_clock(
+        &self,
+        genesis_slot: Slot,
+        genesis_duration: Duration,
+        slot_duration: Duration,
+    ) -> SystemTimeSlotClock {
+        SystemTimeSlotClock::new(genesis_slot, genesis_duration, slot_duration)
+    }
+
+    fn tick<'a>(&'a self, slot_clock: &'a SystemTimeSlotClock) -> BoxFuture<'a, ()> {
+        async move {
+            let wait = slot_clock.duration_to_next_slot().unwrap_or_else(|| {
+                warn!("unable to read slot clock, retrying in one slot");
+                slot_clock.slot_duration()
+            });
+            tokio::time::sleep(wait).await;
+        }
+        .boxed()
+    }
+}
+
+/// Ticker backed by a `ManualSlotClock`, which only advances when `advance_slot` is called.
+#[cfg(test)]
+#[derive(Clone)]
+pub struct ManualTicker {
+    slot_clock: ManualSlotClock,
+    ticks: Arc<Semaphore>,
+}
+
+#[cfg(test)]
+impl ManualTicker {
+    pub fn new(slot_clock: ManualSlotClock) -> Self {
+        Self {
+            slot_clock,
+            ticks: Arc::new(Semaphore::new(0)),
+        }
+    }
+
+    /// Move the clock to the start of the next slot, and wake the main loop.
+    pub fn advance_slot(&self) {
+        self.slot_clock.advance_slot();
+        self.ticks.add_permits(1);
+    }
+
+    /// Wake the main loop without moving the clock, e.g. after setting its time directly.
+    pub fn wake(&self) {
+        self.ticks.add_permits(1);
+    }
+}
+
+#[cfg(test)]
+impl Ticker for ManualTicker {
+    type Clock = ManualSlotClock;
+
+    /// Returns the clock this ticker was created with, ignoring the genesis parameters.
+    fn slot_clock(&self, _: Slot, _: Duration, _: Duration) -> ManualSlotClock {
+        self.slot_clock.clone()
+    }
+
+    fn tick<'a>(&'a self, _: &'a ManualSlotClock) -> BoxFuture<'a, ()> {
+        async move {
+            if let Ok(permit) = self.ticks.acquire().await {
+                permit.forget();
+            }
+        }
+        .boxed()
+    }
+}
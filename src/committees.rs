@@ -0,0 +1,86 @@
+use eth2::{
+    types::{Attestation, AttestationData, Epoch, EthSpec, Slot, StateId},
+    BeaconNodeHttpClient,
+};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Beacon committees for a range of epochs, keyed by `(slot, committee_index)`.
+#[derive(Debug, Default)]
+pub struct Committees {
+    epochs: HashSet<Epoch>,
+    committees: HashMap<(Slot, u64), Vec<u64>>,
+}
+
+impl Committees {
+    /// Fetch the committees for `epoch` from `client` if they haven't been already.
+    pub async fn load_epoch(
+        &mut self,
+        client: &BeaconNodeHttpClient,
+        epoch: Epoch,
+    ) -> Result<(), String> {
+        if self.epochs.contains(&epoch) {
+            return Ok(());
+        }
+        let committees = client
+            .get_beacon_states_committees(StateId::Head, None, None, Some(epoch))
+            .await
+            .map_err(|e| format!("Error fetching committees for epoch {epoch}: {e:?}"))?
+            .ok_or_else(|| format!("No committees for epoch {epoch}"))?
+            .data;
+        for committee in committees {
+            self.committees
+                .insert((committee.slot, committee.index), committee.validators);
+        }
+        self.epochs.insert(epoch);
+        Ok(())
+    }
+
+    /// Drop committees for epochs before `epoch`.
+    pub fn prune<E: EthSpec>(&mut self, epoch: Epoch) {
+        self.epochs.retain(|stored_epoch| *stored_epoch >= epoch);
+        self.committees
+            .retain(|(slot, _), _| slot.epoch(E::slots_per_epoch()) >= epoch);
+    }
+
+    /// Validator indices attesting to each `AttestationData` in `atts`.
+    ///
+    /// Attestations for committees that haven't been loaded are skipped.
+    pub fn attesting_indices<E: EthSpec>(
+        &self,
+        atts: &[Attestation<E>],
+    ) -> HashMap<AttestationData, BTreeSet<u64>> {
+        let mut indices = HashMap::<_, BTreeSet<_>>::new();
+        for att in atts {
+            let Some(committee) = self.committees.get(&(att.data.slot, att.data.index)) else {
+                continue;
+            };
+            indices.entry(att.data.clone()).or_default().extend(
+                committee
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, _)| att.aggregation_bits.get(*i).unwrap_or(false))
+                    .map(|(_, validator_index)| *validator_index),
+            );
+        }
+        indices
+    }
+}
+
+/// Validator indices attesting in only one of two blocks, for `AttestationData` in both.
+///
+/// Returns the indices only in `left` and the indices only in `right`.
+pub fn validator_differences(
+    left: &HashMap<AttestationData, BTreeSet<u64>>,
+    right: &HashMap<AttestationData, BTreeSet<u64>>,
+) -> (BTreeSet<u64>, BTreeSet<u64>) {
+    let mut only_left = BTreeSet::new();
+    let mut only_right = BTreeSet::new();
+    for (data, left_indices) in left {
+        let Some(right_indices) = right.get(data) else {
+            continue;
+        };
+        only_left.extend(left_indices.difference(right_indices));
+        only_right.extend(right_indices.difference(left_indices));
+    }
+    (only_left, only_right)
+}
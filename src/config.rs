@@ -1,4 +1,16 @@
+use crate::alert::AlertWebhookConfig;
+use crate::backoff::BackoffPolicy;
+use crate::distance::DistanceParams;
+use crate::maintenance::MaintenanceWindow;
+use crate::percentiles::DistancePercentilesConfig;
+use eth2::types::ForkName;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::Url;
+use sensitive_url::SensitiveUrl;
 use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::{
     fs::File,
@@ -6,6 +18,7 @@ use std::{
     path::Path,
     sync::Arc,
 };
+use tracing::warn;
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
@@ -13,18 +26,478 @@ pub struct Config {
     pub network: Option<String>,
     pub network_dir: Option<PathBuf>,
     pub canonical_bn: String,
+    /// Error at startup if `canonical_bn` is also one of the dreaming `nodes`, rather than just
+    /// warning. Default: false.
+    ///
+    /// A node whose own block is used as the canonical block will always be classified as closest.
+    #[serde(default)]
+    pub reject_canonical_node: bool,
+    /// Identifier for this run, used to separate results from multiple blockdreamer instances.
+    ///
+    /// Included in post endpoint payloads and results paths. Default: the `network` name, or the
+    /// name of the `network_dir`, so that results from restarts of the same instance are kept
+    /// together.
+    #[serde(default)]
+    pub run_id: Option<String>,
+    /// Use a random UUID as the `run_id`, giving each restart separate results. Default: false.
+    #[serde(default)]
+    pub random_run_id: bool,
+    /// File to write the genesis time, slot duration and fork schedule to at startup (JSON).
+    #[serde(default)]
+    pub chain_info_file: Option<PathBuf>,
+    /// Address to serve HTTP health checks (`/healthz` and `/readyz`), Prometheus metrics
+    /// (`/metrics`) and the latest distance matrix (`/matrix`) on. Default: disabled.
+    #[serde(default)]
+    pub http_address: Option<SocketAddr>,
+    /// OTLP collector to export per-slot traces to over gRPC, e.g. `http://localhost:4317`.
+    /// Default: disabled.
+    ///
+    /// Requires the `otel` feature.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// Named pipe (created with `mkfifo`) to write a JSON summary line to for each classified
+    /// slot. Default: disabled.
+    ///
+    /// Summaries are dropped rather than blocking if no reader is attached or the pipe is full, and
+    /// always if they are longer than `PIPE_BUF` (4096 bytes on Linux), as they could be split.
+    #[serde(default)]
+    pub summary_fifo: Option<PathBuf>,
+    /// Unix domain socket to stream each verdict to as a line of JSON. Default: disabled.
+    ///
+    /// Any number of clients may connect. Clients that fall too far behind skip verdicts. The
+    /// socket file is removed on shutdown, and a stale one is replaced at startup.
+    #[serde(default)]
+    pub verdict_socket: Option<PathBuf>,
+    /// Scale block request timeouts with the size of recent blocks from each node. Default: a
+    /// fixed timeout per node, see `Node::timeout_secs`.
+    #[serde(default)]
+    pub adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    /// Directory to save raw beacon node responses that fail to decode to. Default: disabled.
+    ///
+    /// Only responses decoded by blockdreamer itself (v2 SSZ) can be saved, as the other
+    /// endpoints are decoded within the eth2 client.
+    #[serde(default)]
+    pub debug_dir: Option<PathBuf>,
+    /// Maximum number of responses to save to `debug_dir`. Default: 100.
+    #[serde(default = "default_max_debug_dumps")]
+    pub max_debug_dumps: usize,
+    /// Directory to save each dream block to as pretty-printed JSON, named
+    /// `{node_name}_{slot}.json`. Default: disabled.
+    ///
+    /// Blocks are saved as blinded blocks, so execution payloads are represented by their header.
+    #[serde(default)]
+    pub block_json_dir: Option<PathBuf>,
+    /// Directory to write pairwise distances to as Parquet files. Default: disabled.
+    ///
+    /// Files are named `distances-{run_id}-{first_slot}-{last_slot}.parquet`. Slots at which all
+    /// nodes agree are written with a distance of 0 for each pair. Requires the `parquet` feature.
+    #[serde(default)]
+    pub parquet_dir: Option<PathBuf>,
+    /// Number of slots of distances to buffer before writing a new Parquet file. Default: 300.
+    #[serde(default = "default_parquet_flush_slots")]
+    pub parquet_flush_slots: u64,
+    /// File to append the distances for each slot to as lines of JSON, or "-" for stdout.
+    /// Default: disabled.
+    ///
+    /// Each line is written once the slot's canonical block has been compared, so lags the
+    /// pairwise distance log by one sampled slot.
+    #[serde(default)]
+    pub output_json: Option<String>,
+    /// Webhook to alert on classification anomalies, see `AlertWebhookConfig`. Default: disabled.
+    #[serde(default)]
+    pub alert_webhook: Option<AlertWebhookConfig>,
     /// URLs to push the dreamt blocks to (probably blockgauge).
     #[serde(default)]
     pub post_endpoints: Vec<PostEndpointConfig>,
+    /// Maximum number of posts to run at once, across all endpoints and slots. Default: no limit.
+    ///
+    /// Posts beyond the limit wait for others to complete. A message is logged for posts that
+    /// start after their slot has ended, and waiting posts are dropped after `max_post_lag_slots`.
+    #[serde(default)]
+    pub max_concurrent_posts: Option<usize>,
+    /// Number of slots after its slot that a post may wait for `max_concurrent_posts` before being
+    /// dropped. Default: 2.
+    #[serde(default = "default_max_post_lag_slots")]
+    pub max_post_lag_slots: u64,
+    /// Times during which dream blocks are not posted, e.g. `["Tue 02:00-02:30"]`. Default: none.
+    ///
+    /// Blocks are still requested and compared during a window. See `MaintenanceWindow` for the
+    /// format.
+    #[serde(default)]
+    pub maintenance_windows: Vec<MaintenanceWindow>,
     pub nodes: Vec<Arc<Node>>,
+    /// TOML file mapping node names to labels, e.g. `lighthouse-1 = "Lighthouse"`. Default: none.
+    ///
+    /// Labels from the file fill in nodes without an inline `label`. An inline `label` that
+    /// differs from the file is an error unless `labels_file_overrides` is set. Names in the file
+    /// that don't match a node are ignored. The file only applies to nodes in the config, not to
+    /// discovered nodes, which are labelled by their service tags.
+    #[serde(default)]
+    pub labels_file: Option<PathBuf>,
+    /// Let labels from `labels_file` replace conflicting inline labels. Default: false.
+    #[serde(default)]
+    pub labels_file_overrides: bool,
+    /// Service registry to discover additional nodes from, e.g. Consul's
+    /// `http://localhost:8500/v1/catalog/service/beacon-node`. Default: disabled.
+    ///
+    /// Each service instance becomes a node named after its service ID and labelled by its
+    /// `label=<label>` tag. Instances tagged `v3` use the v3 API. Nodes listed in `nodes` are
+    /// always kept, and discovered nodes with the same name are ignored.
+    #[serde(default)]
+    pub node_discovery_url: Option<String>,
+    /// Number of slots between queries to `node_discovery_url`. Default: 8.
+    #[serde(default = "default_node_discovery_interval_slots")]
+    pub node_discovery_interval_slots: u64,
+    /// Only log the N closest pairs of nodes each slot, rather than every pair. Default: all.
+    #[serde(default)]
+    pub log_top_n_pairs: Option<usize>,
+    /// Parameters for the block distance computation.
+    #[serde(default)]
+    pub distance: DistanceParams,
+    /// Secondary distance parameters to evaluate alongside `distance`. Default: none.
+    ///
+    /// Each slot the canonical block is classified using both parameter sets, and any slot where
+    /// the two verdicts disagree is logged. Useful for trialling new parameters before switching.
+    #[serde(default)]
+    pub shadow_distance: Option<DistanceParams>,
+    /// Log blocks containing multiple attestations with identical data whose aggregation bits
+    /// overlap by at least this fraction (0.0-1.0). Default: disabled.
+    #[serde(default)]
+    pub duplicate_attestation_overlap: Option<f64>,
+    /// Log each node's fee recipient every slot, and flag pairs of nodes whose blocks have
+    /// different fee recipients. Default: false.
+    ///
+    /// Useful for detecting misconfigured fee recipients. Doesn't contribute to the distance.
+    #[serde(default)]
+    pub compare_fee_recipients: bool,
+    /// Request a second block from each v3 node that prefers the builder's payload, and log its
+    /// value difference (MEV uplift) and distance relative to a locally built block. Default: false.
+    ///
+    /// The local block (`builder_boost_factor = 0`) is the one classified and posted, so this can't
+    /// be combined with per-node `builder_boost_factor`s. Doubles the number of block requests.
+    #[serde(default)]
+    pub compare_builder_boost: bool,
+    /// Source of the RANDAO reveal sent with block requests. Default: infinity.
+    ///
+    /// With `canonical`, the canonical block at the slot is fetched from `canonical_bn` once before
+    /// requesting blocks, and its real reveal is sent to every node (without skipping
+    /// verification). The canonical block is usually only published a few seconds into the slot,
+    /// so requests made at the start of the slot mostly fall back to the infinity signature. Each
+    /// fallback is logged.
+    #[serde(default)]
+    pub randao_source: RandaoSource,
+    /// Timeout for fetching the canonical block's RANDAO reveal with `randao_source = "canonical"`
+    /// in milliseconds. Default: 500.
+    ///
+    /// Block requests to all nodes wait for this fetch, so it is kept short.
+    #[serde(default = "default_canonical_randao_timeout_ms")]
+    pub canonical_randao_timeout_ms: u64,
+    /// Exponent used when computing the confidence of each verdict. Default: 1.0.
+    ///
+    /// See `classify::confidence` for the formula.
+    #[serde(default = "default_confidence_exponent")]
+    pub confidence_exponent: f64,
+    /// Ratio of the second closest distance to the closest distance above which the closest node
+    /// is considered significantly closer, if their labels differ. Default: 2.0.
+    ///
+    /// Verdicts below the ratio are too close to call. Must be at least 1.0.
+    #[serde(default = "default_significance_ratio")]
+    pub significance_ratio: f64,
+    /// Log the full delta for each comparison, and when each block is requested. Default: false.
+    #[serde(default)]
+    pub verbose: bool,
+    /// Minimum number of slots of blocks to keep in memory. Default: 8.
+    ///
+    /// Only the 2 most recent sampled slots are needed, unless `slot_sampling` requires more, in
+    /// which case the larger value is used. `distance_window` doesn't affect block retention, as
+    /// only the per-slot distances to canonical are kept for its window.
+    #[serde(default = "default_slots_in_memory")]
+    pub slots_in_memory: u64,
+    /// Only dream and classify every Nth slot, i.e. slots where `slot % slot_sampling == 0`.
+    /// Default: 1 (every slot).
+    ///
+    /// The canonical block is compared against the dream blocks from the previous sampled slot,
+    /// and at least `slot_sampling` slots of blocks are kept in memory so that they're available.
+    #[serde(default = "default_slot_sampling")]
+    pub slot_sampling: u64,
+    /// Time into each slot at which to request blocks, in milliseconds. Default: 0.
+    ///
+    /// Later requests mimic the timing of real proposers, which gives attestations from the
+    /// previous slot more time to arrive. Must be less than the slot duration. Nodes'
+    /// `sample_offsets_ms` are still relative to the start of the slot, so samples at earlier
+    /// offsets are requested immediately.
+    #[serde(default)]
+    pub request_offset_ms: u64,
+    /// Forks to dream blocks in, e.g. `["capella", "deneb"]`. Default: all forks.
+    ///
+    /// Slots in any other fork are skipped with a warning, which is a safety net against producing
+    /// meaningless distances across an untested fork transition.
+    #[serde(default)]
+    pub allowed_forks: Option<Vec<ForkName>>,
+    /// Only compare blocks from the same fork, skipping and logging any comparison between a
+    /// dream block and the canonical block (or two dream blocks) from different forks. Default:
+    /// false.
+    ///
+    /// Blocks either side of a fork boundary can otherwise be compared, but their distance mostly
+    /// reflects the difference in block structure.
+    #[serde(default)]
+    pub require_same_fork: bool,
+    /// Log a per-slot divergence score, aggregating the pairwise distances between all nodes with
+    /// this function (`mean`, `median` or `max`). Default: disabled.
+    ///
+    /// Unaffected by `pairwise_filter` and `log_top_n_pairs`.
+    #[serde(default)]
+    pub divergence: Option<DivergenceAggregation>,
+    /// For each pair of nodes, log how many validators are attested in one node's block but not
+    /// the other's, for attestation data included by both. Default: false.
+    ///
+    /// Committees are fetched from `canonical_bn`. The indices themselves are logged in verbose
+    /// mode.
+    #[serde(default)]
+    pub log_validator_differences: bool,
+    /// Log each node's distance to a consensus of the other nodes' blocks. Default: false.
+    ///
+    /// The consensus contains the attestations included by more than half of the other nodes,
+    /// see `distance::majority_attestations`. Only slots where every node produced a block are
+    /// compared.
+    #[serde(default)]
+    pub log_consensus_distance: bool,
+    /// Log each node's distribution of attestation target and source epochs, and the distance
+    /// between each pair of distributions. Default: false.
+    ///
+    /// This is a cheap alternative signal to the full attestation matching, see
+    /// `distance::EpochDistribution`.
+    #[serde(default)]
+    pub log_epoch_distributions: bool,
+    /// Log the distance between each compared canonical block and its parent. Default: false.
+    ///
+    /// This "canonical churn" is a baseline for how much blocks naturally change from one slot to
+    /// the next. The last canonical block is kept in memory, and reused if it is the parent of the
+    /// next one. Otherwise the parent is fetched from the canonical node, costing an extra
+    /// request per comparison, e.g. with `slot_sampling` > 1 or after a skipped slot.
+    #[serde(default)]
+    pub log_canonical_churn: bool,
+    /// Log the components of the distance (attestations, sync aggregate, execution payload and
+    /// operations) from the canonical block to the two closest nodes. Default: false.
+    #[serde(default)]
+    pub log_distance_breakdown: bool,
+    /// Merge nodes that produce identical blocks into a single entry (e.g. `a+b`) when
+    /// classifying the canonical block. Default: false.
+    ///
+    /// Blocks are compared by root each slot. If the merged nodes have different labels, the
+    /// entry's label is all of them joined by `/`.
+    #[serde(default)]
+    pub collapse_identical_nodes: bool,
+    /// Log a single line when all nodes produce identical blocks, instead of a zero distance for
+    /// every pair. Default: false.
+    #[serde(default)]
+    pub collapse_identical_blocks: bool,
+    /// Don't post blocks when all nodes produce identical blocks. Default: false.
+    #[serde(default)]
+    pub skip_posting_identical_blocks: bool,
+    /// Which pairs of nodes to log pairwise distances for, based on their labels. Default: all.
+    #[serde(default)]
+    pub pairwise_filter: PairwiseFilter,
+    /// Smallest `builder_boost_factor` that nodes may be configured with. Default: no limit.
+    #[serde(default)]
+    pub min_builder_boost_factor: Option<u64>,
+    /// Largest `builder_boost_factor` that nodes may be configured with. Default: no limit.
+    #[serde(default)]
+    pub max_builder_boost_factor: Option<u64>,
+    /// Clamp out-of-range builder boost factors to the limits instead of erroring. Default: false.
+    #[serde(default)]
+    pub clamp_builder_boost_factor: bool,
+    /// Number of times to retry fetching the canonical block if the canonical node doesn't have
+    /// it yet. Default: 0.
+    ///
+    /// Retries stop early if they would run past the end of the slot.
+    #[serde(default)]
+    pub canonical_block_retries: u32,
+    /// Delay before the first canonical block retry in milliseconds, with later retries following
+    /// `retry_backoff`. Default: `retry_backoff.base_ms`.
+    #[serde(default)]
+    pub canonical_block_retry_delay_ms: Option<u64>,
+    /// Backoff between retries of failed requests, e.g. `{ base_ms = 500, multiplier = 2.0 }`.
+    /// Default: see `BackoffPolicy`.
+    ///
+    /// Applies to canonical block, node block request and genesis state retries.
+    /// `canonical_block_retry_delay_ms`, a node's `retry_backoff_ms` and the
+    /// `--genesis-state-retry-delay` flag override `base_ms` for their retries if set.
+    #[serde(default)]
+    pub retry_backoff: BackoffPolicy,
+    /// How to identify the canonical block to compare dream blocks against. Default: slot.
+    ///
+    /// Reorgs of previously compared canonical blocks are logged in both modes.
+    #[serde(default)]
+    pub canonical_comparison: CanonicalComparison,
+    /// Also log each node's mean distance to canonical over this many recent slots. Default: off.
+    ///
+    /// Each node's per-slot distance to canonical is kept in memory for this many slots, while
+    /// blocks are only kept for `slots_in_memory`.
+    #[serde(default)]
+    pub distance_window: Option<u64>,
+    /// Track percentiles of each node's distance to canonical over a long window, see
+    /// `DistancePercentilesConfig`. Default: disabled.
+    #[serde(default)]
+    pub distance_percentiles: Option<DistancePercentilesConfig>,
+    /// Skip the canonical comparison after this many consecutive failures to fetch the canonical
+    /// block, until `canonical_bn` responds again. Default: never skip.
+    #[serde(default)]
+    pub canonical_failure_threshold: Option<u32>,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CanonicalComparison {
+    /// Fetch the canonical block by slot, following the canonical node's current head.
+    #[default]
+    Slot,
+    /// Look up the canonical block root for the slot first, then fetch the block by that root so
+    /// the comparison is pinned to a specific block.
+    Root,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PairwiseFilter {
+    /// Compare every pair of nodes.
+    #[default]
+    All,
+    /// Only compare nodes with the same label (same client, different configs).
+    Intra,
+    /// Only compare nodes with different labels.
+    Inter,
+}
+
+impl PairwiseFilter {
+    /// Whether a pair of nodes should be compared, given whether their labels match.
+    pub fn includes(self, same_label: bool) -> bool {
+        match self {
+            PairwiseFilter::All => true,
+            PairwiseFilter::Intra => same_label,
+            PairwiseFilter::Inter => !same_label,
+        }
+    }
+}
+
+/// Block request timeout of `base_ms + ms_per_kib * estimated_block_size_kib`, capped at `max_ms`.
+///
+/// The block size estimate for each node is a moving average of the SSZ size of its recent
+/// blocks. After a request times out, the estimate is raised so that the next request is allowed
+/// `max_ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdaptiveTimeoutConfig {
+    pub base_ms: u64,
+    pub ms_per_kib: f64,
+    pub max_ms: u64,
+}
+
+impl AdaptiveTimeoutConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.ms_per_kib >= 0.0 && self.ms_per_kib.is_finite()) {
+            return Err(format!(
+                "adaptive_timeout.ms_per_kib must be a non-negative number, got {}",
+                self.ms_per_kib
+            ));
+        }
+        if self.base_ms > self.max_ms {
+            return Err(format!(
+                "adaptive_timeout.base_ms ({}) must not exceed max_ms ({})",
+                self.base_ms, self.max_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DivergenceAggregation {
+    Mean,
+    Median,
+    Max,
+}
+
+impl DivergenceAggregation {
+    /// Aggregate `distances` into a single score, or `None` if there are no distances.
+    pub fn aggregate(self, distances: &[usize]) -> Option<f64> {
+        if distances.is_empty() {
+            return None;
+        }
+        let score = match self {
+            DivergenceAggregation::Mean => {
+                distances.iter().sum::<usize>() as f64 / distances.len() as f64
+            }
+            DivergenceAggregation::Median => {
+                let mut sorted = distances.to_vec();
+                sorted.sort_unstable();
+                let mid = sorted.len() / 2;
+                if sorted.len() % 2 == 0 {
+                    (sorted[mid - 1] + sorted[mid]) as f64 / 2.0
+                } else {
+                    sorted[mid] as f64
+                }
+            }
+            DivergenceAggregation::Max => *distances.iter().max()? as f64,
+        };
+        Some(score)
+    }
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Node {
     pub name: String,
+    /// Client type, used to classify blocks. Required unless provided by `labels_file`.
+    #[serde(default)]
     pub label: String,
     pub url: String,
+    /// Network directory to load this node's spec from, instead of the global network config.
+    /// Default: use the global spec.
+    ///
+    /// The spec is used for SSZ decoding, so this allows testing nodes that disagree about the
+    /// fork schedule. Blocks from nodes with mismatched specs may not be comparable.
+    #[serde(default)]
+    pub network_dir: Option<PathBuf>,
+    /// Extra HTTP headers to send with every request to this node, e.g. for routing.
+    ///
+    /// Values of headers that look sensitive (e.g. `Authorization`) are redacted when the config
+    /// is printed.
+    #[serde(default)]
+    pub headers: RequestHeaders,
+    /// Token to send as `Authorization: Bearer <token>` with every request to this node, e.g. for
+    /// an auth proxy. Default: none.
+    ///
+    /// Redacted when the config is printed. Can't be combined with an `Authorization` header in
+    /// `headers`.
+    #[serde(default)]
+    pub auth_token: Option<AuthToken>,
+    /// Path prefix under which the beacon API is served, e.g. `/beacon/` behind a reverse proxy.
+    #[serde(default)]
+    pub path_prefix: Option<String>,
+    /// Extra query parameters to append to block production requests, e.g. to enable a
+    /// client-specific debugging mode. Default: none.
+    ///
+    /// Clients may silently ignore parameters they don't recognise. Parameters set by
+    /// blockdreamer itself (e.g. `randao_reveal`) can't be overridden.
+    #[serde(default)]
+    pub query_params: BTreeMap<String, String>,
+    /// Timeout for block requests to this node in seconds. Default: 6.
+    ///
+    /// Can't be combined with `adaptive_timeout`.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Number of times to retry a failed block request. Default: 0.
+    ///
+    /// Retries stop early if they would run past the end of the slot. Requests made at
+    /// `sample_offsets_ms` aren't retried.
+    #[serde(default)]
+    pub max_retries: u32,
+    /// Delay before the first retry of a block request in milliseconds, with later retries
+    /// following `retry_backoff`. Default: `retry_backoff.base_ms`.
+    #[serde(default)]
+    pub retry_backoff_ms: Option<u64>,
     #[serde(default)]
     pub skip_randao_verification: bool,
     // Deprecated.
@@ -32,12 +505,186 @@ pub struct Node {
     pub use_builder: bool,
     #[serde(default = "default_true")]
     pub ssz: bool,
+    /// Retry a block request over JSON if the node's SSZ response can't be decoded, e.g. because
+    /// the node encodes blocks differently around a fork boundary. Default: false.
+    ///
+    /// Each fallback is logged. Requests that fail for other reasons, such as timeouts, are not
+    /// retried.
+    #[serde(default)]
+    pub ssz_fallback: bool,
     #[serde(default)]
     pub v3: bool,
+    /// Fall back to the v2 block production API if the node doesn't have the v3 endpoint (404 or
+    /// 405). Default: false.
+    ///
+    /// Once a node has fallen back it keeps using v2 until blockdreamer is restarted.
+    #[serde(default)]
+    pub v3_fallback: bool,
     #[serde(default = "default_true")]
     pub enabled: bool,
+    /// Builder boost factor passed to the v3 block production endpoint. Default: unset.
+    ///
+    /// The node compares `builder_value * builder_boost_factor / 100` against the local payload
+    /// value, so 100 compares them evenly, 0 always uses the local payload and `2**64 - 1`
+    /// always uses the builder payload. Ignored for v2 requests.
     #[serde(default)]
     pub builder_boost_factor: Option<u64>,
+    /// Request a block at each of these offsets (in milliseconds) into the slot and keep the one
+    /// with the most attestations. Default: a single request at the start of the slot.
+    #[serde(default)]
+    pub sample_offsets_ms: Vec<u64>,
+    /// Check that each block returned looks like the unsigned proposal requested, and log any
+    /// anomalies. Default: false.
+    ///
+    /// The block must be for the requested slot and the fork scheduled at that slot, and must
+    /// contain the `randao_reveal` that blockdreamer sent (see `randao_source`).
+    #[serde(default)]
+    pub check_unsigned_proposal: bool,
+    /// How to handle a block for a later slot than the one requested, which suggests that the
+    /// node's clock is fast. Default: reject.
+    #[serde(default)]
+    pub future_slot: FutureSlotHandling,
+    /// Requests for which this node should return different blocks, logging a possible cached
+    /// block if it doesn't. Default: none.
+    ///
+    /// Only enable these for nodes where the blocks really should differ, e.g. `builder_boost`
+    /// for nodes with a builder that is expected to provide payloads.
+    #[serde(default)]
+    pub expect_distinct_blocks: Vec<DistinctBlocks>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DistinctBlocks {
+    /// Local and builder blocks requested by `compare_builder_boost`.
+    BuilderBoost,
+    /// Blocks requested at each of `sample_offsets_ms`.
+    SampleOffsets,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RandaoSource {
+    /// The infinity signature, relying on `skip_randao_verification`.
+    #[default]
+    Infinity,
+    /// The reveal from the canonical block at the slot, if it has been published, falling back to
+    /// the infinity signature.
+    Canonical,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FutureSlotHandling {
+    /// Treat the request as failed, so the block isn't compared.
+    #[default]
+    Reject,
+    /// Log a warning but keep the block, storing it under the requested slot.
+    Warn,
+}
+
+/// Query parameters set by blockdreamer on block production requests.
+const RESERVED_QUERY_PARAMS: &[&str] = &[
+    "randao_reveal",
+    "graffiti",
+    "skip_randao_verification",
+    "builder_boost_factor",
+];
+
+impl Node {
+    /// Headers to send with every request to this node, including the `auth_token`.
+    pub fn header_map(&self) -> Result<HeaderMap, String> {
+        let mut headers = self.headers.header_map()?;
+        if let Some(token) = &self.auth_token {
+            if headers.contains_key(AUTHORIZATION) {
+                return Err(format!(
+                    "auth_token for node {} conflicts with its Authorization header",
+                    self.name
+                ));
+            }
+            let mut value = HeaderValue::from_str(&format!("Bearer {}", token.0))
+                .map_err(|e| format!("Invalid auth_token for node {}: {e}", self.name))?;
+            value.set_sensitive(true);
+            headers.insert(AUTHORIZATION, value);
+        }
+        Ok(headers)
+    }
+
+    /// Check that each of `query_params` has a valid name that isn't set by blockdreamer.
+    pub fn check_query_params(&self) -> Result<(), String> {
+        for name in self.query_params.keys() {
+            let valid = !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+            if !valid {
+                return Err(format!(
+                    "invalid query parameter name {name:?} for node {}",
+                    self.name
+                ));
+            }
+            if RESERVED_QUERY_PARAMS.contains(&name.as_str()) {
+                return Err(format!(
+                    "query parameter {name} for node {} is set by blockdreamer",
+                    self.name
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Header names containing any of these (case-insensitive) have their values redacted.
+const SENSITIVE_HEADER_PARTS: &[&str] = &["auth", "token", "key", "secret", "cookie", "password"];
+
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct AuthToken(pub String);
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("<redacted>")
+    }
+}
+
+#[derive(Default, Clone, Deserialize)]
+#[serde(transparent)]
+pub struct RequestHeaders(pub BTreeMap<String, String>);
+
+impl RequestHeaders {
+    pub fn header_map(&self) -> Result<HeaderMap, String> {
+        self.0
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("Invalid header name {name}: {e}"))?;
+                let value = HeaderValue::from_str(value)
+                    .map_err(|e| format!("Invalid value for header {name}: {e}"))?;
+                Ok((name, value))
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for RequestHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map()
+            .entries(self.0.iter().map(|(name, value)| {
+                let lower_name = name.to_lowercase();
+                let sensitive = SENSITIVE_HEADER_PARTS
+                    .iter()
+                    .any(|part| lower_name.contains(part));
+                (
+                    name,
+                    if sensitive {
+                        "<redacted>"
+                    } else {
+                        value.as_str()
+                    },
+                )
+            }))
+            .finish()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -46,9 +693,12 @@ pub struct PostEndpointConfig {
     pub name: String,
     /// URL to send data to. HTTPS and basic auth are both supported.
     pub url: String,
-    /// Directory to save post responses to.
+    /// Directory to save post responses to, under a subdirectory for the run ID.
+    ///
+    /// Each response is stored with the node's `client_version`, if it was known.
     pub results_dir: Option<PathBuf>,
-    /// Whether to post extra data about the nodes that produced the blocks. Default: true.
+    /// Whether to post extra data about the nodes that produced the blocks (and the run ID).
+    /// Default: true.
     #[serde(default = "default_true")]
     pub extra_data: bool,
     /// Whether to compare attestation rewards after POSTing to the endpoint. Default: false.
@@ -57,11 +707,95 @@ pub struct PostEndpointConfig {
     #[serde(default)]
     pub compare_rewards: bool,
     /// Only post blocks if all clients return a block. Default: false.
+    ///
+    /// A slot with missing blocks is reported as a failed post to this endpoint.
     #[serde(default)]
     pub require_all: bool,
+    /// Skip posting for slots where any node failed to produce a block. Default: false.
+    ///
+    /// Unlike `require_all`, skipped slots are not treated as failed posts: the skip is logged
+    /// along with each node's error, for collectors that only want complete slots.
+    #[serde(default)]
+    pub skip_on_node_error: bool,
     /// Only post blocks if all blocks have the same parent. Default: false.
     #[serde(default)]
     pub require_same_parent: bool,
+    /// Only post blocks that share the most common parent, if they make up at least this fraction
+    /// of the blocks. Default: disabled.
+    ///
+    /// Blocks on other parents (e.g. from a lagging node) are dropped and logged. Must be greater
+    /// than 0.5 and at most 1.0, and can't be combined with `require_same_parent`.
+    #[serde(default)]
+    pub same_parent_quorum: Option<f64>,
+    /// `Content-Type` header to send. Default: `application/json`.
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// `Accept` header to send. Default: none.
+    #[serde(default)]
+    pub accept: Option<String>,
+    /// Only post blocks if at least one of them has a reward of at least this many gwei.
+    /// Default: always post.
+    ///
+    /// Uses the consensus block value reported by nodes using the v3 API, so blocks from v2 nodes
+    /// never meet the threshold.
+    #[serde(default)]
+    pub min_reward_gwei: Option<u64>,
+    /// Protocol used to send blocks to the endpoint. Default: http.
+    ///
+    /// The `grpc` protocol sends SSZ blocks using the schema in `proto/blockdreamer.proto` and
+    /// requires the `grpc` feature. Responses are not inspected, so `results_dir` and
+    /// `compare_rewards` have no effect.
+    #[serde(default)]
+    pub protocol: PostProtocol,
+    /// What to send to the endpoint. Default: blocks.
+    ///
+    /// The `fingerprint` kind sends a compact summary of each block (see `Fingerprint`) instead
+    /// of the full block, always with the extra data. It is only supported over HTTP, and the
+    /// response is not inspected, so `results_dir` and `compare_rewards` have no effect.
+    #[serde(default)]
+    pub kind: PostKind,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostKind {
+    /// Full blinded blocks.
+    #[default]
+    Blocks,
+    /// Compact per-block fingerprints.
+    Fingerprint,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PostProtocol {
+    /// JSON over HTTP(S).
+    #[default]
+    Http,
+    /// Protobuf over gRPC.
+    Grpc,
+}
+
+/// Combine a node's `url` with an optional `path_prefix` under which the beacon API is served.
+///
+/// The prefix is appended to any path already present in `url`, so that requests made by the
+/// client (e.g. `/eth/v3/validator/blocks/{slot}`) land under the prefix.
+pub fn node_url(url: &str, path_prefix: Option<&str>) -> Result<SensitiveUrl, String> {
+    let mut url = Url::parse(url).map_err(|e| format!("Invalid URL: {:?}", e))?;
+    if let Some(prefix) = path_prefix {
+        url.path_segments_mut()
+            .map_err(|()| "Invalid URL: cannot be a base".to_string())?
+            .pop_if_empty()
+            .extend(prefix.split('/').filter(|segment| !segment.is_empty()));
+    }
+    SensitiveUrl::parse(url.as_str()).map_err(|e| format!("Invalid URL: {:?}", e))
+}
+
+/// Load a mapping from node name to label from a TOML file.
+pub fn load_labels_file(path: &Path) -> Result<HashMap<String, String>, String> {
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| format!("unable to read labels file {}: {e}", path.display()))?;
+    toml::from_str(&s).map_err(|e| format!("invalid labels file {}: {e}", path.display()))
 }
 
 impl Config {
@@ -69,10 +803,216 @@ impl Config {
         let mut f = File::open(path)?;
         let mut s = String::new();
         f.read_to_string(&mut s)?;
-        Ok(toml::from_str(&s).unwrap())
+        toml::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Run ID to use if neither `run_id` nor `random_run_id` is set.
+    pub fn default_run_id(&self) -> String {
+        self.network
+            .clone()
+            .or_else(|| {
+                let network_dir = self.network_dir.as_ref()?;
+                Some(network_dir.file_name()?.to_string_lossy().into_owned())
+            })
+            .unwrap_or_else(|| "blockdreamer".to_string())
+    }
+
+    /// Apply the labels from `labels_file` (if any) to the nodes.
+    ///
+    /// All labels are checked for conflicts before any are applied, so that no node is relabelled
+    /// if there is an error. Must be called before the node configs are shared.
+    pub fn apply_labels_file(&mut self) -> Result<(), String> {
+        let Some(path) = &self.labels_file else {
+            return Ok(());
+        };
+        let labels = load_labels_file(path)?;
+        let mut updates = vec![];
+        for (i, node) in self.nodes.iter().enumerate() {
+            let Some(label) = labels.get(&node.name) else {
+                continue;
+            };
+            if node.label == *label {
+                continue;
+            }
+            if !node.label.is_empty() && !self.labels_file_overrides {
+                return Err(format!(
+                    "label {label:?} for {} in {} conflicts with its label {:?} in the config, \
+                     set labels_file_overrides to prefer the labels file",
+                    node.name,
+                    path.display(),
+                    node.label
+                ));
+            }
+            updates.push((i, label));
+        }
+        for (i, label) in updates {
+            let node = &mut self.nodes[i];
+            let name = node.name.clone();
+            Arc::get_mut(node)
+                .ok_or_else(|| format!("config for {name} is already shared"))?
+                .label = label.clone();
+        }
+        Ok(())
+    }
+
+    /// Check the config for invalid values, normalizing them where configured to do so.
+    pub fn validate(&mut self) -> Result<(), String> {
+        if self.slot_sampling == 0 {
+            return Err("slot_sampling must be at least 1".into());
+        }
+        if self.run_id.is_some() && self.random_run_id {
+            return Err("run_id and random_run_id are mutually exclusive".into());
+        }
+        if self.max_concurrent_posts == Some(0) {
+            return Err("max_concurrent_posts must be at least 1".into());
+        }
+        if !(self.significance_ratio >= 1.0 && self.significance_ratio.is_finite()) {
+            return Err(format!(
+                "significance_ratio must be at least 1.0, got {}",
+                self.significance_ratio
+            ));
+        }
+        self.distance.validate()?;
+        self.retry_backoff.validate()?;
+        if let Some(adaptive_timeout) = &self.adaptive_timeout {
+            adaptive_timeout.validate()?;
+        }
+        if self
+            .distance_percentiles
+            .is_some_and(|percentiles| percentiles.window_slots == 0)
+        {
+            return Err("distance_percentiles.window_slots must be at least 1".into());
+        }
+        if let Some(shadow_distance) = &self.shadow_distance {
+            shadow_distance
+                .validate()
+                .map_err(|e| format!("shadow_distance: {e}"))?;
+        }
+        for node in &self.nodes {
+            if node.label.is_empty() {
+                return Err(format!("node {} has no label", node.name));
+            }
+            node.check_query_params()?;
+            self.check_timeout(node)?;
+        }
+        self.check_builder_boost_factors()?;
+        self.check_canonical_node_overlap()?;
+        Ok(())
+    }
+
+    fn check_timeout(&self, node: &Node) -> Result<(), String> {
+        match node.timeout_secs {
+            Some(0) => Err(format!("timeout_secs for {} must be at least 1", node.name)),
+            Some(_) if self.adaptive_timeout.is_some() => Err(format!(
+                "timeout_secs for {} conflicts with adaptive_timeout",
+                node.name
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_canonical_node_overlap(&self) -> Result<(), String> {
+        let canonical_url = node_url(&self.canonical_bn, None)?;
+        for node in self.nodes.iter().filter(|node| node.enabled) {
+            if node_url(&node.url, node.path_prefix.as_deref())?.full != canonical_url.full {
+                continue;
+            }
+            let message = format!(
+                "node {} has the same URL as canonical_bn, its blocks will be compared to themselves",
+                node.name
+            );
+            if self.reject_canonical_node {
+                return Err(message);
+            }
+            warn!("{message}");
+        }
+        Ok(())
+    }
+
+    fn check_builder_boost_factors(&mut self) -> Result<(), String> {
+        let min = self.min_builder_boost_factor.unwrap_or(u64::MIN);
+        let max = self.max_builder_boost_factor.unwrap_or(u64::MAX);
+        if min > max {
+            return Err(format!(
+                "min_builder_boost_factor ({min}) exceeds max_builder_boost_factor ({max})"
+            ));
+        }
+
+        if self.compare_builder_boost {
+            if let Some(node) = self
+                .nodes
+                .iter()
+                .find(|node| node.enabled && node.builder_boost_factor.is_some())
+            {
+                return Err(format!(
+                    "builder_boost_factor for {} conflicts with compare_builder_boost",
+                    node.name
+                ));
+            }
+        }
+
+        for node in &mut self.nodes {
+            let Some(factor) = node.builder_boost_factor else {
+                continue;
+            };
+            if (min..=max).contains(&factor) {
+                continue;
+            }
+            if !self.clamp_builder_boost_factor {
+                return Err(format!(
+                    "builder_boost_factor {factor} for {} is outside the range {min}..={max}",
+                    node.name
+                ));
+            }
+            let clamped = factor.clamp(min, max);
+            warn!(
+                node = node.name,
+                "Clamping builder_boost_factor from {factor} to {clamped}"
+            );
+            Arc::get_mut(node)
+                .ok_or("node config is shared")?
+                .builder_boost_factor = Some(clamped);
+        }
+        Ok(())
     }
 }
 
 fn default_true() -> bool {
     true
 }
+
+fn default_confidence_exponent() -> f64 {
+    1.0
+}
+
+fn default_significance_ratio() -> f64 {
+    2.0
+}
+
+fn default_slots_in_memory() -> u64 {
+    8
+}
+
+fn default_slot_sampling() -> u64 {
+    1
+}
+
+fn default_max_debug_dumps() -> usize {
+    100
+}
+
+fn default_parquet_flush_slots() -> u64 {
+    300
+}
+
+fn default_node_discovery_interval_slots() -> u64 {
+    8
+}
+
+fn default_canonical_randao_timeout_ms() -> u64 {
+    500
+}
+
+fn default_max_post_lag_slots() -> u64 {
+    2
+}
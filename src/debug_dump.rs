@@ -0,0 +1,52 @@
+use eth2::types::Slot;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::fs::{create_dir_all, File};
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+/// Writes raw beacon node responses that failed to decode to a directory, up to a limit.
+///
+/// Only v2 SSZ responses are dumped: v3 responses are read and decoded inside the eth2 client,
+/// which never hands back the raw bytes.
+#[derive(Debug)]
+pub struct DebugDumps {
+    dir: PathBuf,
+    remaining: AtomicUsize,
+}
+
+impl DebugDumps {
+    pub fn new(dir: PathBuf, max_dumps: usize) -> Self {
+        Self {
+            dir,
+            remaining: AtomicUsize::new(max_dumps),
+        }
+    }
+
+    /// Write `bytes` to `{node_name}_{slot}.{extension}`, unless the limit has been reached.
+    pub async fn dump(&self, node_name: &str, slot: Slot, extension: &str, bytes: &[u8]) {
+        let reserved =
+            self.remaining
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |remaining| {
+                    remaining.checked_sub(1)
+                });
+        match reserved {
+            Ok(1) => warn!("debug dump limit reached, no more responses will be saved"),
+            Ok(_) => {}
+            Err(_) => return,
+        }
+
+        let path = self.dir.join(format!("{node_name}_{slot}.{extension}"));
+        if let Err(e) = self.write(&path, bytes).await {
+            error!("unable to write debug dump {}: {}", path.display(), e);
+        } else {
+            info!("saved undecodable response to {}", path.display());
+        }
+    }
+
+    async fn write(&self, path: &Path, bytes: &[u8]) -> Result<(), std::io::Error> {
+        create_dir_all(&self.dir).await?;
+        let mut f = File::create(path).await?;
+        f.write_all(bytes).await
+    }
+}
@@ -0,0 +1,117 @@
+//! Discovery of dreaming nodes from a Consul-style service catalog.
+use crate::config::Node as NodeConfig;
+use eth2::types::Slot;
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tracing::warn;
+
+/// Timeout for requests to the service registry.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Prefix of the service tag holding the node's label, e.g. `label=Lighthouse`.
+const LABEL_TAG_PREFIX: &str = "label=";
+
+/// Service tag enabling the v3 block production API for the node.
+const V3_TAG: &str = "v3";
+
+/// One instance of a service, as returned by Consul's `/v1/catalog/service/:service` endpoint.
+#[derive(Debug, Deserialize)]
+pub struct CatalogEntry {
+    #[serde(rename = "ServiceID")]
+    pub service_id: String,
+    /// Address of the Consul node, used if the service doesn't have its own address.
+    #[serde(rename = "Address")]
+    pub address: String,
+    #[serde(rename = "ServiceAddress", default)]
+    pub service_address: String,
+    #[serde(rename = "ServicePort")]
+    pub service_port: u16,
+    #[serde(rename = "ServiceTags", default)]
+    pub service_tags: Vec<String>,
+}
+
+impl CatalogEntry {
+    /// Convert to a node config named after the service ID, with the label taken from its tags.
+    pub fn node_config(&self) -> Result<NodeConfig, String> {
+        let label = self
+            .service_tags
+            .iter()
+            .find_map(|tag| tag.strip_prefix(LABEL_TAG_PREFIX))
+            .ok_or_else(|| {
+                format!(
+                    "service {} has no `{LABEL_TAG_PREFIX}` tag",
+                    self.service_id
+                )
+            })?;
+        let address = if self.service_address.is_empty() {
+            &self.address
+        } else {
+            &self.service_address
+        };
+        let v3 = self.service_tags.iter().any(|tag| tag == V3_TAG);
+
+        // Deserialize so that all other fields take their defaults from the config.
+        serde_json::from_value(json!({
+            "name": self.service_id,
+            "label": label,
+            "url": format!("http://{}:{}", address, self.service_port),
+            "v3": v3,
+        }))
+        .map_err(|e| format!("invalid node config for service {}: {e}", self.service_id))
+    }
+}
+
+/// Periodically fetches the set of nodes from a service registry.
+pub struct NodeDiscovery {
+    url: String,
+    interval_slots: u64,
+    client: reqwest::Client,
+    last_fetch_slot: Option<Slot>,
+}
+
+impl NodeDiscovery {
+    pub fn new(url: String, interval_slots: u64) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .map_err(|e| format!("Unable to build HTTP client for node discovery: {e}"))?;
+        Ok(Self {
+            url,
+            interval_slots: std::cmp::max(interval_slots, 1),
+            client,
+            last_fetch_slot: None,
+        })
+    }
+
+    /// Whether the node set should be refreshed at `slot`.
+    pub fn is_due(&self, slot: Slot) -> bool {
+        self.last_fetch_slot
+            .is_none_or(|last| slot >= last + self.interval_slots)
+    }
+
+    /// Fetch the current nodes, skipping (and logging) services that can't be converted.
+    pub async fn fetch(&mut self, slot: Slot) -> Result<Vec<NodeConfig>, String> {
+        self.last_fetch_slot = Some(slot);
+        let entries = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| format!("error querying node registry {}: {e}", self.url))?
+            .json::<Vec<CatalogEntry>>()
+            .await
+            .map_err(|e| format!("invalid response from node registry {}: {e}", self.url))?;
+
+        Ok(entries
+            .iter()
+            .filter_map(|entry| {
+                entry
+                    .node_config()
+                    .map_err(|e| warn!("Ignoring discovered node: {e}"))
+                    .ok()
+            })
+            .collect())
+    }
+}
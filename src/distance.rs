@@ -1,27 +1,222 @@
-use eth2::types::{AbstractExecPayload, Attestation, AttestationData, BeaconBlock, EthSpec};
+use crate::distance_expr::{DistanceComponents, DistanceExpression};
+use eth2::types::{
+    AbstractExecPayload, AggregateSignature, Attestation, AttestationData, AttesterSlashing,
+    BeaconBlock, ChainSpec, Deposit, EthSpec, ExecPayload, ForkName, ProposerSlashing,
+    PublicKeyBytes, SignedBlsToExecutionChange, SignedVoluntaryExit,
+};
 use itertools::Itertools;
 use pathfinding::{kuhn_munkres::kuhn_munkres_min, matrix::Matrix};
-use std::collections::{HashMap, HashSet};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
+use std::hash::Hash;
 
-/// Cost of insertions and deletions (indels).
+/// Default cost of insertions and deletions (indels).
 ///
 /// This is calibrated to equal the maximum possible `pos_distance`.
 const INDEL_COST: usize = 128;
 
+/// Minimum total number of attestations for which attestation groups are matched in parallel.
+///
+/// Below this the matching is cheap enough that the overhead of using the thread pool dominates.
+const PARALLEL_MATCHING_MIN_ATTESTATIONS: usize = 32;
+
+/// Amount of gas used that counts as one unit of execution payload distance.
+const GAS_UNIT: u64 = 100_000;
+
+/// Parameters controlling how distances are computed.
+///
+/// The distance between blocks is a weighted sum of the attestation, sync aggregate and execution
+/// payload distances, plus `indel_cost` for each operation included in only one block.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DistanceParams {
+    /// Ignore the committee `index` of `AttestationData` when comparing attestations. Default: false.
+    ///
+    /// This LOOSENS the comparison: attestations that differ only in their `index` are treated as
+    /// comparable and grouped together. It exists for fork transitions (e.g. Electra) where clients
+    /// may briefly disagree about whether `index` is zeroed, and should only be enabled during such
+    /// known windows.
+    #[serde(default)]
+    pub ignore_committee_index: bool,
+    /// Sort each block's attestations into a canonical order before comparing blocks. Default:
+    /// false.
+    ///
+    /// This CHANGES the meaning of `pos_distance`: it measures how far apart two attestations are
+    /// in the canonical order rather than in the order chosen by the client, so blocks containing
+    /// the same attestations in a different order have a distance of 0. Positions in the resulting
+    /// deltas refer to the sorted order.
+    #[serde(default)]
+    pub sort_attestations: bool,
+    /// Canonicalize both blocks with `canonicalize` before comparing them. Default: false.
+    ///
+    /// This reorders attestations (like `sort_attestations`), proposer and attester slashings,
+    /// voluntary exits and BLS to execution changes, so that differences in serialization order
+    /// don't contribute to the distance. Deposits are left alone, as their order is fixed by the
+    /// deposit contract. Can't be combined with `max_attestations`.
+    #[serde(default)]
+    pub canonicalize: bool,
+    /// Only compare the first N attestations of each block, in block order. Default: 0 (all).
+    ///
+    /// This CHANGES the meaning of the distance, ignoring differences in how clients fill the
+    /// tail of the attestation list. It is meant for targeted analysis of the attestations that
+    /// clients rank highest, not for classification. Truncation happens before
+    /// `sort_attestations`.
+    #[serde(default)]
+    pub max_attestations: usize,
+    /// Ignore attestations less than this many slots older than the block. Default: 0 (none).
+    ///
+    /// An attestation is ignored if `block.slot - data.slot` is less than this, e.g. 2 ignores
+    /// attestations from the block's slot and the slot before it. These may not have propagated
+    /// to all nodes yet, so differences in them can reflect network timing rather than client
+    /// behavior. Ignored attestations count as neither a match nor an indel, and positions are
+    /// measured among the remaining attestations. Applied after `max_attestations`.
+    #[serde(default)]
+    pub exclude_recent_attestations: u64,
+    /// Weight of execution payload differences in the distance between blocks. Default: 0.0
+    /// (ignored).
+    ///
+    /// Blinded blocks only carry the payload header, so the execution distance counts 1 for a
+    /// different `transactions_root`, 1 per 100k difference in `gas_used` and 1 per differing
+    /// byte of `extra_data`. This is multiplied by the weight and added to the attestation
+    /// distance, so keep the weight small enough that it doesn't swamp the attestations.
+    #[serde(default)]
+    pub execution_weight: f64,
+    /// Weight of the attestation distance in the distance between blocks. Default: 1.0.
+    #[serde(default = "default_weight")]
+    pub attestation_weight: f64,
+    /// Weight of the number of differing sync committee bits in the distance between blocks.
+    /// Default: 0.0 (ignored).
+    ///
+    /// Blocks prior to Altair have no sync aggregate, so contribute nothing. The number of
+    /// differing bits is logged with each node's distance to canonical either way.
+    #[serde(default)]
+    pub sync_weight: f64,
+    /// Cost of an attestation or operation included in only one of the blocks. Default: 128.
+    ///
+    /// The default equals the maximum possible difference in position between two matched
+    /// attestations. This also affects how attestations are matched, as an unmatched attestation
+    /// costs this plus its number of set bits.
+    #[serde(default = "default_indel_cost")]
+    pub indel_cost: usize,
+    /// Which parts of the blocks to compare. Default: "full".
+    ///
+    /// With "consensus_only", the execution payload (and blob commitments) are ignored entirely,
+    /// so blocks with identical consensus content compare as equal whatever their payloads. This
+    /// can't be combined with a non-zero `execution_weight`.
+    #[serde(default)]
+    pub compare_scope: CompareScope,
+    /// Expression computing the block distance from its components, in place of the weighted
+    /// sum. Default: none.
+    ///
+    /// Supports numbers, `+`, `-`, `*`, `/`, parentheses, `min(a, b)` and `max(a, b)` over the
+    /// unweighted components of the difference between two blocks listed in
+    /// [`Variable`](crate::distance_expr::Variable), e.g. `att` and `ops`.
+    ///
+    /// The weights are NOT applied, so e.g. `"att + ops"` matches the default weights. Division by
+    /// zero yields 0 and negative results count as 0. The expression is checked when the config is
+    /// loaded. Such distances have no upper bound, so normalized distances aren't logged.
+    #[serde(default)]
+    pub expression: Option<DistanceExpression>,
+}
+
+impl Default for DistanceParams {
+    fn default() -> Self {
+        Self {
+            ignore_committee_index: false,
+            sort_attestations: false,
+            canonicalize: false,
+            max_attestations: 0,
+            exclude_recent_attestations: 0,
+            execution_weight: 0.0,
+            attestation_weight: default_weight(),
+            sync_weight: 0.0,
+            indel_cost: default_indel_cost(),
+            compare_scope: CompareScope::default(),
+            expression: None,
+        }
+    }
+}
+
+impl DistanceParams {
+    pub fn validate(&self) -> Result<(), String> {
+        for (name, weight) in [
+            ("attestation_weight", self.attestation_weight),
+            ("sync_weight", self.sync_weight),
+            ("execution_weight", self.execution_weight),
+        ] {
+            if !weight.is_finite() || weight < 0.0 {
+                return Err(format!(
+                    "{name} must be a non-negative number, got {weight}"
+                ));
+            }
+        }
+        if self.canonicalize && self.max_attestations != 0 {
+            return Err("canonicalize can't be combined with max_attestations".into());
+        }
+        if self.compare_scope == CompareScope::ConsensusOnly && self.execution_weight != 0.0 {
+            return Err(format!(
+                "execution_weight ({}) must be 0 with compare_scope = \"consensus_only\"",
+                self.execution_weight
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CompareScope {
+    /// Compare the consensus content of blocks only, ignoring their execution payloads.
+    ConsensusOnly,
+    /// Compare everything that contributes to the distance, including the execution payload if
+    /// `execution_weight` is non-zero.
+    #[default]
+    Full,
+}
+
+/// Scale `distance` to `[0, 1]` by its upper bound `max_distance`.
+///
+/// The result is clamped to 1, as bounds may not hold for unusual parameters (e.g. an `indel_cost`
+/// lower than the maximum position difference).
+pub fn normalize_distance(distance: usize, max_distance: usize) -> f64 {
+    if max_distance == 0 {
+        0.0
+    } else {
+        f64::min(distance as f64 / max_distance as f64, 1.0)
+    }
+}
+
 pub trait Distance {
     /// The type of intermediate data when computing the distance (mostly useful for diagnostics).
     type Delta;
 
     /// Distance between `self` and `other`, or `None` if incomparable.
-    fn distance(&self, other: &Self) -> Option<usize> {
-        self.delta(other).as_ref().map(Self::delta_to_distance)
+    fn distance(&self, other: &Self, params: &DistanceParams) -> Option<usize> {
+        self.delta(other, params)
+            .map(|delta| Self::delta_to_distance(&delta, params))
     }
 
     /// Detailed delta between `self` and `other`, or `None` if incomparable.
-    fn delta(&self, other: &Self) -> Option<Self::Delta>;
+    fn delta(&self, other: &Self, params: &DistanceParams) -> Option<Self::Delta>;
+
+    /// Convert a delta for this type to a distance, applying the weights from `params`.
+    fn delta_to_distance(delta: &Self::Delta, params: &DistanceParams) -> usize;
+
+    /// Upper bound on the distance between `self` and `other`, or `None` if there isn't one.
+    fn max_distance(&self, _other: &Self, _params: &DistanceParams) -> Option<usize> {
+        None
+    }
 
-    /// Convert a delta for this type to a distance.
-    fn delta_to_distance(delta: &Self::Delta) -> usize;
+    /// Distance between `self` and `other` scaled to `[0, 1]` by `max_distance`, or `None` if
+    /// they are incomparable or there is no upper bound.
+    fn normalized_distance(&self, other: &Self, params: &DistanceParams) -> Option<f64> {
+        let distance = self.distance(other, params)?;
+        let max_distance = self.max_distance(other, params)?;
+        Some(normalize_distance(distance, max_distance))
+    }
 
     /// Invert a delta converting it from left-right to right-left form.
     ///
@@ -31,29 +226,183 @@ pub trait Distance {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AttestationDelta {
+    /// Number of aggregation bits set in one attestation but not the other.
+    pub bit_distance: usize,
+    /// Whether the attestations have identical bits but only one of them has a signature.
+    ///
+    /// This is purely diagnostic and doesn't contribute to the distance. Signatures are never
+    /// verified, we only check whether they are empty.
+    pub signature_mismatch: bool,
+}
+
 impl<E: EthSpec> Distance for Attestation<E> {
-    type Delta = usize;
+    type Delta = AttestationDelta;
 
-    fn delta(&self, other: &Self) -> Option<usize> {
-        if self.data != other.data {
+    fn delta(&self, other: &Self, params: &DistanceParams) -> Option<AttestationDelta> {
+        if comparison_key(&self.data, params) != comparison_key(&other.data, params) {
             return None;
         }
         let agg1_unique = self.aggregation_bits.difference(&other.aggregation_bits);
         let agg2_unique = other.aggregation_bits.difference(&self.aggregation_bits);
-        Some(agg1_unique.num_set_bits() + agg2_unique.num_set_bits())
+        let bit_distance = agg1_unique.num_set_bits() + agg2_unique.num_set_bits();
+
+        let empty_signature = AggregateSignature::empty();
+        let signature_mismatch = bit_distance == 0
+            && (self.signature == empty_signature) != (other.signature == empty_signature);
+
+        Some(AttestationDelta {
+            bit_distance,
+            signature_mismatch,
+        })
+    }
+
+    fn delta_to_distance(delta: &AttestationDelta, _: &DistanceParams) -> usize {
+        delta.bit_distance
     }
 
-    fn delta_to_distance(delta: &usize) -> usize {
-        *delta
+    fn max_distance(&self, other: &Self, _: &DistanceParams) -> Option<usize> {
+        Some(self.aggregation_bits.num_set_bits() + other.aggregation_bits.num_set_bits())
     }
 }
 
+/// The part of an attestation's data used to decide whether two attestations are comparable.
+fn comparison_key(data: &AttestationData, params: &DistanceParams) -> AttestationData {
+    let mut key = data.clone();
+    if params.ignore_committee_index {
+        key.index = 0;
+    }
+    key
+}
+
 type IndexMap<'a, E> = HashMap<AttestationData, Vec<(usize, &'a Attestation<E>)>>;
 
-fn index_by_attestation_data<E: EthSpec>(atts: &[Attestation<E>]) -> IndexMap<E> {
+fn index_by_attestation_data<'a, E: EthSpec>(
+    atts: &'a [Attestation<E>],
+    params: &DistanceParams,
+) -> IndexMap<'a, E> {
     atts.iter()
         .enumerate()
-        .into_group_map_by(|(_, att)| att.data.clone())
+        .into_group_map_by(|(_, att)| comparison_key(&att.data, params))
+}
+
+/// Fraction of the smaller attestation's set bits that are also set in the other attestation.
+fn overlap_fraction<E: EthSpec>(att1: &Attestation<E>, att2: &Attestation<E>) -> f64 {
+    let shared = att1
+        .aggregation_bits
+        .intersection(&att2.aggregation_bits)
+        .num_set_bits();
+    let smaller = std::cmp::min(
+        att1.aggregation_bits.num_set_bits(),
+        att2.aggregation_bits.num_set_bits(),
+    );
+    shared as f64 / std::cmp::max(smaller, 1) as f64
+}
+
+/// Find attestations within a single block that duplicate each other.
+///
+/// An attestation is a duplicate if another attestation in the block has identical
+/// `AttestationData` and at least `min_overlap` of the smaller one's bits are shared. Returns the
+/// number of duplicates for each such `AttestationData`.
+pub fn find_duplicate_attestations<E: EthSpec>(
+    atts: &[Attestation<E>],
+    min_overlap: f64,
+) -> Vec<(AttestationData, usize)> {
+    index_by_attestation_data(atts, &DistanceParams::default())
+        .into_iter()
+        .filter_map(|(data, group)| {
+            let num_duplicates = group
+                .iter()
+                .filter(|(i, att1)| {
+                    group
+                        .iter()
+                        .any(|(j, att2)| i != j && overlap_fraction(att1, att2) >= min_overlap)
+                })
+                .count();
+            (num_duplicates > 0).then_some((data, num_duplicates))
+        })
+        .collect()
+}
+
+/// Number of attestations for each target and source epoch in a block.
+///
+/// This is a coarse but cheap fingerprint: clients that include more late attestations have more
+/// weight on older epochs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EpochDistribution {
+    pub target: BTreeMap<u64, usize>,
+    pub source: BTreeMap<u64, usize>,
+}
+
+impl EpochDistribution {
+    pub fn new<E: EthSpec>(atts: &[Attestation<E>]) -> Self {
+        let mut distribution = Self::default();
+        for att in atts {
+            *distribution
+                .target
+                .entry(att.data.target.epoch.as_u64())
+                .or_default() += 1;
+            *distribution
+                .source
+                .entry(att.data.source.epoch.as_u64())
+                .or_default() += 1;
+        }
+        distribution
+    }
+
+    /// L1 distance between the target histograms plus the L1 distance between the source
+    /// histograms.
+    pub fn distance(&self, other: &Self) -> usize {
+        l1_distance(&self.target, &other.target) + l1_distance(&self.source, &other.source)
+    }
+}
+
+fn l1_distance(histogram1: &BTreeMap<u64, usize>, histogram2: &BTreeMap<u64, usize>) -> usize {
+    histogram1
+        .keys()
+        .chain(histogram2.keys())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .map(|epoch| {
+            let count1 = histogram1.get(epoch).copied().unwrap_or(0);
+            let count2 = histogram2.get(epoch).copied().unwrap_or(0);
+            count1.abs_diff(count2)
+        })
+        .sum()
+}
+
+/// Synthesize the attestations of a "consensus" block from several blocks' attestations.
+///
+/// An attestation is included if more than half of the `attestation_sets` include an attestation
+/// with identical data and aggregation bits (signatures are ignored). Attestations are ordered by
+/// their first appearance when scanning the sets in order.
+pub fn majority_attestations<E: EthSpec>(
+    attestation_sets: &[&[Attestation<E>]],
+) -> Vec<Attestation<E>> {
+    let mut counts = HashMap::new();
+    let mut first_appearance = vec![];
+
+    for atts in attestation_sets {
+        let mut seen_in_set = HashSet::new();
+        for att in atts.iter() {
+            let key = (&att.data, &att.aggregation_bits);
+            if !seen_in_set.insert(key) {
+                continue;
+            }
+            let count = counts.entry(key).or_insert(0);
+            if *count == 0 {
+                first_appearance.push(att);
+            }
+            *count += 1;
+        }
+    }
+
+    first_appearance
+        .into_iter()
+        .filter(|att| 2 * counts[&(&att.data, &att.aggregation_bits)] > attestation_sets.len())
+        .cloned()
+        .collect()
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -72,6 +421,8 @@ pub enum Delta {
         ///
         /// i.e. `atts1[left].distance(atts2[right])`
         bit_distance: usize,
+        /// Whether the attestations differ only in the presence of a signature (diagnostic only).
+        signature_mismatch: bool,
     },
     /// Insert a new attestation on the left without matching it against any right attestation.
     InsertLeft { index: usize, num_set_bits: usize },
@@ -80,7 +431,7 @@ pub enum Delta {
 }
 
 impl Delta {
-    fn total_distance(&self) -> usize {
+    fn total_distance(&self, indel_cost: usize) -> usize {
         match self {
             Delta::Modify {
                 pos_distance,
@@ -88,7 +439,7 @@ impl Delta {
                 ..
             } => *pos_distance + *bit_distance,
             Delta::InsertLeft { num_set_bits, .. } | Delta::InsertRight { num_set_bits, .. } => {
-                *num_set_bits + INDEL_COST
+                *num_set_bits + indel_cost
             }
         }
     }
@@ -108,6 +459,7 @@ fn abs_diff(x: usize, y: usize) -> usize {
 fn compute_matching_att_deltas<E: EthSpec>(
     atts1: &[(usize, &Attestation<E>)],
     atts2: &[(usize, &Attestation<E>)],
+    params: &DistanceParams,
 ) -> Vec<Delta> {
     // Create a matrix with one row for each member of `atts1` and one column
     // for each member of `atts2`.
@@ -123,13 +475,14 @@ fn compute_matching_att_deltas<E: EthSpec>(
                 match (atts1.get(i), atts2.get(j)) {
                     // One side is out of bounds: this represents an insertion.
                     (Some((_, att)), None) | (None, Some((_, att))) => {
-                        att.aggregation_bits.num_set_bits() + INDEL_COST
+                        att.aggregation_bits.num_set_bits() + params.indel_cost
                     }
                     // Both sides are in bounds.
                     (Some((pos1, att1)), Some((pos2, att2))) => {
                         let pos_distance = abs_diff(*pos1, *pos2);
-                        let bit_distance =
-                            att1.distance(att2).expect("attestations are comparable");
+                        let bit_distance = att1
+                            .distance(att2, params)
+                            .expect("attestations are comparable");
                         pos_distance + bit_distance
                     }
                     // Neither side is in bounds.
@@ -152,13 +505,19 @@ fn compute_matching_att_deltas<E: EthSpec>(
             // Diff between two attestations, a modification.
             (Some((pos1, att1)), Some((pos2, att2))) => {
                 let pos_distance = abs_diff(*pos1, *pos2);
-                let bit_distance = att1.distance(att2).expect("attestations are comparable");
+                let AttestationDelta {
+                    bit_distance,
+                    signature_mismatch,
+                } = att1
+                    .delta(att2, params)
+                    .expect("attestations are comparable");
 
                 deltas.push(Delta::Modify {
                     left: *pos1,
                     right: *pos2,
                     pos_distance,
                     bit_distance,
+                    signature_mismatch,
                 });
             }
             // Insertion on the left.
@@ -182,6 +541,119 @@ fn compute_matching_att_deltas<E: EthSpec>(
     deltas
 }
 
+/// Number of matched attestations that differ only in whether they have a signature.
+pub fn count_signature_mismatches(deltas: &[Delta]) -> usize {
+    deltas
+        .iter()
+        .filter(|delta| {
+            matches!(
+                delta,
+                Delta::Modify {
+                    signature_mismatch: true,
+                    ..
+                }
+            )
+        })
+        .count()
+}
+
+/// The first `max` attestations, or all of them if `max` is 0.
+pub fn leading_attestations<E: EthSpec>(atts: &[Attestation<E>], max: usize) -> &[Attestation<E>] {
+    if max == 0 {
+        atts
+    } else {
+        &atts[..std::cmp::min(max, atts.len())]
+    }
+}
+
+/// Sort attestations into a canonical order: by attestation data, then by aggregation bits.
+pub fn sort_attestations<E: EthSpec>(atts: &mut [Attestation<E>]) {
+    atts.sort_by(|att1, att2| {
+        let key = |att: &Attestation<E>| {
+            (
+                att.data.slot,
+                att.data.index,
+                att.data.beacon_block_root,
+                att.data.source.epoch,
+                att.data.source.root,
+                att.data.target.epoch,
+                att.data.target.root,
+            )
+        };
+        key(att1)
+            .cmp(&key(att2))
+            .then_with(|| {
+                att1.aggregation_bits
+                    .len()
+                    .cmp(&att2.aggregation_bits.len())
+            })
+            .then_with(|| {
+                att1.aggregation_bits
+                    .as_slice()
+                    .cmp(att2.aggregation_bits.as_slice())
+            })
+    });
+}
+
+/// Sort the lists in a block's body into a canonical order, so that blocks that differ only in the
+/// order of their contents become identical.
+///
+/// Attestations are sorted by `sort_attestations`, and slashings, exits and BLS to execution
+/// changes by the validator(s) they apply to (see `Operation`). Deposits must be included in
+/// deposit contract order, so are never reordered.
+pub fn canonicalize<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block: &mut BeaconBlock<E, Payload>,
+) {
+    let mut body = block.body_mut();
+    sort_attestations(body.attestations_mut());
+    sort_operations(body.proposer_slashings_mut());
+    sort_operations(body.attester_slashings_mut());
+    sort_operations(body.voluntary_exits_mut());
+    if let Ok(changes) = body.bls_to_execution_changes_mut() {
+        sort_operations(changes);
+    }
+}
+
+/// `block`, canonicalized with `canonicalize` if `params.canonicalize` is set.
+pub fn canonicalized_block<'a, E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block: &'a BeaconBlock<E, Payload>,
+    params: &DistanceParams,
+) -> Cow<'a, BeaconBlock<E, Payload>> {
+    if !params.canonicalize {
+        return Cow::Borrowed(block);
+    }
+    let mut block = block.clone();
+    canonicalize(&mut block);
+    Cow::Owned(block)
+}
+
+/// The `blocks` of each node, canonicalized with `canonicalize` if `params.canonicalize` is set.
+pub fn canonicalized_blocks<'a, E: EthSpec, Payload: AbstractExecPayload<E>>(
+    blocks: &'a HashMap<String, BeaconBlock<E, Payload>>,
+    params: &DistanceParams,
+) -> Cow<'a, HashMap<String, BeaconBlock<E, Payload>>> {
+    if !params.canonicalize {
+        return Cow::Borrowed(blocks);
+    }
+    Cow::Owned(
+        blocks
+            .par_iter()
+            .map(|(name, block)| {
+                let mut block = block.clone();
+                canonicalize(&mut block);
+                (name.clone(), block)
+            })
+            .collect(),
+    )
+}
+
+fn sort_operations<T: Operation>(operations: &mut [T])
+where
+    T::Key: Ord,
+{
+    operations.sort_by_cached_key(T::key);
+}
+
 fn sort_deltas(deltas: &mut Vec<Delta>) {
     // Sort by (left index, right index, handedness).
     deltas.sort_unstable_by_key(|delta| match delta {
@@ -194,32 +666,56 @@ fn sort_deltas(deltas: &mut Vec<Delta>) {
 impl<E: EthSpec> Distance for &[Attestation<E>] {
     type Delta = Vec<Delta>;
 
-    fn delta(&self, other: &Self) -> Option<Self::Delta> {
-        let left_index_map = index_by_attestation_data(self);
-        let right_index_map = index_by_attestation_data(other);
+    fn delta(&self, other: &Self, params: &DistanceParams) -> Option<Self::Delta> {
+        let left_index_map = index_by_attestation_data(self, params);
+        let right_index_map = index_by_attestation_data(other, params);
         let empty = vec![];
 
-        let mut deltas = Vec::with_capacity(std::cmp::max(self.len(), other.len()));
-
         let att_datas = left_index_map
             .keys()
             .chain(right_index_map.keys())
             .collect::<HashSet<_>>();
 
-        for att_data in att_datas {
+        // Groups of attestations with the same data are matched independently.
+        let group_deltas = |att_data: &AttestationData| {
             let atts1 = left_index_map.get(att_data).unwrap_or(&empty);
             let atts2 = right_index_map.get(att_data).unwrap_or(&empty);
             assert!(!atts1.is_empty() || !atts2.is_empty());
-            deltas.extend(compute_matching_att_deltas(atts1, atts2));
-        }
+            compute_matching_att_deltas(atts1, atts2, params)
+        };
+        let mut deltas = if self.len() + other.len() >= PARALLEL_MATCHING_MIN_ATTESTATIONS {
+            att_datas
+                .into_par_iter()
+                .flat_map_iter(group_deltas)
+                .collect::<Vec<_>>()
+        } else {
+            att_datas.into_iter().flat_map(group_deltas).collect()
+        };
 
+        // The order of groups is arbitrary, so sort for a deterministic result.
         sort_deltas(&mut deltas);
 
         Some(deltas)
     }
 
-    fn delta_to_distance(deltas: &Self::Delta) -> usize {
-        deltas.iter().map(|delta| delta.total_distance()).sum()
+    fn delta_to_distance(deltas: &Self::Delta, params: &DistanceParams) -> usize {
+        deltas
+            .iter()
+            .map(|delta| delta.total_distance(params.indel_cost))
+            .sum()
+    }
+
+    /// The distance if no attestations were matched.
+    ///
+    /// Matching two attestations never costs more than inserting both, as long as `indel_cost`
+    /// is at least the maximum position difference.
+    fn max_distance(&self, other: &Self, params: &DistanceParams) -> Option<usize> {
+        Some(
+            self.iter()
+                .chain(other.iter())
+                .map(|att| att.aggregation_bits.num_set_bits() + params.indel_cost)
+                .sum(),
+        )
     }
 
     fn invert_delta(mut deltas: Self::Delta) -> Self::Delta {
@@ -244,11 +740,13 @@ impl<E: EthSpec> Distance for &[Attestation<E>] {
                     right,
                     pos_distance,
                     bit_distance,
+                    signature_mismatch,
                 } => Delta::Modify {
                     left: right,
                     right: left,
                     pos_distance,
                     bit_distance,
+                    signature_mismatch,
                 },
             };
             *delta = new_delta;
@@ -259,18 +757,431 @@ impl<E: EthSpec> Distance for &[Attestation<E>] {
     }
 }
 
+/// Difference between the execution payload headers of two blocks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExecutionDelta {
+    pub transactions_root_mismatch: bool,
+    pub gas_used_diff: u64,
+    /// Number of differing bytes of `extra_data`, counting bytes beyond the shorter one.
+    pub extra_data_distance: usize,
+    /// Unweighted contribution to the block distance, which is scaled by `execution_weight`.
+    pub distance: u64,
+}
+
+/// Difference between two blocks, made up of the deltas for each part of the block body.
+///
+/// Convert to a distance with `BeaconBlock::delta_to_distance`, which weights each part according
+/// to the `DistanceParams`.
+#[derive(Debug, Clone)]
+pub struct BlockDelta {
+    pub attestations: Vec<Delta>,
+    /// Execution payload delta, or `None` if `execution_weight` is 0 without an `expression`,
+    /// `compare_scope` is "consensus_only" or either block lacks a payload.
+    pub execution: Option<ExecutionDelta>,
+    /// Number of sync committee bits set in one block's sync aggregate but not the other's, or
+    /// `None` prior to Altair.
+    pub sync_aggregate: Option<usize>,
+    /// Number of operations of each kind included in only one of the blocks.
+    pub operations: OperationsDelta,
+}
+
+impl BlockDelta {
+    /// Weighted contribution of each component to the block distance.
+    pub fn breakdown<E: EthSpec>(&self, params: &DistanceParams) -> DistanceBreakdown {
+        let weighted = |weight: f64, distance: usize| (weight * distance as f64).round() as usize;
+        let attestations = <&[Attestation<E>]>::delta_to_distance(&self.attestations, params);
+        let execution = self
+            .execution
+            .as_ref()
+            .map_or(0, |execution| execution.distance as usize);
+        DistanceBreakdown {
+            attestations: weighted(params.attestation_weight, attestations),
+            sync_aggregate: weighted(params.sync_weight, self.sync_aggregate.unwrap_or(0)),
+            execution: weighted(params.execution_weight, execution),
+            operations: self.operations.distance(params.indel_cost),
+        }
+    }
+
+    /// Unweighted components of the block distance, for evaluating `DistanceParams::expression`.
+    pub fn components<E: EthSpec>(&self, params: &DistanceParams) -> DistanceComponents {
+        let operations = &self.operations;
+        DistanceComponents {
+            att: <&[Attestation<E>]>::delta_to_distance(&self.attestations, params),
+            sync: self.sync_aggregate.unwrap_or(0),
+            payload: self
+                .execution
+                .as_ref()
+                .map_or(0, |execution| execution.distance as usize),
+            slashings: operations.proposer_slashings + operations.attester_slashings,
+            deposits: operations.deposits,
+            exits: operations.voluntary_exits,
+            bls_changes: operations.bls_to_execution_changes,
+            ops: operations.distance(params.indel_cost),
+        }
+    }
+}
+
+/// The block distance split into its weighted components, see `BlockDelta::breakdown`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DistanceBreakdown {
+    pub attestations: usize,
+    pub sync_aggregate: usize,
+    pub execution: usize,
+    pub operations: usize,
+}
+
+impl DistanceBreakdown {
+    pub fn total(&self) -> usize {
+        self.attestations + self.sync_aggregate + self.execution + self.operations
+    }
+}
+
+impl fmt::Display for DistanceBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "attestations {}, sync aggregate {}, execution {}, operations {}",
+            self.attestations, self.sync_aggregate, self.execution, self.operations
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationsDelta {
+    pub proposer_slashings: usize,
+    pub attester_slashings: usize,
+    pub deposits: usize,
+    pub voluntary_exits: usize,
+    /// Always 0 prior to Capella.
+    pub bls_to_execution_changes: usize,
+}
+
+impl OperationsDelta {
+    fn new<E: EthSpec, Payload: AbstractExecPayload<E>>(
+        block1: &BeaconBlock<E, Payload>,
+        block2: &BeaconBlock<E, Payload>,
+    ) -> Self {
+        let (body1, body2) = (block1.body(), block2.body());
+        let bls_changes1 = body1
+            .bls_to_execution_changes()
+            .map_or(&[][..], |changes| &changes[..]);
+        let bls_changes2 = body2
+            .bls_to_execution_changes()
+            .map_or(&[][..], |changes| &changes[..]);
+        Self {
+            proposer_slashings: num_unmatched(
+                body1.proposer_slashings(),
+                body2.proposer_slashings(),
+            ),
+            attester_slashings: num_unmatched(
+                body1.attester_slashings(),
+                body2.attester_slashings(),
+            ),
+            deposits: num_unmatched(body1.deposits(), body2.deposits()),
+            voluntary_exits: num_unmatched(body1.voluntary_exits(), body2.voluntary_exits()),
+            bls_to_execution_changes: num_unmatched(bls_changes1, bls_changes2),
+        }
+    }
+
+    fn distance(&self, indel_cost: usize) -> usize {
+        indel_cost
+            * (self.proposer_slashings
+                + self.attester_slashings
+                + self.deposits
+                + self.voluntary_exits
+                + self.bls_to_execution_changes)
+    }
+}
+
+/// An operation included in a block, identified by the validator(s) it applies to.
+pub trait Operation {
+    type Key: Eq + Hash;
+
+    fn key(&self) -> Self::Key;
+}
+
+/// Lists of operations are compared by the number of operations present in only one list, each
+/// costing `indel_cost`, like an attestation insertion.
+impl<T: Operation> Distance for &[T] {
+    type Delta = usize;
+
+    fn delta(&self, other: &Self, _: &DistanceParams) -> Option<usize> {
+        Some(num_unmatched(*self, *other))
+    }
+
+    fn delta_to_distance(delta: &usize, params: &DistanceParams) -> usize {
+        delta * params.indel_cost
+    }
+
+    fn max_distance(&self, other: &Self, params: &DistanceParams) -> Option<usize> {
+        Some((self.len() + other.len()) * params.indel_cost)
+    }
+}
+
+/// Number of operations whose keys are present in only one of `left` and `right`.
+fn num_unmatched<T: Operation>(left: &[T], right: &[T]) -> usize {
+    let left = left.iter().map(T::key).collect::<HashSet<_>>();
+    let right = right.iter().map(T::key).collect::<HashSet<_>>();
+    left.symmetric_difference(&right).count()
+}
+
+impl Operation for ProposerSlashing {
+    type Key = u64;
+
+    fn key(&self) -> u64 {
+        self.signed_header_1.message.proposer_index
+    }
+}
+
+/// Attester slashings are identified by the validators they slash, i.e. the intersection of the
+/// attesting indices of their two attestations.
+///
+/// Two slashings for the same double vote may be built from different aggregates, so comparing
+/// them in full would overstate the difference.
+impl<E: EthSpec> Operation for AttesterSlashing<E> {
+    type Key = BTreeSet<u64>;
+
+    fn key(&self) -> BTreeSet<u64> {
+        let indices_1 = self
+            .attestation_1
+            .attesting_indices
+            .iter()
+            .collect::<HashSet<_>>();
+        self.attestation_2
+            .attesting_indices
+            .iter()
+            .filter(|index| indices_1.contains(index))
+            .copied()
+            .collect()
+    }
+}
+
+/// Deposits don't have a validator index yet, so are identified by public key.
+impl Operation for Deposit {
+    type Key = PublicKeyBytes;
+
+    fn key(&self) -> PublicKeyBytes {
+        self.data.pubkey
+    }
+}
+
+impl Operation for SignedVoluntaryExit {
+    type Key = u64;
+
+    fn key(&self) -> u64 {
+        self.message.validator_index
+    }
+}
+
+impl Operation for SignedBlsToExecutionChange {
+    type Key = u64;
+
+    fn key(&self) -> u64 {
+        self.message.validator_index
+    }
+}
+
+fn sync_aggregate_distance<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block1: &BeaconBlock<E, Payload>,
+    block2: &BeaconBlock<E, Payload>,
+) -> Option<usize> {
+    let bits1 = &block1.body().sync_aggregate().ok()?.sync_committee_bits;
+    let bits2 = &block2.body().sync_aggregate().ok()?.sync_committee_bits;
+    Some(
+        bits1
+            .iter()
+            .zip(bits2.iter())
+            .filter(|(bit1, bit2)| bit1 != bit2)
+            .count(),
+    )
+}
+
+/// Number of sync committee bits set in either block's sync aggregate, or `None` prior to Altair.
+fn sync_aggregate_bits_set<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block1: &BeaconBlock<E, Payload>,
+    block2: &BeaconBlock<E, Payload>,
+) -> Option<usize> {
+    let bits1 = &block1.body().sync_aggregate().ok()?.sync_committee_bits;
+    let bits2 = &block2.body().sync_aggregate().ok()?.sync_committee_bits;
+    Some(
+        bits1
+            .iter()
+            .zip(bits2.iter())
+            .filter(|(bit1, bit2)| *bit1 || *bit2)
+            .count(),
+    )
+}
+
+/// Number of operations of all kinds counted by `OperationsDelta`.
+fn num_operations<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block: &BeaconBlock<E, Payload>,
+) -> usize {
+    let body = block.body();
+    body.proposer_slashings().len()
+        + body.attester_slashings().len()
+        + body.deposits().len()
+        + body.voluntary_exits().len()
+        + body
+            .bls_to_execution_changes()
+            .map_or(0, |changes| changes.len())
+}
+
+fn execution_delta<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block1: &BeaconBlock<E, Payload>,
+    block2: &BeaconBlock<E, Payload>,
+    params: &DistanceParams,
+) -> Option<ExecutionDelta> {
+    if params.compare_scope == CompareScope::ConsensusOnly
+        || (params.execution_weight <= 0.0 && params.expression.is_none())
+    {
+        return None;
+    }
+    let header1 = block1
+        .body()
+        .execution_payload()
+        .ok()?
+        .to_execution_payload_header();
+    let header2 = block2
+        .body()
+        .execution_payload()
+        .ok()?
+        .to_execution_payload_header();
+
+    let transactions_root_mismatch = header1.transactions_root() != header2.transactions_root();
+    let gas_used_diff = header1.gas_used().abs_diff(header2.gas_used());
+    let (extra_data1, extra_data2) = (header1.extra_data(), header2.extra_data());
+    let extra_data_distance = extra_data1
+        .iter()
+        .zip(extra_data2.iter())
+        .filter(|(byte1, byte2)| byte1 != byte2)
+        .count()
+        + extra_data1.len().abs_diff(extra_data2.len());
+
+    let distance = u64::from(transactions_root_mismatch)
+        + gas_used_diff / GAS_UNIT
+        + extra_data_distance as u64;
+
+    Some(ExecutionDelta {
+        transactions_root_mismatch,
+        gas_used_diff,
+        extra_data_distance,
+        distance,
+    })
+}
+
+/// The attestations of `block` to compare, after applying `max_attestations`,
+/// `exclude_recent_attestations` and `sort_attestations` in that order.
+fn attestations_to_compare<'a, E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block: &'a BeaconBlock<E, Payload>,
+    params: &DistanceParams,
+) -> Cow<'a, [Attestation<E>]> {
+    let mut atts = Cow::Borrowed(leading_attestations(
+        block.body().attestations(),
+        params.max_attestations,
+    ));
+    if params.exclude_recent_attestations > 0 {
+        let min_age = params.exclude_recent_attestations;
+        atts = atts
+            .iter()
+            .filter(|att| att.data.slot + min_age <= block.slot())
+            .cloned()
+            .collect();
+    }
+    if params.sort_attestations {
+        sort_attestations(atts.to_mut());
+    }
+    atts
+}
+
+fn block_delta<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block1: &BeaconBlock<E, Payload>,
+    block2: &BeaconBlock<E, Payload>,
+    params: &DistanceParams,
+) -> Option<BlockDelta> {
+    let atts1 = attestations_to_compare(block1, params);
+    let atts2 = attestations_to_compare(block2, params);
+    let attestations = (&*atts1).delta(&&*atts2, params)?;
+    Some(BlockDelta {
+        attestations,
+        execution: execution_delta(block1, block2, params),
+        sync_aggregate: sync_aggregate_distance(block1, block2),
+        operations: OperationsDelta::new(block1, block2),
+    })
+}
+
+/// Forks of `block1` and `block2` according to their structure, if they differ.
+///
+/// Such blocks can still be compared (see below), but the distance between them mostly reflects
+/// the difference in structure.
+pub fn fork_mismatch<E: EthSpec, Payload: AbstractExecPayload<E>>(
+    block1: &BeaconBlock<E, Payload>,
+    block2: &BeaconBlock<E, Payload>,
+    spec: &ChainSpec,
+) -> Option<(ForkName, ForkName)> {
+    let fork_name = |block: &BeaconBlock<E, Payload>| {
+        block
+            .fork_name(spec)
+            .unwrap_or_else(|inconsistent| inconsistent.object_fork)
+    };
+    let (fork1, fork2) = (fork_name(block1), fork_name(block2));
+    (fork1 != fork2).then_some((fork1, fork2))
+}
+
+/// Block-level distance, for full and blinded blocks alike (e.g. `BlindedBeaconBlock<E>`).
+///
+/// Blocks from different forks can be compared: parts of the body that only exist in later forks
+/// (sync aggregate, execution payload, BLS to execution changes) are treated as absent or empty.
+///
+/// With `params.canonicalize`, both blocks must already have been canonicalized, e.g. with
+/// `canonicalized_block`. This happens once per block rather than once per pair compared.
 impl<E: EthSpec, Payload: AbstractExecPayload<E>> Distance for BeaconBlock<E, Payload> {
-    type Delta = Vec<Delta>;
+    type Delta = BlockDelta;
+
+    fn delta(&self, other: &Self, params: &DistanceParams) -> Option<Self::Delta> {
+        block_delta(self, other, params)
+    }
 
-    fn delta(&self, other: &Self) -> Option<Self::Delta> {
-        (&self.body().attestations()[..]).delta(&&other.body().attestations()[..])
+    fn delta_to_distance(delta: &Self::Delta, params: &DistanceParams) -> usize {
+        match &params.expression {
+            Some(expression) => expression.evaluate(&delta.components::<E>(params)),
+            None => delta.breakdown::<E>(params).total(),
+        }
     }
 
-    fn delta_to_distance(deltas: &Self::Delta) -> usize {
-        <&[Attestation<E>]>::delta_to_distance(deltas)
+    /// Bound on the distance if the blocks had nothing in common.
+    ///
+    /// The execution payload distance has no natural bound, so isn't included. There's no bound at
+    /// all with an `expression`, as its result needn't grow with its components.
+    fn max_distance(&self, other: &Self, params: &DistanceParams) -> Option<usize> {
+        if params.expression.is_some() {
+            return None;
+        }
+        let atts1 = attestations_to_compare(self, params);
+        let atts2 = attestations_to_compare(other, params);
+        let attestations = (&*atts1).max_distance(&&*atts2, params)? as f64;
+        let sync_aggregate = sync_aggregate_bits_set(self, other).unwrap_or(0) as f64;
+        let weighted =
+            params.attestation_weight * attestations + params.sync_weight * sync_aggregate;
+        Some(
+            weighted.round() as usize
+                + (num_operations(self) + num_operations(other)) * params.indel_cost,
+        )
     }
 
     fn invert_delta(delta: Self::Delta) -> Self::Delta {
-        <&[Attestation<E>]>::invert_delta(delta)
+        BlockDelta {
+            attestations: <&[Attestation<E>]>::invert_delta(delta.attestations),
+            execution: delta.execution,
+            sync_aggregate: delta.sync_aggregate,
+            operations: delta.operations,
+        }
     }
 }
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+fn default_indel_cost() -> usize {
+    INDEL_COST
+}
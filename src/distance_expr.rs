@@ -0,0 +1,337 @@
+//! Restricted arithmetic expressions for computing the block distance from its components.
+//!
+//! An expression combines numbers and the variables listed in `Variable` with `+`, `-`, `*`,
+//! `/`, unary minus, parentheses and the functions `min(a, b)` and `max(a, b)`, e.g.
+//! `att + 4 * sync + max(ops, 128 * slashings)`.
+use serde::Deserialize;
+use std::fmt;
+
+/// Maximum nesting of parentheses, function calls and unary minus, which bounds the parser's
+/// recursion.
+const MAX_DEPTH: usize = 32;
+
+/// Unweighted components of the distance between two blocks, see `BlockDelta::components`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DistanceComponents {
+    pub att: usize,
+    pub sync: usize,
+    pub payload: usize,
+    pub slashings: usize,
+    pub deposits: usize,
+    pub exits: usize,
+    pub bls_changes: usize,
+    pub ops: usize,
+}
+
+/// Variable that an expression may refer to, one per field of `DistanceComponents`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variable {
+    /// Attestation distance, including `indel_cost` for attestations in only one block.
+    Att,
+    /// Number of differing sync committee bits.
+    Sync,
+    /// Execution payload distance, 0 with `compare_scope = "consensus_only"`.
+    Payload,
+    /// Number of proposer and attester slashings included in only one block.
+    Slashings,
+    /// Number of deposits included in only one block.
+    Deposits,
+    /// Number of voluntary exits included in only one block.
+    Exits,
+    /// Number of BLS to execution changes included in only one block.
+    BlsChanges,
+    /// Distance of all operations, i.e. `indel_cost` for each one included in only one block.
+    Ops,
+}
+
+impl Variable {
+    pub const ALL: [Variable; 8] = [
+        Variable::Att,
+        Variable::Sync,
+        Variable::Payload,
+        Variable::Slashings,
+        Variable::Deposits,
+        Variable::Exits,
+        Variable::BlsChanges,
+        Variable::Ops,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Variable::Att => "att",
+            Variable::Sync => "sync",
+            Variable::Payload => "payload",
+            Variable::Slashings => "slashings",
+            Variable::Deposits => "deposits",
+            Variable::Exits => "exits",
+            Variable::BlsChanges => "bls_changes",
+            Variable::Ops => "ops",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|variable| variable.name() == name)
+    }
+
+    fn value(self, components: &DistanceComponents) -> f64 {
+        let value = match self {
+            Variable::Att => components.att,
+            Variable::Sync => components.sync,
+            Variable::Payload => components.payload,
+            Variable::Slashings => components.slashings,
+            Variable::Deposits => components.deposits,
+            Variable::Exits => components.exits,
+            Variable::BlsChanges => components.bls_changes,
+            Variable::Ops => components.ops,
+        };
+        value as f64
+    }
+}
+
+/// A parsed distance expression, deserialized from its source string.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct DistanceExpression {
+    source: String,
+    expr: Expr,
+}
+
+impl DistanceExpression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!(
+                "unexpected {token} in distance expression {source:?}"
+            ));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    /// Evaluate the expression, rounding to the nearest distance.
+    ///
+    /// Division by zero yields 0, and negative results count as 0.
+    pub fn evaluate(&self, components: &DistanceComponents) -> usize {
+        self.expr.evaluate(components).max(0.0).round() as usize
+    }
+}
+
+impl TryFrom<String> for DistanceExpression {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl fmt::Display for DistanceExpression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Variable(Variable),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    Min(Box<Expr>, Box<Expr>),
+    Max(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, components: &DistanceComponents) -> f64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Variable(variable) => variable.value(components),
+            Expr::Neg(expr) => -expr.evaluate(components),
+            Expr::Binary(op, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(components), rhs.evaluate(components));
+                match op {
+                    '+' => lhs + rhs,
+                    '-' => lhs - rhs,
+                    '*' => lhs * rhs,
+                    _ if rhs == 0.0 => 0.0,
+                    _ => lhs / rhs,
+                }
+            }
+            Expr::Min(a, b) => a.evaluate(components).min(b.evaluate(components)),
+            Expr::Max(a, b) => a.evaluate(components).max(b.evaluate(components)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(value) => write!(f, "number {value}"),
+            Token::Ident(name) => write!(f, "`{name}`"),
+            Token::Symbol(symbol) => write!(f, "`{symbol}`"),
+        }
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars
+                .peek()
+                .filter(|(_, c)| c.is_ascii_digit() || *c == '.')
+            {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            let number = &source[start..end];
+            let value = number
+                .parse()
+                .map_err(|_| format!("invalid number {number:?} in distance expression"))?;
+            tokens.push(Token::Number(value));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(i, c)) = chars
+                .peek()
+                .filter(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+            {
+                end = i + c.len_utf8();
+                chars.next();
+            }
+            tokens.push(Token::Ident(source[start..end].to_string()));
+        } else if "+-*/(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            return Err(format!(
+                "unexpected character {c:?} at position {start} in distance expression"
+            ));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser, with the usual precedence of `*` and `/` over `+` and `-`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Current nesting depth, at most `MAX_DEPTH`.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, String> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("unexpected end of distance expression")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Symbol(c) if c == symbol => Ok(()),
+            token => Err(format!("expected `{symbol}`, found {token}")),
+        }
+    }
+
+    /// expr := term (('+' | '-') term)*
+    fn expr(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.term()?;
+        while let Some(op) = ['+', '-'].into_iter().find(|op| self.eat(*op)) {
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.term()?));
+        }
+        Ok(lhs)
+    }
+
+    /// term := factor (('*' | '/') factor)*
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut lhs = self.factor()?;
+        while let Some(op) = ['*', '/'].into_iter().find(|op| self.eat(*op)) {
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(self.factor()?));
+        }
+        Ok(lhs)
+    }
+
+    /// Parse a factor, failing if factors are nested more than `MAX_DEPTH` levels deep.
+    fn factor(&mut self) -> Result<Expr, String> {
+        if self.depth == MAX_DEPTH {
+            return Err(format!(
+                "distance expression is nested more than {MAX_DEPTH} levels deep"
+            ));
+        }
+        self.depth += 1;
+        let factor = self.unchecked_factor();
+        self.depth -= 1;
+        factor
+    }
+
+    /// factor := number | variable | '-' factor | '(' expr ')' | ('min' | 'max') '(' expr ',' expr ')'
+    fn unchecked_factor(&mut self) -> Result<Expr, String> {
+        match self.next()? {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Symbol('-') => Ok(Expr::Neg(Box::new(self.factor()?))),
+            Token::Symbol('(') => {
+                let expr = self.expr()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Token::Ident(name) if name == "min" || name == "max" => {
+                self.expect('(')?;
+                let a = Box::new(self.expr()?);
+                self.expect(',')?;
+                let b = Box::new(self.expr()?);
+                self.expect(')')?;
+                Ok(if name == "min" {
+                    Expr::Min(a, b)
+                } else {
+                    Expr::Max(a, b)
+                })
+            }
+            Token::Ident(name) => Variable::from_name(&name)
+                .map(Expr::Variable)
+                .ok_or_else(|| {
+                    let names = Variable::ALL.map(Variable::name).join(", ");
+                    format!(
+                        "unknown variable `{name}` in distance expression, expected one of {names}"
+                    )
+                }),
+            token => Err(format!("unexpected {token} in distance expression")),
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use eth2::types::{BlindedBeaconBlock, EthSpec, Hash256, Uint256};
+use serde::{Deserialize, Serialize};
+
+/// Compact summary of a dreamt block, for collectors that don't need the full block.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub num_attestations: usize,
+    /// Total number of aggregation bits set across all attestations.
+    pub total_set_bits: usize,
+    pub parent_root: Hash256,
+    pub block_root: Hash256,
+    /// Number of sync committee bits set, or `None` prior to Altair.
+    pub sync_aggregate_participation: Option<usize>,
+    /// Consensus block value reported by the node (decimal wei), if it uses the v3 API.
+    pub reported_reward: Option<String>,
+}
+
+impl Fingerprint {
+    pub fn new<E: EthSpec>(
+        block: &BlindedBeaconBlock<E>,
+        reported_reward: Option<Uint256>,
+    ) -> Self {
+        let attestations = block.body().attestations();
+        Self {
+            num_attestations: attestations.len(),
+            total_set_bits: attestations
+                .iter()
+                .map(|att| att.aggregation_bits.num_set_bits())
+                .sum(),
+            parent_root: block.parent_root(),
+            block_root: block.canonical_root(),
+            sync_aggregate_participation: block
+                .body()
+                .sync_aggregate()
+                .ok()
+                .map(|sync_aggregate| sync_aggregate.sync_committee_bits.num_set_bits()),
+            reported_reward: reported_reward.map(|reward| reward.to_string()),
+        }
+    }
+}
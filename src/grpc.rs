@@ -0,0 +1,67 @@
+//! Minimal gRPC client for posting dreamt blocks as protobuf.
+//!
+//! The message types mirror `proto/blockdreamer.proto` and are written by hand so that no
+//! `protoc` is required at build time.
+use tonic::{
+    client::Grpc,
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    transport::{Channel, Endpoint},
+    Request,
+};
+
+const POST_BLOCKS_PATH: &str = "/blockdreamer.BlockIngest/PostBlocks";
+
+/// Protobuf equivalent of `PostPayload`, with blocks encoded as SSZ.
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct DreamBlocks {
+    #[prost(string, tag = "6")]
+    pub run_id: String,
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    #[prost(string, repeated, tag = "2")]
+    pub names: Vec<String>,
+    #[prost(string, repeated, tag = "3")]
+    pub labels: Vec<String>,
+    /// Client version reported by each node at startup, empty if unknown.
+    #[prost(string, repeated, tag = "7")]
+    pub versions: Vec<String>,
+    #[prost(bytes = "vec", repeated, tag = "4")]
+    pub blocks_ssz: Vec<Vec<u8>>,
+    /// Consensus block value reported by each node in decimal wei, empty if not reported.
+    #[prost(string, repeated, tag = "5")]
+    pub reported_rewards: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, prost::Message)]
+pub struct PostBlocksResponse {}
+
+/// Channel to the gRPC server at `url`, connecting on first use and reconnecting as needed.
+///
+/// Must be called from within a tokio runtime.
+pub fn channel(url: &str) -> Result<Channel, String> {
+    Ok(Endpoint::from_shared(url.to_string())
+        .map_err(|e| format!("invalid gRPC URL: {e}"))?
+        .connect_lazy())
+}
+
+/// Send `message` to the `BlockIngest.PostBlocks` method of the gRPC server on `channel`.
+pub async fn post_blocks(channel: Channel, message: DreamBlocks) -> Result<(), String> {
+    let mut client = Grpc::new(channel);
+    client
+        .ready()
+        .await
+        .map_err(|e| format!("gRPC service not ready: {e}"))?;
+
+    let codec = ProstCodec::<DreamBlocks, PostBlocksResponse>::default();
+    client
+        .unary(
+            Request::new(message),
+            PathAndQuery::from_static(POST_BLOCKS_PATH),
+            codec,
+        )
+        .await
+        .map_err(|status| format!("gRPC error: {status}"))?;
+
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Tracks progress of the main loop, for readiness checks.
+///
+/// Times are stored as seconds since the UNIX epoch, with 0 meaning "never".
+#[derive(Debug, Default)]
+pub struct Heartbeat {
+    /// Maximum age of the last slot and last block before we are considered wedged.
+    ///
+    /// Set once the genesis time is resolved and the slot clock is running.
+    max_age_secs: AtomicU64,
+    last_slot_at: AtomicU64,
+    last_block_at: AtomicU64,
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+impl Heartbeat {
+    /// Mark the genesis time as resolved, allowing progress to be `max_age` old.
+    pub fn genesis_resolved(&self, max_age: Duration) {
+        self.max_age_secs
+            .store(std::cmp::max(max_age.as_secs(), 1), Ordering::Relaxed);
+    }
+
+    /// Record that the main loop woke up for a new slot.
+    pub fn record_slot(&self) {
+        self.last_slot_at.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Record that a block was received from one of the nodes.
+    pub fn record_block(&self) {
+        self.last_block_at.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// Check whether we are ready, returning the reason if not.
+    pub fn readiness(&self) -> Result<(), String> {
+        self.readiness_at(unix_now())
+    }
+
+    /// Check readiness at time `now` (seconds since the UNIX epoch).
+    pub fn readiness_at(&self, now: u64) -> Result<(), String> {
+        let max_age = self.max_age_secs.load(Ordering::Relaxed);
+        if max_age == 0 {
+            return Err("genesis not resolved".into());
+        }
+
+        let last_slot_at = self.last_slot_at.load(Ordering::Relaxed);
+        if last_slot_at == 0 {
+            return Err("slot loop not started".into());
+        }
+        let slot_age = now.saturating_sub(last_slot_at);
+        if slot_age > max_age {
+            return Err(format!("slot loop stalled, last slot {slot_age}s ago"));
+        }
+
+        let last_block_at = self.last_block_at.load(Ordering::Relaxed);
+        if last_block_at == 0 || now.saturating_sub(last_block_at) > max_age {
+            return Err(format!("no blocks from any node in the last {max_age}s"));
+        }
+
+        Ok(())
+    }
+}
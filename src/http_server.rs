@@ -0,0 +1,75 @@
+//! HTTP server for health checks, metrics and inspection of the latest distances.
+use crate::heartbeat::Heartbeat;
+use crate::matrix::LatestMatrix;
+use crate::metrics::Metrics;
+use crate::percentiles::DistancePercentiles;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use warp::{http::StatusCode, Filter, Reply};
+
+/// Start serving on `address` in the background, returning the address bound to.
+///
+/// - `GET /healthz` returns 200 while the process is alive.
+/// - `GET /readyz` returns 200 if the `heartbeat` is ready, and 503 with the reason otherwise.
+/// - `GET /matrix` returns the latest distance matrix as JSON, or 503 if there isn't one yet.
+/// - `GET /metrics` returns Prometheus metrics in the text format.
+/// - `GET /percentiles` returns each node's distance percentiles as JSON, or 404 if disabled.
+pub fn start(
+    address: SocketAddr,
+    heartbeat: Arc<Heartbeat>,
+    latest_matrix: Arc<LatestMatrix>,
+    metrics: Arc<Metrics>,
+    distance_percentiles: Option<Arc<DistancePercentiles>>,
+) -> Result<SocketAddr, String> {
+    let healthz = warp::path("healthz")
+        .and(warp::path::end())
+        .map(|| warp::reply::with_status("OK".to_string(), StatusCode::OK));
+
+    let readyz =
+        warp::path("readyz")
+            .and(warp::path::end())
+            .map(move || match heartbeat.readiness() {
+                Ok(()) => warp::reply::with_status("OK".to_string(), StatusCode::OK),
+                Err(reason) => warp::reply::with_status(reason, StatusCode::SERVICE_UNAVAILABLE),
+            });
+
+    let matrix =
+        warp::path("matrix")
+            .and(warp::path::end())
+            .map(move || match latest_matrix.get() {
+                Some(matrix) => warp::reply::json(&matrix).into_response(),
+                None => warp::reply::with_status(
+                    "no slot processed yet".to_string(),
+                    StatusCode::SERVICE_UNAVAILABLE,
+                )
+                .into_response(),
+            });
+
+    let metrics =
+        warp::path("metrics")
+            .and(warp::path::end())
+            .map(move || match metrics.encode() {
+                Ok(body) => warp::reply::with_status(body, StatusCode::OK),
+                Err(e) => warp::reply::with_status(e, StatusCode::INTERNAL_SERVER_ERROR),
+            });
+
+    let percentiles = warp::path("percentiles")
+        .and(warp::path::end())
+        .map(move || match &distance_percentiles {
+            Some(percentiles) => warp::reply::json(&percentiles.summary()).into_response(),
+            None => warp::reply::with_status(
+                "distance_percentiles is not configured".to_string(),
+                StatusCode::NOT_FOUND,
+            )
+            .into_response(),
+        });
+
+    let routes = warp::get().and(healthz.or(readyz).or(matrix).or(metrics).or(percentiles));
+
+    let (bound_address, server) = warp::serve(routes)
+        .try_bind_ephemeral(address)
+        .map_err(|e| format!("unable to start HTTP server on {address}: {e}"))?;
+    tokio::spawn(server);
+
+    Ok(bound_address)
+}
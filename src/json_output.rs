@@ -0,0 +1,117 @@
+//! Output of the distances for each slot as lines of JSON, for analysis.
+use eth2::types::Slot;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use tracing::error;
+
+/// Path that selects stdout rather than a file.
+const STDOUT_PATH: &str = "-";
+
+/// Distances for one slot, written as a single line.
+#[derive(Debug, Default, Serialize)]
+pub struct SlotDistances {
+    pub slot: Slot,
+    /// Distances between pairs of nodes.
+    pub distances: Vec<PairDistance>,
+    /// Distance from each node to the canonical block, empty if it wasn't compared.
+    pub canonical: Vec<NodeDistance>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PairDistance {
+    pub a: String,
+    pub b: String,
+    pub distance: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NodeDistance {
+    pub node: String,
+    pub distance: usize,
+}
+
+/// Collects the distances for each slot and writes them once the slot's canonical block has been
+/// compared, which happens after the pairwise comparison of the next sampled slot.
+pub struct JsonOutput {
+    writer: Box<dyn Write + Send>,
+    pending: BTreeMap<Slot, SlotDistances>,
+}
+
+impl JsonOutput {
+    /// Append to the file at `path`, or write to stdout if `path` is "-".
+    pub fn new(path: &str) -> Result<Self, String> {
+        let writer: Box<dyn Write + Send> = if path == STDOUT_PATH {
+            Box::new(io::stdout())
+        } else {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| format!("unable to open {path}: {e}"))?;
+            Box::new(BufWriter::new(file))
+        };
+        Ok(Self::with_writer(writer))
+    }
+
+    pub fn with_writer(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_pairs(&mut self, slot: Slot, pairs: &[(&String, &String, usize)]) {
+        self.entry(slot).distances = pairs
+            .iter()
+            .map(|(a, b, distance)| PairDistance {
+                a: a.to_string(),
+                b: b.to_string(),
+                distance: *distance,
+            })
+            .collect();
+    }
+
+    pub fn set_canonical(&mut self, slot: Slot, distances: &[(&str, usize)]) {
+        self.entry(slot).canonical = distances
+            .iter()
+            .map(|(node, distance)| NodeDistance {
+                node: node.to_string(),
+                distance: *distance,
+            })
+            .collect();
+    }
+
+    fn entry(&mut self, slot: Slot) -> &mut SlotDistances {
+        self.pending.entry(slot).or_insert_with(|| SlotDistances {
+            slot,
+            ..SlotDistances::default()
+        })
+    }
+
+    /// Write all slots up to and including `slot`, which won't receive any more distances.
+    pub fn write_through(&mut self, slot: Slot) {
+        let later = self.pending.split_off(&(slot + 1));
+        let done = std::mem::replace(&mut self.pending, later);
+        if let Err(e) = self.write(done.into_values()) {
+            error!("unable to write JSON output: {e}");
+        }
+    }
+
+    /// Write all pending slots, e.g. at shutdown.
+    pub fn write_all(&mut self) {
+        let done = std::mem::take(&mut self.pending);
+        if let Err(e) = self.write(done.into_values()) {
+            error!("unable to write JSON output: {e}");
+        }
+    }
+
+    fn write(&mut self, slots: impl Iterator<Item = SlotDistances>) -> io::Result<()> {
+        for slot_distances in slots {
+            serde_json::to_writer(&mut self.writer, &slot_distances)?;
+            self.writer.write_all(b"\n")?;
+        }
+        self.writer.flush()
+    }
+}
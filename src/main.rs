@@ -1,49 +1,158 @@
-use crate::cli::CliConfig;
-use crate::distance::Distance;
+use crate::alert::Alerter;
+use crate::block_json::BlockJsonDir;
+use crate::chain_info::ChainInfo;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::classify::classify;
+use crate::cli::{CliConfig, LogFormat};
+use crate::clock::{SystemTicker, Ticker};
+use crate::committees::{validator_differences, Committees};
+use crate::debug_dump::DebugDumps;
+use crate::discovery::NodeDiscovery;
+use crate::distance::{
+    canonicalized_block, canonicalized_blocks, count_signature_mismatches,
+    find_duplicate_attestations, fork_mismatch, majority_attestations, normalize_distance,
+    Distance, DistanceParams, EpochDistribution,
+};
+use crate::heartbeat::Heartbeat;
+use crate::json_output::JsonOutput;
+use crate::maintenance::active_window;
+use crate::matrix::{DistanceMatrix, LatestMatrix, VerdictSummary};
+use crate::metrics::{node_labels, Metrics};
+use crate::parquet_sink::ParquetSink;
+use crate::percentiles::DistancePercentiles;
 use crate::post::PostEndpoint;
+use crate::stale_block::log_repeated_roots;
+use crate::summary_fifo::{SlotSummary, SummaryFifo};
+use crate::telemetry::SlotTrace;
+use crate::verdict_socket::VerdictSocket;
+use chrono::Utc;
 use clap::Parser;
-use config::{Config, PostEndpointConfig};
+use config::{
+    CanonicalComparison, Config, DistinctBlocks, Node as NodeConfig, PostEndpointConfig,
+    RandaoSource,
+};
 use eth2::{
-    types::{BlindedBeaconBlock, BlockId, Slot, Uint256},
+    types::{
+        Address, BlindedBeaconBlock, BlockId, ChainSpec, EthSpec, ExecPayload, Hash256,
+        ProduceBlockV3Metadata, Slot, Uint256,
+    },
     BeaconNodeHttpClient, Timeouts,
 };
 use eth2_network_config::Eth2NetworkConfig;
 use futures::future::join_all;
+use futures::FutureExt;
 use itertools::Itertools;
 use logging::test_logger;
-use node::Node;
+use node::{canonical_randao_reveal, Node, PREFER_BUILDER_BOOST_FACTOR};
+use rayon::prelude::*;
 use sensitive_url::SensitiveUrl;
-use slot_clock::{SlotClock, SystemTimeSlotClock};
-use std::collections::HashMap;
+use slot_clock::SlotClock;
+use std::any::Any;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{self, IsTerminal};
+use std::panic::AssertUnwindSafe;
 use std::process::ExitCode;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::Semaphore;
+use tracing::{error, info, warn};
+use uuid::Uuid;
 
+mod alert;
+mod backoff;
+mod block_json;
+mod chain_info;
+mod circuit_breaker;
+mod classify;
 mod cli;
+mod clock;
+mod committees;
 mod config;
+mod debug_dump;
+mod discovery;
 mod distance;
+mod distance_expr;
+mod fingerprint;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod heartbeat;
+mod http_server;
+mod json_output;
+mod maintenance;
+mod matrix;
+mod metrics;
 mod node;
+mod parquet_sink;
+mod percentiles;
 mod post;
+mod stale_block;
+mod summary_fifo;
+mod telemetry;
 mod tests;
+mod verdict_socket;
 
 #[cfg(all(feature = "mainnet", not(feature = "gnosis")))]
 type E = eth2::types::MainnetEthSpec;
 #[cfg(feature = "gnosis")]
 type E = eth2::types::GnosisEthSpec;
 
-// FIXME: add to config
-const VERBOSE: bool = false;
+fn main() -> ExitCode {
+    let cli_config = CliConfig::parse();
+    init_logging(&cli_config);
+    match build_runtime(&cli_config) {
+        Ok(runtime) => runtime.block_on(async_main(cli_config)),
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
 
-const SIGNIFICANCE_NUMERATOR: usize = 2;
-const SIGNIFICANCE_DENOM: usize = 1;
-const NUM_SLOTS_IN_MEMORY: u64 = 8;
+/// Build the tokio runtime, and the rayon pool used for distance computations, with the thread
+/// counts from `cli_config`.
+///
+/// Distance computations use rayon's global pool, and are called from async tasks that block
+/// their tokio worker thread until the pool has finished. Keeping the pools separate means that
+/// requests to nodes are still made while distances are being computed, as long as there are
+/// worker threads to spare.
+fn build_runtime(cli_config: &CliConfig) -> Result<tokio::runtime::Runtime, String> {
+    let mut distance_pool =
+        rayon::ThreadPoolBuilder::new().thread_name(|i| format!("distance-{i}"));
+    if let Some(threads) = cli_config.distance_threads {
+        distance_pool = distance_pool.num_threads(threads.get());
+    }
+    distance_pool
+        .build_global()
+        .map_err(|e| format!("Unable to build distance thread pool: {e}"))?;
 
-#[tokio::main(flavor = "multi_thread")]
-async fn main() -> ExitCode {
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(threads) = cli_config.worker_threads {
+        runtime.worker_threads(threads.get());
+    }
+    runtime
+        .build()
+        .map_err(|e| format!("Unable to build tokio runtime: {e}"))
+}
+
+/// Print log messages at `--log-level` and above to stderr, in the `--log-format`.
+fn init_logging(cli_config: &CliConfig) {
+    let subscriber = tracing_subscriber::fmt()
+        .with_max_level(cli_config.log_level)
+        .with_writer(io::stderr)
+        .with_ansi(io::stderr().is_terminal());
+    match cli_config.log_format {
+        LogFormat::Text => subscriber.with_target(false).init(),
+        LogFormat::Json => subscriber.json().flatten_event(true).init(),
+    }
+}
+
+async fn async_main(cli_config: CliConfig) -> ExitCode {
     let shutdown_signal = Arc::new(AtomicBool::new(false));
 
     let mut sigint = signal(SignalKind::interrupt()).unwrap();
@@ -52,26 +161,50 @@ async fn main() -> ExitCode {
     // Spawn task in the background.
     let shutdown_signal_inner = shutdown_signal.clone();
     let run_handle = tokio::spawn(async move {
-        run(shutdown_signal_inner).await.unwrap();
+        let result = async {
+            let config = load_config(&cli_config)?;
+            if let Some(endpoint) = &config.otlp_endpoint {
+                telemetry::init(endpoint)?;
+            }
+            let monitoring = Monitoring::start(&config)?;
+            let (spec, genesis_time) = load_genesis(&config, &cli_config).await?;
+            run(
+                shutdown_signal_inner,
+                SystemTicker,
+                config,
+                spec,
+                genesis_time,
+                monitoring,
+                cli_config.require_all_nodes,
+            )
+            .await
+        }
+        .await;
+        telemetry::shutdown();
+        result
     });
 
     // Wait for signals to shutdown.
     tokio::select! {
         _ = sigint.recv()=> {
-            eprintln!("shutting down on SIGINT");
+            info!("shutting down on SIGINT");
             shutdown_signal.store(true, Ordering::Relaxed);
         },
         _ = sigterm.recv()  => {
-            eprintln!("shutting down on SIGTERM");
+            info!("shutting down on SIGTERM");
             shutdown_signal.store(true, Ordering::Relaxed);
         }
         res = run_handle => {
             match res {
-                Ok(_) => {
+                Ok(Ok(())) => {
                     return ExitCode::SUCCESS;
                 }
+                Ok(Err(e)) => {
+                    error!("{e}");
+                    return ExitCode::FAILURE;
+                }
                 Err(e) => {
-                    eprintln!("{e}");
+                    error!("{e}");
                     return ExitCode::FAILURE;
                 }
             }
@@ -80,35 +213,80 @@ async fn main() -> ExitCode {
     ExitCode::SUCCESS
 }
 
-async fn run(shutdown_signal: Arc<AtomicBool>) -> Result<(), String> {
-    // Load config.
-    let cli_config = CliConfig::parse();
-    let config = Config::from_file(&cli_config.config).unwrap();
-    eprintln!("{:#?}", config);
-    eprintln!("Blockdreamer is ready");
+/// Load the config file, applying the command line overrides and the labels file.
+fn load_config(cli_config: &CliConfig) -> Result<Config, String> {
+    let mut config = Config::from_file(&cli_config.config).map_err(|e| {
+        format!(
+            "Unable to load config {}: {}",
+            cli_config.config.display(),
+            e
+        )
+    })?;
+    if let Some(canonical_bn) = &cli_config.canonical_bn {
+        config.canonical_bn = canonical_bn.clone();
+    }
+    config.apply_labels_file()?;
+    config.validate()?;
+    info!("{:#?}", config);
+    info!("Blockdreamer is ready");
 
     // Deprecation warnings.
     for node_config in &config.nodes {
         if node_config.use_builder {
-            eprintln!(
-                "Node config `use_builder` is deprecated and has no effect ({})",
-                node_config.name
+            warn!(
+                node = node_config.name,
+                "Node config `use_builder` is deprecated and has no effect"
             );
         }
     }
 
-    // This logger is unused currently.
-    let dummy_logger = test_logger();
+    Ok(config)
+}
 
-    // Mapping from node name to label.
-    let labels = config
-        .nodes
-        .iter()
-        .filter(|node| node.enabled)
-        .map(|node| (node.name.clone(), node.label.clone()))
-        .collect::<HashMap<_, _>>();
+/// State shared between the main loop and the HTTP server.
+struct Monitoring {
+    heartbeat: Arc<Heartbeat>,
+    latest_matrix: Arc<LatestMatrix>,
+    metrics: Arc<Metrics>,
+    distance_percentiles: Option<Arc<DistancePercentiles>>,
+}
+
+impl Monitoring {
+    /// Create the shared state, and serve it over HTTP if `http_address` is set.
+    ///
+    /// Health checks report not ready until the slot loop is running.
+    fn start(config: &Config) -> Result<Self, String> {
+        let monitoring = Self {
+            heartbeat: Arc::new(Heartbeat::default()),
+            latest_matrix: Arc::new(LatestMatrix::default()),
+            metrics: Arc::new(Metrics::new()?),
+            distance_percentiles: config
+                .distance_percentiles
+                .map(|percentiles| Arc::new(DistancePercentiles::new(percentiles.window_slots))),
+        };
+        if let Some(address) = config.http_address {
+            let bound_address = http_server::start(
+                address,
+                monitoring.heartbeat.clone(),
+                monitoring.latest_matrix.clone(),
+                monitoring.metrics.clone(),
+                monitoring.distance_percentiles.clone(),
+            )?;
+            info!("HTTP server listening on {bound_address}");
+        }
+        Ok(monitoring)
+    }
+}
+
+/// Load the chain spec and genesis time of the configured network, retrying the genesis state
+/// download as configured in `cli_config`.
+async fn load_genesis(
+    config: &Config,
+    cli_config: &CliConfig,
+) -> Result<(Arc<ChainSpec>, u64), String> {
+    // Lighthouse's genesis state download requires a slog logger, but its messages are discarded.
+    let dummy_logger = test_logger();
 
-    // Get network config and slot clock.
     let network_config = match (&config.network, &config.network_dir) {
         (Some(network), None) => Eth2NetworkConfig::constant(network)?
             .ok_or_else(|| format!("Unknown network `{}`", network))?,
@@ -117,51 +295,310 @@ async fn run(shutdown_signal: Arc<AtomicBool>) -> Result<(), String> {
         (None, None) => return Err("one of network or network_dir is required".into()),
     };
     let spec = Arc::new(network_config.chain_spec::<E>()?);
-    let genesis_state = network_config
-        .genesis_state::<E>(
-            None,
-            Duration::from_secs(cli_config.genesis_state_timeout),
-            &dummy_logger,
-        )
-        .await?
-        .ok_or("genesis state must be known")?;
-    let slot_clock = SystemTimeSlotClock::new(
+    if config.request_offset_ms >= spec.seconds_per_slot * 1000 {
+        return Err(format!(
+            "request_offset_ms ({}) must be less than the slot duration ({}s)",
+            config.request_offset_ms, spec.seconds_per_slot
+        ));
+    }
+    let genesis_time = {
+        let mut attempt = 0;
+        let backoff = config.retry_backoff.with_base(
+            cli_config
+                .genesis_state_retry_delay
+                .map(|delay| delay * 1000),
+        );
+        loop {
+            attempt += 1;
+            info!(attempt, "Loading genesis state");
+            let result = network_config
+                .genesis_state::<E>(
+                    None,
+                    Duration::from_secs(cli_config.genesis_state_timeout),
+                    &dummy_logger,
+                )
+                .await
+                .and_then(|state| state.ok_or_else(|| "genesis state must be known".into()))
+                .and_then(|state| check_genesis_time(state.genesis_time(), &spec));
+
+            match result {
+                Ok(genesis_time) => break genesis_time,
+                Err(e) if attempt <= cli_config.genesis_state_retries => {
+                    let retry_delay = backoff.delay(attempt - 1);
+                    warn!(
+                        "Unable to load genesis state: {e}, retrying in {:.1}s",
+                        retry_delay.as_secs_f64()
+                    );
+                    tokio::time::sleep(retry_delay).await;
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "unable to load genesis state after {attempt} attempts: {e}"
+                    ))
+                }
+            }
+        }
+    };
+    Ok((spec, genesis_time))
+}
+
+/// Request, compare and classify blocks for each slot until `shutdown_signal` is set.
+///
+/// Slots are driven by `ticker`, starting from `genesis_time` (in seconds since the UNIX epoch).
+/// With `require_all_nodes`, an error is returned if any node is unreachable at startup.
+async fn run<T: Ticker>(
+    shutdown_signal: Arc<AtomicBool>,
+    ticker: T,
+    config: Config,
+    spec: Arc<ChainSpec>,
+    genesis_time: u64,
+    monitoring: Monitoring,
+    require_all_nodes: bool,
+) -> Result<(), String> {
+    let Monitoring {
+        heartbeat,
+        latest_matrix,
+        metrics,
+        distance_percentiles,
+    } = monitoring;
+
+    // Mapping from node name to label.
+    let mut labels = config
+        .nodes
+        .iter()
+        .filter(|node| node.enabled)
+        .map(|node| (node.name.clone(), node.label.clone()))
+        .collect::<HashMap<_, _>>();
+
+    if let Some(path) = &config.chain_info_file {
+        ChainInfo::new(genesis_time, &spec).write_to_file(path)?;
+    }
+    let slot_clock = ticker.slot_clock(
         spec.genesis_slot,
-        Duration::from_secs(genesis_state.genesis_time()),
+        Duration::from_secs(genesis_time),
         Duration::from_secs(spec.seconds_per_slot),
     );
+    // Blocks are only requested every `slot_sampling` slots, so allow for that plus some slack.
+    heartbeat.genesis_resolved(slot_clock.slot_duration() * (config.slot_sampling as u32 + 2));
+
+    let debug_dumps = config
+        .debug_dir
+        .clone()
+        .map(|dir| Arc::new(DebugDumps::new(dir, config.max_debug_dumps)));
+    let block_json_dir = config.block_json_dir.clone().map(BlockJsonDir::new);
+
+    // Establish connection to canonical BN.
+    let canonical_bn = {
+        let url = SensitiveUrl::parse(&config.canonical_bn)
+            .map_err(|e| format!("Invalid canonical_bn URL: {:?}", e))?;
+        BeaconNodeHttpClient::new(url, Timeouts::set_all(Duration::from_secs(6)))
+    };
 
     // Establish connections to beacon nodes.
-    let nodes = config
+    let adaptive_timeout = config.adaptive_timeout;
+    let mut nodes = config
         .nodes
         .iter()
         .filter(|node| node.enabled)
         .cloned()
-        .map(|config| Node::new(config, spec.clone()))
+        .map(|config| {
+            let node_spec = match &config.network_dir {
+                Some(network_dir) => Eth2NetworkConfig::load(network_dir.clone())
+                    .and_then(|network_config| network_config.chain_spec::<E>())
+                    .map(Arc::new)
+                    .map_err(|e| format!("Unable to load spec for {}: {}", config.name, e))?,
+                None => spec.clone(),
+            };
+            Node::new(config, node_spec, debug_dumps.clone(), adaptive_timeout)
+        })
         .collect::<Result<Vec<_>, String>>()?;
 
-    // Establish connection to canonical BN.
-    let canonical_bn = {
-        let url = SensitiveUrl::parse(&config.canonical_bn)
-            .map_err(|e| format!("Invalid URL: {:?}", e))?;
-        BeaconNodeHttpClient::new(url, Timeouts::set_all(Duration::from_secs(6)))
+    // Check that all nodes are reachable before starting, so that bad URLs show up early.
+    let version_results = join_all(nodes.iter_mut().map(|node| node.load_version())).await;
+    let mut unreachable = vec![];
+    for (node, result) in nodes.iter().zip(version_results) {
+        match result {
+            Ok(()) => info!(
+                node = node.config.name,
+                "Node uses {}, version: {}",
+                node.api_method(),
+                node.version.as_deref().unwrap_or("unknown")
+            ),
+            Err(e) => {
+                warn!(node = node.config.name, "Node is UNREACHABLE: {e}");
+                unreachable.push(node.config.name.clone());
+            }
+        }
+    }
+    match canonical_bn.get_node_version().await {
+        Ok(response) => info!("Canonical node version: {}", response.data.version),
+        Err(e) => {
+            warn!("Canonical node is UNREACHABLE: {:?}", e);
+            unreachable.push("canonical_bn".to_string());
+        }
+    }
+    if unreachable.is_empty() {
+        info!(
+            "Connectivity check passed: all {} nodes and canonical_bn are reachable",
+            nodes.len()
+        );
+    } else {
+        let summary = format!(
+            "Connectivity check failed: {} unreachable",
+            unreachable.join(", ")
+        );
+        if require_all_nodes {
+            return Err(summary);
+        }
+        warn!("{summary}");
+    }
+
+    let run_id = match &config.run_id {
+        Some(run_id) => run_id.clone(),
+        None if config.random_run_id => Uuid::new_v4().to_string(),
+        None => config.default_run_id(),
     };
+    info!("Run ID: {run_id}");
 
     // Establish connections to post endpoints.
     let post_endpoints = config
         .post_endpoints
         .iter()
-        .map(|config| PostEndpoint::new(&config))
-        .collect_vec();
+        .map(|endpoint_config| PostEndpoint::new(endpoint_config, &run_id, metrics.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
 
     // Main loop.
     let mut all_blocks: HashMap<Slot, HashMap<String, BlindedBeaconBlock<E>>> = HashMap::new();
+    // Roots of the canonical blocks compared against, for reorg detection.
+    let mut canonical_roots: HashMap<Slot, Hash256> = HashMap::new();
+    // Root of the last canonical block compared and the block itself, for the canonical churn.
+    let mut last_canonical_block: Option<(Hash256, BlindedBeaconBlock<E>)> = None;
+    // Distance from each node's dream block to the canonical block, for rolling averages.
+    let mut canonical_distances: HashMap<Slot, HashMap<String, usize>> = HashMap::new();
+    // Slot at which the distance percentiles were last logged.
+    let mut last_percentiles_log: Option<Slot> = None;
+    let mut summary_fifo = config.summary_fifo.clone().map(SummaryFifo::new);
+    let verdict_socket = config
+        .verdict_socket
+        .clone()
+        .map(VerdictSocket::bind)
+        .transpose()?;
+    let mut alerter = config.alert_webhook.clone().map(Alerter::new).transpose()?;
+    let mut parquet_sink = config
+        .parquet_dir
+        .clone()
+        .map(|dir| ParquetSink::new(dir, &run_id, config.parquet_flush_slots))
+        .transpose()?;
+    let mut json_output = config
+        .output_json
+        .as_deref()
+        .map(JsonOutput::new)
+        .transpose()?;
+    let mut committees = Committees::default();
+    let post_semaphore = config
+        .max_concurrent_posts
+        .map(|max| Arc::new(Semaphore::new(max)));
+    let mut canonical_breaker = CircuitBreaker::new(config.canonical_failure_threshold);
+    // Most recent classification, included in the distance matrix served over HTTP.
+    let mut latest_verdict: Option<VerdictSummary> = None;
+    let mut node_discovery = config
+        .node_discovery_url
+        .clone()
+        .map(|url| NodeDiscovery::new(url, config.node_discovery_interval_slots))
+        .transpose()?;
+    let static_node_names = config
+        .nodes
+        .iter()
+        .map(|node| node.name.clone())
+        .collect::<HashSet<_>>();
 
     while !shutdown_signal.load(Ordering::Relaxed) {
-        let wait = slot_clock.duration_to_next_slot().expect("post genesis");
-        tokio::time::sleep(wait).await;
+        // Refresh discovered nodes before waiting for the next slot. The refresh is abandoned at
+        // the slot boundary so that block requests at the start of the slot aren't delayed, and
+        // retried once it is next due.
+        if let Some(discovery) = &mut node_discovery {
+            let due_slot = slot_clock.now().filter(|slot| discovery.is_due(*slot));
+            if let (Some(current_slot), Some(deadline)) =
+                (due_slot, slot_clock.duration_to_next_slot())
+            {
+                let refresh = async {
+                    match discovery.fetch(current_slot).await {
+                        Ok(discovered) => {
+                            reconcile_discovered_nodes(
+                                &mut nodes,
+                                &mut labels,
+                                &static_node_names,
+                                &metrics,
+                                discovered,
+                                |config| {
+                                    Node::new(
+                                        config,
+                                        spec.clone(),
+                                        debug_dumps.clone(),
+                                        adaptive_timeout,
+                                    )
+                                },
+                            )
+                            .await
+                        }
+                        Err(e) => warn!("{e}"),
+                    }
+                };
+                if tokio::time::timeout(deadline, refresh).await.is_err() {
+                    warn!("node discovery didn't finish before the next slot, abandoning it");
+                }
+            }
+        }
 
-        let slot = slot_clock.now().unwrap();
+        ticker.tick(&slot_clock).await;
+
+        let slot = match clock::current_slot(&slot_clock) {
+            Ok(slot) => slot,
+            Err(e) => {
+                warn!("skipping slot: {e}");
+                continue;
+            }
+        };
+
+        heartbeat.record_slot();
+
+        if slot % config.slot_sampling != 0 {
+            continue;
+        }
+
+        if let Some(allowed_forks) = &config.allowed_forks {
+            let fork_name = spec.fork_name_at_slot::<E>(slot);
+            if !allowed_forks.contains(&fork_name) {
+                info!(%slot, "fork {fork_name} is not in allowed_forks, skipping slot");
+                continue;
+            }
+        }
+
+        // Wait until the configured offset into the slot. Requests are still rejected below if
+        // the slot has rolled over in the meantime.
+        if config.request_offset_ms > 0 {
+            let offset = Duration::from_millis(config.request_offset_ms);
+            match clock::time_until_offset(&slot_clock, offset) {
+                Ok(wait) => tokio::time::sleep(wait).await,
+                Err(e) => {
+                    warn!(%slot, "skipping slot: {e}");
+                    continue;
+                }
+            }
+        }
+
+        let slot_reveal = match config.randao_source {
+            RandaoSource::Canonical => {
+                let timeout = Duration::from_millis(config.canonical_randao_timeout_ms);
+                canonical_randao_reveal::<E>(&canonical_bn, slot, timeout).await
+            }
+            RandaoSource::Infinity => None,
+        };
+
+        let slot_trace = SlotTrace::start(slot);
+        let compare_builder_boost = config.compare_builder_boost;
+        let verbose = config.verbose;
+        let retry_backoff = config.retry_backoff;
 
         // Dispatch requests in parallel to all dreaming nodes.
         let handles = nodes
@@ -169,29 +606,97 @@ async fn run(shutdown_signal: Arc<AtomicBool>) -> Result<(), String> {
             .map(|node| {
                 let inner = node.clone();
                 let slot_clock = slot_clock.clone();
+                let metrics = metrics.clone();
                 let name = node.config.name.clone();
+                let slot_reveal = slot_reveal.clone();
+
+                let node_span = slot_trace.node_request(&name);
 
                 tokio::spawn(async move {
-                    let current_slot = slot_clock.now().unwrap();
-                    if current_slot != slot {
-                        return Err(format!(
-                            "too slow, slot {} expired (slot now: {})",
-                            slot, current_slot
-                        ));
-                    }
-                    let slot_offset = slot_clock.seconds_from_current_slot_start().unwrap();
-                    if VERBOSE {
-                        eprintln!(
-                            "requesting block from {} at {}s after slot start",
-                            name,
-                            slot_offset.as_secs()
-                        );
-                    }
+                    let result: Result<_, String> = async {
+                        let current_slot = clock::current_slot(&slot_clock)?;
+                        if current_slot != slot {
+                            return Err(format!(
+                                "too slow, slot {} expired (slot now: {})",
+                                slot, current_slot
+                            ));
+                        }
+                        let slot_offset = clock::time_into_slot(&slot_clock)?;
+                        if verbose {
+                            info!(
+                                %slot,
+                                node = name,
+                                "requesting block at {}s after slot start",
+                                slot_offset.as_secs()
+                            );
+                        }
 
-                    let (blinded_block, opt_metadata) = inner
-                        .get_block_with_timeout::<E>(slot, inner.config.builder_boost_factor)
-                        .await?;
-                    Ok((blinded_block, opt_metadata))
+                        // Only the v3 API supports the builder boost factor.
+                        let compare_builder = compare_builder_boost && inner.uses_v3();
+                        let builder_boost_factor = if compare_builder {
+                            Some(0)
+                        } else {
+                            inner.config.builder_boost_factor
+                        };
+                        let local_request = async {
+                            if inner.config.sample_offsets_ms.is_empty() {
+                                let start = Instant::now();
+                                let result = inner
+                                    .get_block_with_retries::<E, _>(
+                                        slot,
+                                        slot_reveal.as_ref(),
+                                        builder_boost_factor,
+                                        &slot_clock,
+                                        retry_backoff,
+                                    )
+                                    .await;
+                                // Sampled requests include deliberate delays, so only time these.
+                                if result.is_ok() {
+                                    metrics.observe_block_request(
+                                        &node_labels(&inner),
+                                        start.elapsed(),
+                                    );
+                                }
+                                result
+                            } else {
+                                inner
+                                    .get_best_sampled_block::<E>(
+                                        slot,
+                                        slot_reveal.as_ref(),
+                                        builder_boost_factor,
+                                        slot_offset,
+                                    )
+                                    .await
+                            }
+                        };
+                        let builder_request = async {
+                            if compare_builder {
+                                Some(
+                                    inner
+                                        .get_block_with_retries::<E, _>(
+                                            slot,
+                                            slot_reveal.as_ref(),
+                                            Some(PREFER_BUILDER_BOOST_FACTOR),
+                                            &slot_clock,
+                                            retry_backoff,
+                                        )
+                                        .await,
+                                )
+                            } else {
+                                None
+                            }
+                        };
+                        let (local_result, builder_result) =
+                            tokio::join!(local_request, builder_request);
+                        // If either request downgraded the node to v2, neither block honoured the
+                        // boost factor, so there is nothing to compare.
+                        let builder_result = builder_result.filter(|_| inner.uses_v3());
+                        let (blinded_block, opt_metadata, blobs) = local_result?;
+                        Ok((blinded_block, opt_metadata, blobs, builder_result))
+                    }
+                    .await;
+                    node_span.finish(&result);
+                    result
                 })
             })
             .collect::<Vec<_>>();
@@ -202,161 +707,1208 @@ async fn run(shutdown_signal: Arc<AtomicBool>) -> Result<(), String> {
         for (result, node) in join_all(handles).await.into_iter().zip(&nodes) {
             let name = node.config.name.clone();
 
-            match result.map_err(|e| format!("Task panicked: {:?}", e))? {
-                Ok((block, metadata)) => {
-                    eprintln!(
-                        "slot {}: block from {} with {} attestations & purported reward {} wei",
-                        slot,
-                        name,
+            let result = result.map_err(|e| format!("Task panicked: {:?}", e))?;
+            metrics.record_block_request(&node_labels(node), result.is_ok());
+            match result {
+                Ok((block, metadata, blobs, builder_result)) => {
+                    heartbeat.record_block();
+                    match builder_result {
+                        Some(Ok((builder_block, builder_metadata, _))) => {
+                            if node
+                                .config
+                                .expect_distinct_blocks
+                                .contains(&DistinctBlocks::BuilderBoost)
+                            {
+                                log_repeated_roots(
+                                    slot,
+                                    &name,
+                                    &[
+                                        ("local block".to_string(), block.canonical_root()),
+                                        (
+                                            "builder block".to_string(),
+                                            builder_block.canonical_root(),
+                                        ),
+                                    ],
+                                );
+                            }
+                            log_builder_comparison(
+                                slot,
+                                &name,
+                                (&block, metadata.as_ref()),
+                                (&builder_block, builder_metadata.as_ref()),
+                                &config.distance,
+                            );
+                        }
+                        Some(Err(e)) => {
+                            warn!(%slot, node = name, "failed to produce a builder block: {e}");
+                        }
+                        None => (),
+                    }
+
+                    let reported_reward = metadata.map(|m| m.consensus_block_value);
+                    let num_blobs =
+                        blobs.map_or(String::new(), |blobs| format!(", {} blobs", blobs.count));
+                    info!(
+                        %slot,
+                        node = name,
+                        "block with {} attestations{} & purported reward {} wei",
                         block.body().attestations().len(),
-                        metadata.map_or(Uint256::zero(), |m| m.consensus_block_value)
+                        num_blobs,
+                        reported_reward.unwrap_or(Uint256::zero())
                     );
 
+                    if config.compare_fee_recipients {
+                        info!(
+                            %slot,
+                            node = name,
+                            "block has fee recipient {:?}",
+                            fee_recipient(&block)
+                        );
+                    }
+
+                    if let Some(min_overlap) = config.duplicate_attestation_overlap {
+                        let duplicates = find_duplicate_attestations(
+                            &block.body().attestations()[..],
+                            min_overlap,
+                        );
+                        for (data, count) in duplicates {
+                            warn!(
+                                %slot,
+                                node = name,
+                                "block has {} duplicate attestations for slot {} index {}",
+                                count,
+                                data.slot,
+                                data.index
+                            );
+                        }
+                    }
+
+                    if let Some(block_json_dir) = &block_json_dir {
+                        block_json_dir.save(&name, slot, &block).await;
+                    }
+
                     if !post_endpoints.is_empty() {
-                        post_blocks.push(Some(block.clone()));
+                        post_blocks.push(Ok((block.clone(), reported_reward)));
                     }
 
                     slot_blocks.insert(node.config.name.clone(), block);
                 }
                 Err(e) => {
-                    eprintln!("{} failed to produce a block: {}", name, e);
+                    warn!(%slot, node = name, "failed to produce a block: {e}");
                     if !post_endpoints.is_empty() {
-                        post_blocks.push(None);
+                        post_blocks.push(Err(e));
                     }
                 }
             }
         }
 
-        for post_endpoint in &post_endpoints {
-            let names_and_labels = nodes
+        // Count distinct blocks among the nodes that succeeded (only if needed, as hashing is slow).
+        let all_identical = (config.collapse_identical_blocks
+            || config.skip_posting_identical_blocks)
+            && slot_blocks.len() > 1
+            && slot_blocks
+                .values()
+                .map(|block| block.canonical_root())
+                .collect::<HashSet<_>>()
+                .len()
+                == 1;
+        // Agreement only covers the nodes that responded.
+        let failed_nodes = nodes.len() - slot_blocks.len();
+
+        let maintenance_window = active_window(&config.maintenance_windows, Utc::now());
+        if all_identical && config.skip_posting_identical_blocks {
+            if !post_endpoints.is_empty() {
+                info!(
+                    %slot,
+                    failed_nodes,
+                    "all {} nodes that responded produced identical blocks ({} failed), skipping post",
+                    slot_blocks.len(),
+                    failed_nodes
+                );
+            }
+            for post_endpoint in &post_endpoints {
+                metrics.record_post(&post_endpoint.name, "skipped");
+            }
+        } else if let Some(window) = maintenance_window {
+            if !post_endpoints.is_empty() {
+                info!(%slot, %window, "in maintenance window, skipping posts");
+            }
+            for post_endpoint in &post_endpoints {
+                metrics.record_post(&post_endpoint.name, "skipped");
+            }
+        } else {
+            let node_info = nodes
                 .iter()
-                .map(|node| (node.config.name.clone(), node.config.label.clone()))
+                .map(|node| {
+                    (
+                        node.config.name.clone(),
+                        node.config.label.clone(),
+                        node.version.clone(),
+                    )
+                })
                 .collect_vec();
-            let endpoint = post_endpoint.clone();
-            let post_blocks = post_blocks.clone();
-            tokio::spawn(async move {
-                if let Err(e) = endpoint
-                    .post_blocks(names_and_labels, post_blocks, slot)
-                    .await
-                {
-                    eprintln!(
-                        "error posting blocks to {} at slot {}: {}",
-                        endpoint.name, slot, e
-                    );
-                }
-            });
+            // Shared by all endpoints, so that queued posts don't each hold a copy of the blocks.
+            let post_blocks = Arc::new(std::mem::take(&mut post_blocks));
+            let max_post_lag_slots = config.max_post_lag_slots;
+            for post_endpoint in &post_endpoints {
+                let node_info = node_info.clone();
+                let endpoint = post_endpoint.clone();
+                let post_blocks = post_blocks.clone();
+                let post_semaphore = post_semaphore.clone();
+                let slot_clock = slot_clock.clone();
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    // Wait for a free slot if the number of concurrent posts is limited, giving up
+                    // once the post is too far behind.
+                    let _permit = match post_semaphore {
+                        Some(semaphore) => {
+                            let deadline = slot + max_post_lag_slots + 1;
+                            let wait = slot_clock
+                                .duration_to_slot(deadline)
+                                .unwrap_or(Duration::ZERO);
+                            match tokio::time::timeout(wait, semaphore.acquire_owned()).await {
+                                Ok(Ok(permit)) => Some(permit),
+                                Ok(Err(_)) => return,
+                                Err(_) => {
+                                    warn!(
+                                        %slot,
+                                        endpoint = endpoint.name,
+                                        "dropping post, still queued at slot {deadline}"
+                                    );
+                                    metrics.record_post(&endpoint.name, "dropped");
+                                    return;
+                                }
+                            }
+                        }
+                        None => None,
+                    };
+                    if let Ok(current_slot) = clock::current_slot(&slot_clock) {
+                        if current_slot > slot {
+                            warn!(
+                                %slot,
+                                endpoint = endpoint.name,
+                                "posting is behind: posting at slot {current_slot}"
+                            );
+                        }
+                    }
+
+                    // Catch panics so that they're logged rather than silently dropped.
+                    let result =
+                        AssertUnwindSafe(endpoint.post_blocks(node_info, post_blocks, slot))
+                            .catch_unwind()
+                            .await;
+                    match result {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => {
+                            error!(
+                                %slot,
+                                endpoint = endpoint.name,
+                                "error posting blocks: {e}"
+                            );
+                        }
+                        Err(panic) => {
+                            error!(
+                                %slot,
+                                endpoint = endpoint.name,
+                                "post task panicked: {}",
+                                panic_message(&*panic)
+                            );
+                        }
+                    }
+                });
+            }
         }
 
         if slot_blocks.len() == nodes.len() {
             all_blocks.insert(slot, slot_blocks);
         } else {
-            eprintln!("slot {slot}: discarding results due to failures");
+            warn!(%slot, "discarding results due to failures");
         }
 
-        // Compare canonical block from previous slot to dream blocks.
-        let prev_slot = slot - 1;
-        match canonical_bn
-            .get_beacon_blocks(BlockId::Slot(prev_slot))
-            .await
-        {
-            Ok(Some(res)) => {
-                let (full_block, _) = res.data.deconstruct();
-                let (block, _) = full_block.into();
-                if let Some(dream_blocks) = all_blocks.get(&prev_slot) {
-                    let mut distances = dream_blocks
-                        .iter()
-                        .map(|(name, dream_block)| {
-                            let delta = dream_block.delta(&block).unwrap();
-                            let distance = BlindedBeaconBlock::<E>::delta_to_distance(&delta);
-                            if VERBOSE {
-                                eprintln!("canonical({})-{} delta: {:#?}", prev_slot, name, delta);
-                            }
-                            eprintln!(
-                                "slot {}: canonical <=> {} distance: {}",
-                                prev_slot, name, distance
+        // Compare canonical block from previous (sampled) slot to dream blocks.
+        let prev_slot = slot - config.slot_sampling;
+
+        // Skip the comparison while the canonical node is unreachable, probing it once per slot.
+        let canonical_available = !canonical_breaker.is_open()
+            || match canonical_bn.get_node_version().await {
+                Ok(_) => {
+                    canonical_breaker.record_success();
+                    info!("canonical available again, resuming comparison");
+                    true
+                }
+                Err(_) => false,
+            };
+
+        let comparison_span = slot_trace.canonical_comparison(prev_slot);
+        if canonical_available {
+            // Check whether the canonical block compared against last time has been reorged out.
+            let last_compared_slot = prev_slot - config.slot_sampling;
+            if let Some(old_root) = canonical_roots.get(&last_compared_slot).copied() {
+                match get_canonical_root(&canonical_bn, last_compared_slot).await {
+                    Ok(new_root) if new_root != Some(old_root) => {
+                        warn!(
+                            slot = %last_compared_slot,
+                            "canonical block reorged from {:?} to {:?}",
+                            old_root,
+                            new_root
+                        );
+                        canonical_roots.remove(&last_compared_slot);
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!(
+                            slot = %last_compared_slot,
+                            "Error checking canonical root: {e}"
+                        );
+                    }
+                }
+            }
+
+            let block_id = match config.canonical_comparison {
+                CanonicalComparison::Slot => BlockId::Slot(prev_slot),
+                CanonicalComparison::Root => {
+                    match get_canonical_root(&canonical_bn, prev_slot).await {
+                        Ok(opt_root) => opt_root.map_or(BlockId::Slot(prev_slot), BlockId::Root),
+                        Err(e) => {
+                            warn!(
+                                slot = %prev_slot,
+                                "Error fetching canonical root, comparing by slot: {e}"
                             );
-                            (name, distance)
-                        })
-                        .collect::<Vec<_>>();
+                            BlockId::Slot(prev_slot)
+                        }
+                    }
+                }
+            };
 
-                    distances.sort_unstable_by_key(|(_, distance)| *distance);
+            // The canonical node may not have imported the block yet, so retry 404s while there's
+            // time left in the slot.
+            let backoff = config
+                .retry_backoff
+                .with_base(config.canonical_block_retry_delay_ms);
+            let mut attempt = 0;
+            let canonical_block = loop {
+                let result = canonical_bn.get_beacon_blocks::<E>(block_id).await;
+                let retry_delay = backoff.delay(attempt);
+                let time_left = clock::time_into_slot(&slot_clock)
+                    .is_ok_and(|elapsed| elapsed + retry_delay < slot_clock.slot_duration());
+                if !matches!(result, Ok(None))
+                    || attempt >= config.canonical_block_retries
+                    || !time_left
+                {
+                    break result;
+                }
+                attempt += 1;
+                tokio::time::sleep(retry_delay).await;
+            };
 
-                    let (closest_name, closest_distance) = &distances[0];
-                    let (second_closest_name, second_closest_distance) =
-                        &distances.get(1).unwrap_or(&distances[0]);
+            match canonical_block {
+                Ok(Some(res)) => {
+                    canonical_breaker.record_success();
+                    let (full_block, _) = res.data.deconstruct();
+                    let canonical_root = full_block.canonical_root();
+                    canonical_roots.insert(prev_slot, canonical_root);
+                    let (block, _) = full_block.into();
+                    if config.log_canonical_churn {
+                        log_canonical_churn(
+                            &canonical_bn,
+                            prev_slot,
+                            &block,
+                            last_canonical_block.as_ref(),
+                            &config.distance,
+                        )
+                        .await;
+                        last_canonical_block = Some((canonical_root, block.clone()));
+                    }
+                    if let Some(dream_blocks) = all_blocks.get(&prev_slot) {
+                        let dream_blocks = if config.require_same_fork {
+                            same_fork_blocks(prev_slot, dream_blocks, &block, &spec)
+                        } else {
+                            Cow::Borrowed(dream_blocks)
+                        };
+                        let compared_blocks = canonicalized_blocks(&dream_blocks, &config.distance);
+                        let compared_block = canonicalized_block(&block, &config.distance);
+                        // Optionally merge nodes with identical blocks into a single entry.
+                        let (entries, entry_labels, entry_members) =
+                            if config.collapse_identical_nodes {
+                                collapse_identical_blocks(prev_slot, &compared_blocks, &labels)
+                            } else {
+                                let entries = compared_blocks
+                                    .iter()
+                                    .map(|(name, dream_block)| (name.clone(), dream_block))
+                                    .collect();
+                                let members = compared_blocks
+                                    .keys()
+                                    .map(|name| (name.clone(), vec![name.as_str()]))
+                                    .collect();
+                                (entries, labels.clone(), members)
+                            };
 
-                    let closest_label = &labels[closest_name.as_str()];
-                    let second_closest_label = &labels[second_closest_name.as_str()];
+                        // Compute deltas in parallel, then log them in a deterministic order.
+                        let mut deltas = entries
+                            .par_iter()
+                            .map(|(name, dream_block)| {
+                                let delta = dream_block
+                                    .delta(&compared_block, &config.distance)
+                                    .unwrap();
+                                let max_distance =
+                                    dream_block.max_distance(&compared_block, &config.distance);
+                                (name.as_str(), delta, max_distance)
+                            })
+                            .collect::<Vec<_>>();
+                        deltas.sort_unstable_by_key(|(name, _, _)| *name);
 
-                    if closest_label == second_closest_label {
-                        eprintln!(
-                            "slot {}: canonical block is likely {}@{} (two closest match)",
-                            prev_slot, closest_label, closest_distance
-                        );
-                    } else if *second_closest_distance
-                        >= closest_distance * SIGNIFICANCE_NUMERATOR / SIGNIFICANCE_DENOM
-                    {
-                        eprintln!(
-                            "slot {}: canonical block is likely {} \
-                             (significantly closer @{} than 2nd place {}@{})",
+                        let mut distances = deltas
+                            .iter()
+                            .map(|(name, delta, max_distance)| {
+                                let distance = BlindedBeaconBlock::<E>::delta_to_distance(
+                                    delta,
+                                    &config.distance,
+                                );
+                                if config.verbose {
+                                    info!(
+                                        slot = %prev_slot,
+                                        node = name,
+                                        "canonical delta: {:#?}",
+                                        delta
+                                    );
+                                }
+                                // The sync aggregate is logged separately as it only counts
+                                // towards the distance with a non-zero `sync_weight`.
+                                info!(
+                                    slot = %prev_slot,
+                                    node = name,
+                                    distance,
+                                    sync_bits = delta.sync_aggregate,
+                                    "canonical <=> {} distance: {}{}{}",
+                                    name,
+                                    distance,
+                                    normalized_suffix(distance, *max_distance),
+                                    sync_suffix(delta.sync_aggregate)
+                                );
+                                (*name, distance)
+                            })
+                            .collect::<Vec<_>>();
+
+                        // Break ties by name so that verdicts are stable.
+                        distances.sort_unstable_by_key(|(name, distance)| (*distance, *name));
+
+                        // Show which components drove the verdict.
+                        if config.log_distance_breakdown {
+                            for (name, _) in distances.iter().take(2) {
+                                let Some((_, delta, _)) =
+                                    deltas.iter().find(|(delta_name, _, _)| delta_name == name)
+                                else {
+                                    continue;
+                                };
+                                info!(
+                                    slot = %prev_slot,
+                                    node = name,
+                                    "canonical distance breakdown: {}",
+                                    delta.breakdown::<E>(&config.distance)
+                                );
+                            }
+                        }
+
+                        // Collapsed entries stand for each of their members.
+                        let node_distances = distances
+                            .iter()
+                            .flat_map(|(name, distance)| {
+                                entry_members[*name]
+                                    .iter()
+                                    .map(move |member| (*member, *distance))
+                            })
+                            .collect::<Vec<_>>();
+                        for (name, distance) in &node_distances {
+                            if let Some(node) = nodes.iter().find(|node| node.config.name == *name)
+                            {
+                                metrics.set_canonical_distance(&node_labels(node), *distance);
+                            }
+                        }
+                        let versions = node_distances
+                            .iter()
+                            .filter_map(|(name, _)| {
+                                let node = nodes.iter().find(|node| node.config.name == *name)?;
+                                Some((*name, node.version.as_deref()?))
+                            })
+                            .collect::<BTreeMap<_, _>>();
+
+                        if let Some(output) = &mut json_output {
+                            output.set_canonical(prev_slot, &distances);
+                        }
+                        canonical_distances.insert(
                             prev_slot,
-                            closest_label,
-                            closest_distance,
-                            second_closest_label,
-                            second_closest_distance
+                            distances
+                                .iter()
+                                .map(|(name, distance)| (name.to_string(), *distance))
+                                .collect(),
                         );
+                        if let (Some(percentiles), Some(percentiles_config)) =
+                            (&distance_percentiles, config.distance_percentiles)
+                        {
+                            percentiles.record(prev_slot, &node_distances);
+                            let interval = percentiles_config.log_interval_slots;
+                            if interval > 0
+                                && last_percentiles_log
+                                    .is_none_or(|last| prev_slot >= last + interval)
+                            {
+                                last_percentiles_log = Some(prev_slot);
+                                for (name, summary) in percentiles.summary() {
+                                    info!(
+                                        slot = %prev_slot,
+                                        node = name,
+                                        "canonical distance over last {} slots: {}",
+                                        percentiles_config.window_slots,
+                                        summary
+                                    );
+                                }
+                            }
+                        }
+                        if let Some(window) = config.distance_window {
+                            for (name, _) in &distances {
+                                let window_distances = canonical_distances
+                                    .iter()
+                                    .filter(|(stored_slot, _)| **stored_slot + window > prev_slot)
+                                    .filter_map(|(_, slot_distances)| slot_distances.get(*name))
+                                    .copied()
+                                    .collect::<Vec<_>>();
+                                let mean_distance = window_distances.iter().sum::<usize>() as f64
+                                    / window_distances.len() as f64;
+                                info!(
+                                    slot = %prev_slot,
+                                    node = name,
+                                    "canonical mean distance over last {} slots: {:.1} ({} samples)",
+                                    window,
+                                    mean_distance,
+                                    window_distances.len()
+                                );
+                            }
+                        }
+
+                        match classify(
+                            &distances,
+                            &entry_labels,
+                            config.significance_ratio,
+                            config.confidence_exponent,
+                        ) {
+                            Ok(classification) => {
+                                info!(
+                                    slot = %prev_slot,
+                                    verdict = %classification.verdict,
+                                    confidence = classification.confidence,
+                                    "{} (confidence {:.2})",
+                                    classification.verdict,
+                                    classification.confidence
+                                );
+                                metrics.record_classification(&classification);
+
+                                let verdict_summary = VerdictSummary::new(
+                                    prev_slot,
+                                    &classification,
+                                    &distances,
+                                    &versions,
+                                );
+                                if let Some(socket) = &verdict_socket {
+                                    socket.send(&verdict_summary);
+                                }
+                                latest_verdict = Some(verdict_summary);
+
+                                if let Some(fifo) = &mut summary_fifo {
+                                    fifo.write(&SlotSummary::new(
+                                        prev_slot,
+                                        &classification,
+                                        &distances,
+                                        &versions,
+                                    ));
+                                }
+
+                                if let Some(alerter) = &mut alerter {
+                                    for alert in alerter.check(
+                                        prev_slot,
+                                        &classification,
+                                        &distances,
+                                        Instant::now(),
+                                    ) {
+                                        alerter.send(alert);
+                                    }
+                                }
+
+                                if let Some(shadow_params) = &config.shadow_distance {
+                                    let shadow_blocks =
+                                        canonicalized_blocks(&dream_blocks, shadow_params);
+                                    let shadow_block = canonicalized_block(&block, shadow_params);
+                                    let shadow_distances = sorted_distances(
+                                        &shadow_blocks,
+                                        &shadow_block,
+                                        shadow_params,
+                                    );
+                                    let shadow = classify(
+                                        &shadow_distances,
+                                        &labels,
+                                        config.significance_ratio,
+                                        config.confidence_exponent,
+                                    );
+                                    if let Some(shadow) = shadow.ok().filter(|shadow| {
+                                        shadow.verdict.label() != classification.verdict.label()
+                                    }) {
+                                        info!(
+                                            slot = %prev_slot,
+                                            "shadow distance disagrees: {} (confidence {:.2})",
+                                            shadow.verdict,
+                                            shadow.confidence
+                                        );
+                                    }
+                                }
+                            }
+                            Err(e) => {
+                                warn!(slot = %prev_slot, "unable to classify canonical block: {e}");
+                            }
+                        }
                     } else {
-                        eprintln!(
-                            "slot {}: canonical block is too close to call ({}@{} vs {}@{})",
-                            prev_slot,
-                            closest_name,
-                            closest_distance,
-                            second_closest_name,
-                            second_closest_distance
-                        );
+                        info!(slot = %prev_slot, "No dream blocks");
                     }
-                } else {
-                    eprintln!("No dream blocks for slot {}", prev_slot);
                 }
-            }
-            Ok(None) => {
-                eprintln!("No canonical block at slot {}", prev_slot);
-            }
-            Err(e) => {
-                eprintln!(
-                    "Error fetching canonical block at slot {}: {:?}",
-                    prev_slot, e
-                );
+                Ok(None) => {
+                    canonical_breaker.record_success();
+                    info!(slot = %prev_slot, "No canonical block");
+                }
+                Err(e) => {
+                    if canonical_breaker.record_failure() {
+                        warn!("canonical unavailable, skipping comparison");
+                    }
+                    warn!(slot = %prev_slot, "Error fetching canonical block: {:?}", e);
+                }
             }
         }
 
-        if let Some(blocks) = all_blocks.get(&slot) {
+        comparison_span.finish(&if canonical_available {
+            Ok(())
+        } else {
+            Err("canonical unavailable".to_string())
+        });
+
+        if all_identical && config.collapse_identical_blocks {
+            info!(
+                %slot,
+                failed_nodes,
+                "all {} nodes that responded agree (identical blocks), {} failed",
+                nodes.len() - failed_nodes,
+                failed_nodes
+            );
+            if let Some(blocks) = all_blocks.get(&slot).filter(|_| {
+                config.http_address.is_some() || json_output.is_some() || parquet_sink.is_some()
+            }) {
+                let pairs = blocks
+                    .keys()
+                    .tuple_combinations()
+                    .map(|(name1, name2)| (name1, name2, 0))
+                    .collect::<Vec<_>>();
+                if config.http_address.is_some() {
+                    latest_matrix.set(DistanceMatrix::new(
+                        slot,
+                        &labels,
+                        &pairs,
+                        latest_verdict.clone(),
+                    ));
+                }
+                if let Some(output) = &mut json_output {
+                    output.set_pairs(slot, &pairs);
+                }
+                if let Some(sink) = &mut parquet_sink {
+                    sink.push_pairs(slot, &pairs, &labels);
+                }
+            }
+        } else if let Some(blocks) = all_blocks.get(&slot) {
+            // Attestations may be for the current or previous epoch.
+            let attesting_indices = if config.log_validator_differences {
+                let epoch = slot.epoch(E::slots_per_epoch());
+                for epoch in [epoch.saturating_sub(1u64), epoch] {
+                    if let Err(e) = committees.load_epoch(&canonical_bn, epoch).await {
+                        warn!(%slot, "{e}");
+                    }
+                }
+                committees.prune::<E>(epoch.saturating_sub(1u64));
+                blocks
+                    .iter()
+                    .map(|(name, block)| {
+                        let indices = committees.attesting_indices(block.body().attestations());
+                        (name, indices)
+                    })
+                    .collect::<HashMap<_, _>>()
+            } else {
+                HashMap::new()
+            };
+
+            let mut pair_distances = vec![];
+            // Distances between all pairs regardless of `pairwise_filter`, for the divergence, the
+            // matrix served over HTTP, the Parquet output and the JSON output.
+            let record_all_pairs = config.divergence.is_some()
+                || config.http_address.is_some()
+                || parquet_sink.is_some()
+                || json_output.is_some();
+            let included_pair = |name1: &String, name2: &String| {
+                config
+                    .pairwise_filter
+                    .includes(labels.get(name1) == labels.get(name2))
+            };
+
+            // Compute the delta for each pair of nodes once per slot, in parallel. Pairs are keyed
+            // by name in lexicographic order.
+            let params = &config.distance;
+            let compared_blocks = canonicalized_blocks(blocks, params);
+            let mut pair_deltas = compared_blocks
+                .iter()
+                .tuple_combinations()
+                .map(|(entry1, entry2)| {
+                    if entry1.0 < entry2.0 {
+                        (entry1, entry2)
+                    } else {
+                        (entry2, entry1)
+                    }
+                })
+                .filter(|((name1, _), (name2, _))| record_all_pairs || included_pair(name1, name2))
+                .filter(|((name1, block1), (name2, block2))| {
+                    !config.require_same_fork
+                        || match fork_mismatch(*block1, *block2, &spec) {
+                            Some((fork1, fork2)) => {
+                                info!(
+                                    %slot,
+                                    node_a = name1,
+                                    node_b = name2,
+                                    "fork mismatch ({fork1} vs {fork2}), skipping comparison"
+                                );
+                                false
+                            }
+                            None => true,
+                        }
+                })
+                .collect::<Vec<_>>()
+                .into_par_iter()
+                .map(|((name1, block1), (name2, block2))| {
+                    ((name1, name2), block1.delta(block2, params).unwrap())
+                })
+                .collect::<HashMap<_, _>>();
+
+            let mut all_pair_distances = vec![];
             for (name1, block1) in blocks {
                 for (name2, block2) in blocks {
-                    // Use lexicographic name ordering to establish order.
-                    if name1 >= name2 {
+                    let Some(delta) = pair_deltas.remove(&(name1, name2)) else {
+                        continue;
+                    };
+                    let included = included_pair(name1, name2);
+
+                    let distance =
+                        BlindedBeaconBlock::<E>::delta_to_distance(&delta, &config.distance);
+                    all_pair_distances.push((name1, name2, distance));
+                    if !included {
                         continue;
                     }
 
-                    let delta = block1.delta(block2).unwrap();
-                    if VERBOSE {
-                        eprintln!("{}-{} delta: {:#?}", name1, name2, delta);
+                    if config.verbose {
+                        info!(%slot, node_a = name1, node_b = name2, "delta: {:#?}", delta);
                     }
-                    eprintln!(
-                        "slot {}: {} <=> {} distance: {}",
-                        slot,
-                        name1,
-                        name2,
-                        BlindedBeaconBlock::<E>::delta_to_distance(&delta)
+                    let signature_mismatches = count_signature_mismatches(&delta.attestations);
+                    if signature_mismatches > 0 {
+                        info!(
+                            %slot,
+                            node_a = name1,
+                            node_b = name2,
+                            "{} attestations differ only in signature presence",
+                            signature_mismatches
+                        );
+                    }
+                    if config.compare_fee_recipients {
+                        let (fee_recipient1, fee_recipient2) =
+                            (fee_recipient(block1), fee_recipient(block2));
+                        if fee_recipient1 != fee_recipient2 {
+                            info!(
+                                %slot,
+                                node_a = name1,
+                                node_b = name2,
+                                "fee recipients differ: {:?} vs {:?}",
+                                fee_recipient1,
+                                fee_recipient2
+                            );
+                        }
+                    }
+                    if let (Some(indices1), Some(indices2)) =
+                        (attesting_indices.get(name1), attesting_indices.get(name2))
+                    {
+                        let (only1, only2) = validator_differences(indices1, indices2);
+                        info!(
+                            %slot,
+                            node_a = name1,
+                            node_b = name2,
+                            "{} validators only attested by {}, {} only by {}",
+                            only1.len(),
+                            name1,
+                            only2.len(),
+                            name2
+                        );
+                        if config.verbose {
+                            info!(
+                                %slot,
+                                "{} only: {:?}, {} only: {:?}",
+                                name1,
+                                only1,
+                                name2,
+                                only2
+                            );
+                        }
+                    }
+                    let max_distance = block1.max_distance(block2, &config.distance);
+                    pair_distances.push((name1, name2, distance, max_distance));
+                }
+            }
+
+            if let Some(aggregation) = config.divergence {
+                let distances = all_pair_distances
+                    .iter()
+                    .map(|(_, _, distance)| *distance)
+                    .collect::<Vec<_>>();
+                if let Some(divergence) = aggregation.aggregate(&distances) {
+                    info!(
+                        %slot,
+                        divergence,
+                        "divergence ({:?} pairwise distance): {:.1}",
+                        aggregation,
+                        divergence
+                    );
+                    metrics.set_divergence(divergence);
+                }
+            }
+
+            if config.http_address.is_some() {
+                latest_matrix.set(DistanceMatrix::new(
+                    slot,
+                    &labels,
+                    &all_pair_distances,
+                    latest_verdict.clone(),
+                ));
+            }
+
+            if let Some(output) = &mut json_output {
+                output.set_pairs(slot, &all_pair_distances);
+            }
+
+            if let Some(sink) = &mut parquet_sink {
+                sink.push_pairs(slot, &all_pair_distances, &labels);
+            }
+
+            // Only log the closest pairs if configured, to keep the log manageable.
+            if let Some(n) = config.log_top_n_pairs {
+                pair_distances.sort_unstable_by_key(|(name1, name2, distance, _)| {
+                    (*distance, *name1, *name2)
+                });
+                pair_distances.truncate(n);
+            }
+
+            for (name1, name2, distance, max_distance) in pair_distances {
+                info!(
+                    %slot,
+                    node_a = name1,
+                    node_b = name2,
+                    distance,
+                    "{} <=> {} distance: {}{}",
+                    name1,
+                    name2,
+                    distance,
+                    normalized_suffix(distance, max_distance)
+                );
+            }
+        }
+
+        if config.log_consensus_distance {
+            if let Some(blocks) = all_blocks.get(&slot) {
+                for (name, block) in blocks.iter().sorted_by_key(|(name, _)| *name) {
+                    let others = blocks
+                        .iter()
+                        .filter(|(other_name, _)| *other_name != name)
+                        .sorted_by_key(|(other_name, _)| *other_name)
+                        .map(|(_, other_block)| &other_block.body().attestations()[..])
+                        .collect::<Vec<_>>();
+                    let consensus = majority_attestations(&others);
+                    let distance = (&block.body().attestations()[..])
+                        .distance(&consensus.as_slice(), &config.distance)
+                        .unwrap();
+                    info!(
+                        %slot,
+                        node = name,
+                        distance,
+                        "distance to consensus of other nodes: {}",
+                        distance
+                    );
+                }
+            }
+        }
+
+        if config.log_epoch_distributions {
+            if let Some(blocks) = all_blocks.get(&slot) {
+                let distributions = blocks
+                    .iter()
+                    .sorted_by_key(|(name, _)| *name)
+                    .map(|(name, block)| {
+                        let distribution = EpochDistribution::new(&block.body().attestations()[..]);
+                        info!(
+                            %slot,
+                            node = name,
+                            "target epochs: {:?}, source epochs: {:?}",
+                            distribution.target,
+                            distribution.source
+                        );
+                        (name, distribution)
+                    })
+                    .collect::<Vec<_>>();
+                for ((name1, distribution1), (name2, distribution2)) in
+                    distributions.iter().tuple_combinations()
+                {
+                    info!(
+                        %slot,
+                        node_a = name1,
+                        node_b = name2,
+                        "epoch distribution distance: {}",
+                        distribution1.distance(distribution2)
                     );
                 }
             }
         }
 
         // Prune blocks to prevent the in-memory map from consuming too much memory. We really only
-        // need the 2 most recent slots, but there's no harm in keeping a few more.
-        all_blocks.retain(|stored_slot, _| *stored_slot + NUM_SLOTS_IN_MEMORY >= slot);
+        // need the 2 most recent sampled slots, but there's no harm in keeping a few more.
+        let slots_in_memory = std::cmp::max(config.slots_in_memory, config.slot_sampling);
+        // The canonical block for `prev_slot` has been compared, so its distances are complete.
+        if let Some(output) = &mut json_output {
+            output.write_through(prev_slot);
+        }
+
+        all_blocks.retain(|stored_slot, _| *stored_slot + slots_in_memory >= slot);
+        canonical_roots.retain(|stored_slot, _| *stored_slot + slots_in_memory >= slot);
+        // Distances are only kept for the rolling mean, for as long as they're in its window.
+        let distance_window = config.distance_window.unwrap_or(0);
+        canonical_distances.retain(|stored_slot, _| *stored_slot + distance_window > prev_slot);
+    }
+
+    if let Some(sink) = &mut parquet_sink {
+        if let Err(e) = sink.flush() {
+            error!("{e}");
+        }
+    }
+    if let Some(output) = &mut json_output {
+        output.write_all();
     }
 
     Ok(())
 }
+
+/// Replace the previously discovered nodes with the `discovered` ones, logging any changes.
+///
+/// Nodes from the config file (`static_names`) are never added or removed.
+async fn reconcile_discovered_nodes(
+    nodes: &mut Vec<Node>,
+    labels: &mut HashMap<String, String>,
+    static_names: &HashSet<String>,
+    metrics: &Metrics,
+    discovered: Vec<NodeConfig>,
+    new_node: impl Fn(Arc<NodeConfig>) -> Result<Node, String>,
+) {
+    let discovered = discovered
+        .into_iter()
+        .filter(|config| !static_names.contains(&config.name))
+        .map(|config| (config.name.clone(), config))
+        .collect::<BTreeMap<_, _>>();
+
+    // Remove nodes that have gone away or changed.
+    nodes.retain(|node| {
+        let name = &node.config.name;
+        if static_names.contains(name) {
+            return true;
+        }
+        let keep = discovered.get(name).is_some_and(|config| {
+            config.url == node.config.url && config.label == node.config.label
+        });
+        if !keep {
+            info!(node = name, "Removed discovered node");
+            labels.remove(name);
+            metrics.remove_node(&node_labels(node));
+        }
+        keep
+    });
+
+    for (name, config) in discovered {
+        if nodes.iter().any(|node| node.config.name == name) {
+            continue;
+        }
+        let mut node = match new_node(Arc::new(config)) {
+            Ok(node) => node,
+            Err(e) => {
+                warn!(node = name, "Unable to add discovered node: {e}");
+                continue;
+            }
+        };
+        if let Err(e) = node.load_version().await {
+            warn!(node = name, "{e}");
+        }
+        info!(
+            node = name,
+            "Added discovered node with label {} at {}, version: {}",
+            node.config.label,
+            node.config.url,
+            node.version.as_deref().unwrap_or("unknown")
+        );
+        labels.insert(name, node.config.label.clone());
+        nodes.push(node);
+    }
+}
+
+/// Root of the canonical block at `slot`, or `None` if the slot is empty.
+async fn get_canonical_root(
+    canonical_bn: &BeaconNodeHttpClient,
+    slot: Slot,
+) -> Result<Option<Hash256>, String> {
+    canonical_bn
+        .get_beacon_headers_block_id(BlockId::Slot(slot))
+        .await
+        .map(|opt_res| opt_res.map(|res| res.data.root))
+        .map_err(|e| format!("{e:?}"))
+}
+
+/// Log the distance between the canonical `block` at `slot` and its parent.
+///
+/// The parent is taken from `last_block` if it matches, and fetched by root otherwise.
+async fn log_canonical_churn<E: EthSpec>(
+    canonical_bn: &BeaconNodeHttpClient,
+    slot: Slot,
+    block: &BlindedBeaconBlock<E>,
+    last_block: Option<&(Hash256, BlindedBeaconBlock<E>)>,
+    params: &DistanceParams,
+) {
+    let parent_root = block.parent_root();
+    let fetched_parent: BlindedBeaconBlock<E>;
+    let parent = match last_block {
+        Some((root, last_block)) if *root == parent_root => last_block,
+        _ => match canonical_bn
+            .get_beacon_blocks::<E>(BlockId::Root(parent_root))
+            .await
+        {
+            Ok(Some(res)) => {
+                let (full_parent, _) = res.data.deconstruct();
+                let (parent, _) = full_parent.into();
+                fetched_parent = parent;
+                &fetched_parent
+            }
+            Ok(None) => {
+                warn!(%slot, "parent block {parent_root:?} not found for canonical churn");
+                return;
+            }
+            Err(e) => {
+                warn!(%slot, "unable to fetch parent block for canonical churn: {e:?}");
+                return;
+            }
+        },
+    };
+    let (block, parent) = (
+        canonicalized_block(block, params),
+        canonicalized_block(parent, params),
+    );
+    match block.distance(&parent, params) {
+        Some(distance) => info!(
+            %slot,
+            distance,
+            "canonical churn (distance to parent at slot {}): {}",
+            parent.slot(),
+            distance
+        ),
+        None => info!(%slot, "canonical block is incomparable to its parent"),
+    }
+}
+
+/// Check that the genesis time from the genesis state is plausible for `spec`.
+fn check_genesis_time(genesis_time: u64, spec: &ChainSpec) -> Result<u64, String> {
+    if genesis_time == 0 || genesis_time < spec.min_genesis_time {
+        return Err(format!(
+            "invalid genesis time {genesis_time}, expected at least {}",
+            spec.min_genesis_time
+        ));
+    }
+    Ok(genesis_time)
+}
+
+/// The `dream_blocks` from the same fork as the canonical `block`, logging any that aren't.
+fn same_fork_blocks<'a>(
+    slot: Slot,
+    dream_blocks: &'a HashMap<String, BlindedBeaconBlock<E>>,
+    block: &BlindedBeaconBlock<E>,
+    spec: &ChainSpec,
+) -> Cow<'a, HashMap<String, BlindedBeaconBlock<E>>> {
+    let mismatched = dream_blocks
+        .iter()
+        .filter_map(|(name, dream_block)| {
+            let (fork, canonical_fork) = fork_mismatch(dream_block, block, spec)?;
+            info!(
+                %slot,
+                node = name,
+                "canonical fork mismatch ({fork} vs {canonical_fork}), skipping comparison"
+            );
+            Some(name)
+        })
+        .collect::<HashSet<_>>();
+    if mismatched.is_empty() {
+        return Cow::Borrowed(dream_blocks);
+    }
+    Cow::Owned(
+        dream_blocks
+            .iter()
+            .filter(|(name, _)| !mismatched.contains(name))
+            .map(|(name, dream_block)| (name.clone(), dream_block.clone()))
+            .collect(),
+    )
+}
+
+/// Group the `dream_blocks` with identical roots into single entries named `name1+name2`.
+///
+/// Returns the entries along with `labels` extended with the label of each merged entry, which is
+/// the distinct labels of its members joined by `/`, and the names of the members of each entry.
+/// Members are returned explicitly because node names may themselves contain `+`.
+fn collapse_identical_blocks<'a>(
+    slot: Slot,
+    dream_blocks: &'a HashMap<String, BlindedBeaconBlock<E>>,
+    labels: &HashMap<String, String>,
+) -> (
+    Vec<(String, &'a BlindedBeaconBlock<E>)>,
+    HashMap<String, String>,
+    HashMap<String, Vec<&'a str>>,
+) {
+    let mut groups = HashMap::<_, Vec<_>>::new();
+    for (name, dream_block) in dream_blocks {
+        groups
+            .entry(dream_block.canonical_root())
+            .or_default()
+            .push((name.as_str(), dream_block));
+    }
+
+    let mut entry_labels = labels.clone();
+    let mut entry_members = HashMap::new();
+    let entries = groups
+        .into_values()
+        .map(|mut members| {
+            members.sort_unstable_by_key(|(name, _)| *name);
+            let dream_block = members[0].1;
+            let member_names = members.iter().map(|(name, _)| *name).collect::<Vec<_>>();
+            if members.len() == 1 {
+                entry_members.insert(members[0].0.to_string(), member_names);
+                return (members[0].0.to_string(), dream_block);
+            }
+
+            let name = member_names.join("+");
+            let label = member_names
+                .iter()
+                .filter_map(|name| labels.get(*name))
+                .unique()
+                .join("/");
+            info!(%slot, "collapsing identical blocks into {name} ({label})");
+            entry_labels.insert(name.clone(), label);
+            entry_members.insert(name.clone(), member_names);
+            (name, dream_block)
+        })
+        .collect();
+    (entries, entry_labels, entry_members)
+}
+
+/// Message from a caught panic payload, if it has one.
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = panic.downcast_ref::<String>() {
+        message
+    } else {
+        "<unknown panic>"
+    }
+}
+
+/// Log suffix with `distance` normalized by `max_distance`, empty if the distance is unbounded.
+fn normalized_suffix(distance: usize, max_distance: Option<usize>) -> String {
+    max_distance
+        .map(|max_distance| {
+            format!(
+                " (normalized {:.3})",
+                normalize_distance(distance, max_distance)
+            )
+        })
+        .unwrap_or_default()
+}
+
+/// Log suffix with the number of differing sync committee bits, empty prior to Altair.
+fn sync_suffix(sync_bits: Option<usize>) -> String {
+    sync_bits
+        .map(|sync_bits| format!(", sync committee bits differing: {sync_bits}"))
+        .unwrap_or_default()
+}
+
+/// Fee recipient of the block's execution payload, or `None` prior to Bellatrix.
+fn fee_recipient(block: &BlindedBeaconBlock<E>) -> Option<Address> {
+    block
+        .body()
+        .execution_payload()
+        .ok()
+        .map(|payload| payload.fee_recipient())
+}
+
+/// Log the value difference (MEV uplift) and distance between a node's locally built block and the
+/// block it produced when preferring the builder.
+fn log_builder_comparison(
+    slot: Slot,
+    name: &str,
+    (local_block, local_metadata): (&BlindedBeaconBlock<E>, Option<&ProduceBlockV3Metadata>),
+    (builder_block, builder_metadata): (&BlindedBeaconBlock<E>, Option<&ProduceBlockV3Metadata>),
+    params: &DistanceParams,
+) {
+    let (builder_block, local_block) = (
+        canonicalized_block(builder_block, params),
+        canonicalized_block(local_block, params),
+    );
+    let Some(distance) = builder_block.distance(&local_block, params) else {
+        warn!(%slot, node = name, "builder and local blocks are for different forks");
+        return;
+    };
+    let (Some(local), Some(builder)) = (local_metadata, builder_metadata) else {
+        info!(%slot, node = name, distance, "builder <=> local distance: {distance}");
+        return;
+    };
+    if !builder.execution_payload_blinded {
+        info!(%slot, node = name, "built its payload locally despite preferring the builder");
+    }
+
+    let local_value = local
+        .execution_payload_value
+        .saturating_add(local.consensus_block_value);
+    let builder_value = builder
+        .execution_payload_value
+        .saturating_add(builder.consensus_block_value);
+    let uplift = if builder_value >= local_value {
+        format!("+{}", builder_value - local_value)
+    } else {
+        format!("-{}", local_value - builder_value)
+    };
+    info!(
+        %slot,
+        node = name,
+        distance,
+        "MEV uplift: {uplift} wei, builder <=> local distance: {distance}"
+    );
+}
+
+/// Distances from `block` to each of the `dream_blocks`, sorted in ascending order (then by name).
+fn sorted_distances<'a>(
+    dream_blocks: &'a HashMap<String, BlindedBeaconBlock<E>>,
+    block: &BlindedBeaconBlock<E>,
+    params: &DistanceParams,
+) -> Vec<(&'a str, usize)> {
+    let mut distances = dream_blocks
+        .par_iter()
+        .map(|(name, dream_block)| {
+            let distance = dream_block.distance(block, params).unwrap();
+            (name.as_str(), distance)
+        })
+        .collect::<Vec<_>>();
+    distances.sort_unstable_by_key(|(name, distance)| (*distance, *name));
+    distances
+}
@@ -0,0 +1,123 @@
+//! Maintenance windows during which dream blocks are not posted, e.g. while a collector is down.
+//!
+//! Windows are written as strings, all in UTC:
+//!
+//! - `2024-03-05T02:00:00Z/2024-03-05T04:00:00Z`: a one-off window between two RFC 3339 times.
+//! - `02:00-02:30`: a window every day.
+//! - `Tue,Thu 23:30-00:30`: a window on the given days, which spans midnight if it ends before it
+//!   starts. Such a window belongs to the day it starts on.
+use chrono::{DateTime, Datelike, NaiveTime, Utc, Weekday};
+use serde::Deserialize;
+use std::fmt;
+
+/// A maintenance window, deserialized from its source string.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct MaintenanceWindow {
+    source: String,
+    kind: WindowKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum WindowKind {
+    Once {
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    },
+    Recurring {
+        /// Days on which the window starts, empty for every day.
+        days: Vec<Weekday>,
+        start: NaiveTime,
+        end: NaiveTime,
+    },
+}
+
+impl MaintenanceWindow {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let kind = if let Some((start, end)) = source.split_once('/') {
+            let parse_time = |time: &str| {
+                DateTime::parse_from_rfc3339(time.trim())
+                    .map(|time| time.with_timezone(&Utc))
+                    .map_err(|e| format!("invalid time {time:?} in maintenance window: {e}"))
+            };
+            let (start, end) = (parse_time(start)?, parse_time(end)?);
+            if start >= end {
+                return Err(format!(
+                    "maintenance window {source:?} ends before it starts"
+                ));
+            }
+            WindowKind::Once { start, end }
+        } else {
+            let (days, times) = match source.trim().split_once(char::is_whitespace) {
+                Some((days, times)) => (parse_days(days)?, times.trim()),
+                None => (vec![], source.trim()),
+            };
+            let (start, end) = times
+                .split_once('-')
+                .ok_or_else(|| format!("expected HH:MM-HH:MM in maintenance window {source:?}"))?;
+            let (start, end) = (parse_time_of_day(start)?, parse_time_of_day(end)?);
+            if start == end {
+                return Err(format!("maintenance window {source:?} is empty"));
+            }
+            WindowKind::Recurring { days, start, end }
+        };
+        Ok(Self {
+            source: source.to_string(),
+            kind,
+        })
+    }
+
+    /// Whether `now` falls within the window, including its start but not its end.
+    pub fn contains(&self, now: DateTime<Utc>) -> bool {
+        match &self.kind {
+            WindowKind::Once { start, end } => *start <= now && now < *end,
+            WindowKind::Recurring { days, start, end } => {
+                let starts_on = |day: Weekday| days.is_empty() || days.contains(&day);
+                let time = now.time();
+                if start < end {
+                    starts_on(now.weekday()) && *start <= time && time < *end
+                } else {
+                    (starts_on(now.weekday()) && *start <= time)
+                        || (starts_on(now.weekday().pred()) && time < *end)
+                }
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for MaintenanceWindow {
+    type Error = String;
+
+    fn try_from(source: String) -> Result<Self, String> {
+        Self::parse(&source)
+    }
+}
+
+impl fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// The first of `windows` that contains `now`, if any.
+pub fn active_window(
+    windows: &[MaintenanceWindow],
+    now: DateTime<Utc>,
+) -> Option<&MaintenanceWindow> {
+    windows.iter().find(|window| window.contains(now))
+}
+
+fn parse_days(days: &str) -> Result<Vec<Weekday>, String> {
+    days.split(',')
+        .map(|day| {
+            day.trim()
+                .parse()
+                .map_err(|_| format!("invalid day {day:?} in maintenance window"))
+        })
+        .collect()
+}
+
+fn parse_time_of_day(time: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(time.trim(), "%H:%M")
+        .map_err(|e| format!("invalid time {time:?} in maintenance window: {e}"))
+}
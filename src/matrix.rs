@@ -0,0 +1,108 @@
+use crate::classify::Classification;
+use eth2::types::Slot;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Pairwise distances between the blocks of all nodes for a single slot.
+#[derive(Debug, Clone, Serialize)]
+pub struct DistanceMatrix {
+    pub slot: Slot,
+    /// Seconds since the UNIX epoch at which the matrix was computed.
+    pub timestamp: u64,
+    pub labels: BTreeMap<String, String>,
+    /// Distance between each pair of nodes, keyed by both names in either order.
+    pub distances: BTreeMap<String, BTreeMap<String, usize>>,
+    /// Most recent classification of a canonical block, usually for an earlier slot.
+    pub verdict: Option<VerdictSummary>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VerdictSummary {
+    pub slot: Slot,
+    pub verdict: &'static str,
+    pub label: Option<String>,
+    pub confidence: f64,
+    /// Distance from each node's block to the canonical block.
+    pub distances: BTreeMap<String, usize>,
+    /// Client version of each compared node, where known.
+    pub versions: BTreeMap<String, String>,
+}
+
+impl VerdictSummary {
+    pub fn new(
+        slot: Slot,
+        classification: &Classification,
+        distances: &[(&str, usize)],
+        versions: &BTreeMap<&str, &str>,
+    ) -> Self {
+        Self {
+            slot,
+            verdict: classification.verdict.kind(),
+            label: classification.verdict.label().map(str::to_string),
+            confidence: classification.confidence,
+            distances: distances
+                .iter()
+                .map(|(name, distance)| (name.to_string(), *distance))
+                .collect(),
+            versions: versions
+                .iter()
+                .map(|(name, version)| (name.to_string(), version.to_string()))
+                .collect(),
+        }
+    }
+}
+
+impl DistanceMatrix {
+    pub fn new(
+        slot: Slot,
+        labels: &HashMap<String, String>,
+        pair_distances: &[(&String, &String, usize)],
+        verdict: Option<VerdictSummary>,
+    ) -> Self {
+        let mut distances = BTreeMap::<String, BTreeMap<String, usize>>::new();
+        for (name1, name2, distance) in pair_distances {
+            distances
+                .entry(name1.to_string())
+                .or_default()
+                .insert(name2.to_string(), *distance);
+            distances
+                .entry(name2.to_string())
+                .or_default()
+                .insert(name1.to_string(), *distance);
+        }
+        Self {
+            slot,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |now| now.as_secs()),
+            labels: labels
+                .iter()
+                .map(|(name, label)| (name.clone(), label.clone()))
+                .collect(),
+            distances,
+            verdict,
+        }
+    }
+}
+
+/// The most recently computed distance matrix, shared with the HTTP server.
+#[derive(Debug, Default)]
+pub struct LatestMatrix {
+    matrix: RwLock<Option<DistanceMatrix>>,
+}
+
+impl LatestMatrix {
+    pub fn set(&self, matrix: DistanceMatrix) {
+        *self.matrix.write().unwrap_or_else(|e| e.into_inner()) = Some(matrix);
+    }
+
+    /// Get a copy of the latest matrix, or `None` if no slot has been processed yet.
+    pub fn get(&self) -> Option<DistanceMatrix> {
+        self.matrix
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+    }
+}
@@ -0,0 +1,184 @@
+//! Prometheus metrics, served at `/metrics` by the HTTP server.
+//!
+//! Per-node metrics are labelled with the node's name, client label and client version, so that
+//! they can be aggregated by client family or version.
+use crate::classify::{Classification, Verdict};
+use crate::node::Node;
+use prometheus::{
+    Encoder, Gauge, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry,
+    TextEncoder,
+};
+use std::time::Duration;
+
+/// Label names for per-node metrics, in the order returned by `node_labels`.
+const NODE_LABEL_NAMES: [&str; 3] = ["node", "client", "version"];
+
+/// Buckets for block request latency in seconds, past the default request timeout of 6s to cover
+/// longer configured timeouts and retries within the slot.
+const REQUEST_SECONDS_BUCKETS: [f64; 11] =
+    [0.1, 0.25, 0.5, 0.75, 1.0, 1.5, 2.0, 4.0, 6.0, 8.0, 12.0];
+
+/// Label names for block request counts: the node labels plus the outcome.
+const BLOCK_REQUEST_LABEL_NAMES: [&str; 4] = ["node", "client", "version", "outcome"];
+
+pub struct Metrics {
+    registry: Registry,
+    block_request_seconds: HistogramVec,
+    block_requests: IntCounterVec,
+    canonical_distance: IntGaugeVec,
+    divergence: Gauge,
+    classifications: IntCounterVec,
+    posts: IntCounterVec,
+}
+
+/// Label values for a node's metrics: its name, client label and version.
+pub fn node_labels(node: &Node) -> [&str; 3] {
+    [
+        &node.config.name,
+        &node.config.label,
+        node.version.as_deref().unwrap_or("unknown"),
+    ]
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, String> {
+        let registry = Registry::new();
+        let block_request_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "blockdreamer_block_request_seconds",
+                "Time taken by each node to return a block",
+            )
+            .buckets(REQUEST_SECONDS_BUCKETS.to_vec()),
+            &NODE_LABEL_NAMES,
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+        let block_requests = IntCounterVec::new(
+            Opts::new(
+                "blockdreamer_block_requests_total",
+                "Block requests to each node by outcome (success or failure)",
+            ),
+            &BLOCK_REQUEST_LABEL_NAMES,
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+        let canonical_distance = IntGaugeVec::new(
+            Opts::new(
+                "blockdreamer_canonical_distance",
+                "Distance from each node's block to the canonical block at the last compared slot",
+            ),
+            &NODE_LABEL_NAMES,
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+        let divergence = Gauge::new(
+            "blockdreamer_divergence",
+            "Aggregated pairwise distance between all nodes at the last compared slot",
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+        let classifications = IntCounterVec::new(
+            Opts::new(
+                "blockdreamer_classifications_total",
+                "Classifications of canonical blocks by verdict and client label",
+            ),
+            &["verdict", "label"],
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+        let posts = IntCounterVec::new(
+            Opts::new(
+                "blockdreamer_posts_total",
+                "Posts of dream blocks to each endpoint by outcome (success, failure, skipped or dropped)",
+            ),
+            &["endpoint", "outcome"],
+        )
+        .map_err(|e| format!("invalid metric: {e}"))?;
+
+        registry
+            .register(Box::new(block_request_seconds.clone()))
+            .and_then(|()| registry.register(Box::new(block_requests.clone())))
+            .and_then(|()| registry.register(Box::new(canonical_distance.clone())))
+            .and_then(|()| registry.register(Box::new(divergence.clone())))
+            .and_then(|()| registry.register(Box::new(classifications.clone())))
+            .and_then(|()| registry.register(Box::new(posts.clone())))
+            .map_err(|e| format!("unable to register metrics: {e}"))?;
+
+        Ok(Self {
+            registry,
+            block_request_seconds,
+            block_requests,
+            canonical_distance,
+            divergence,
+            classifications,
+            posts,
+        })
+    }
+
+    pub fn observe_block_request(&self, labels: &[&str; 3], duration: Duration) {
+        self.block_request_seconds
+            .with_label_values(labels)
+            .observe(duration.as_secs_f64());
+    }
+
+    /// Count a block request to a node, including any retries, as a success or failure.
+    pub fn record_block_request(&self, labels: &[&str; 3], success: bool) {
+        let [node, client, version] = *labels;
+        self.block_requests
+            .with_label_values(&[node, client, version, outcome(success)])
+            .inc();
+    }
+
+    pub fn set_canonical_distance(&self, labels: &[&str; 3], distance: usize) {
+        self.canonical_distance
+            .with_label_values(labels)
+            .set(distance as i64);
+    }
+
+    pub fn set_divergence(&self, divergence: f64) {
+        self.divergence.set(divergence);
+    }
+
+    /// Count the classification of a canonical block, labelled with the verdict and the client
+    /// label it was attributed to (empty if too close to call).
+    pub fn record_classification(&self, classification: &Classification) {
+        let (verdict, label) = match &classification.verdict {
+            Verdict::TwoClosestMatch { label, .. } => ("two_closest_match", label.as_str()),
+            Verdict::SignificantlyCloser { label, .. } => ("significantly_closer", label.as_str()),
+            Verdict::TooCloseToCall { .. } => ("too_close_to_call", ""),
+        };
+        self.classifications
+            .with_label_values(&[verdict, label])
+            .inc();
+    }
+
+    /// Count a post of the dream blocks for a slot to `endpoint`, or its skipping.
+    pub fn record_post(&self, endpoint: &str, outcome: &str) {
+        self.posts.with_label_values(&[endpoint, outcome]).inc();
+    }
+
+    /// Remove the metrics of a node that is no longer running.
+    pub fn remove_node(&self, labels: &[&str; 3]) {
+        // Errors just mean the node never had a value recorded.
+        let _ = self.block_request_seconds.remove_label_values(labels);
+        let _ = self.canonical_distance.remove_label_values(labels);
+        let [node, client, version] = *labels;
+        for success in [true, false] {
+            let _ =
+                self.block_requests
+                    .remove_label_values(&[node, client, version, outcome(success)]);
+        }
+    }
+
+    /// Encode all metrics in the Prometheus text format.
+    pub fn encode(&self) -> Result<String, String> {
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|e| format!("unable to encode metrics: {e}"))?;
+        String::from_utf8(buffer).map_err(|e| format!("unable to encode metrics: {e}"))
+    }
+}
+
+fn outcome(success: bool) -> &'static str {
+    if success {
+        "success"
+    } else {
+        "failure"
+    }
+}
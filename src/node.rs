@@ -1,42 +1,360 @@
-use crate::config::Node as NodeConfig;
+use crate::backoff::BackoffPolicy;
+use crate::clock;
+use crate::config::{
+    node_url, AdaptiveTimeoutConfig, DistinctBlocks, FutureSlotHandling, Node as NodeConfig,
+};
+use crate::debug_dump::DebugDumps;
+use crate::stale_block::log_repeated_roots;
 use eth2::{
     types::{
-        BlindedBeaconBlock, ChainSpec, EthSpec, FullBlockContents, ProduceBlockV3Metadata,
-        ProduceBlockV3Response, Signature, SignatureBytes, SkipRandaoVerification, Slot,
+        BlindedBeaconBlock, BlockId, ChainSpec, EthSpec, FullBlockContents, KzgCommitment,
+        ProduceBlockV3Metadata, ProduceBlockV3Response, Signature, SignatureBytes,
+        SkipRandaoVerification, Slot,
     },
     BeaconNodeHttpClient, Timeouts,
 };
+use futures::future::join_all;
+use reqwest::header::{HeaderMap, ACCEPT, CONTENT_TYPE};
 use sensitive_url::SensitiveUrl;
-use std::sync::Arc;
+use slot_clock::SlotClock;
+use ssz::Encode;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc,
+};
 use std::time::Duration;
+use tracing::{info, warn};
+
+/// Default timeout for block production requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(6);
+
+/// Builder boost factor that always prefers the builder's payload when one is available.
+pub const PREFER_BUILDER_BOOST_FACTOR: u64 = u64::MAX;
+
+/// Media type of SSZ request and response bodies in the beacon API.
+const SSZ_CONTENT_TYPE: &str = "application/octet-stream";
 
 #[derive(Clone)]
 pub struct Node {
     pub config: Arc<NodeConfig>,
     pub client: BeaconNodeHttpClient,
+    /// Client for block production requests, with the node's `query_params` appended.
+    block_client: BeaconNodeHttpClient,
+    /// HTTP client and timeout of `block_client`, for requests it can't make itself.
+    http_client: reqwest::Client,
+    http_timeout: Duration,
     pub spec: Arc<ChainSpec>,
+    pub debug_dumps: Option<Arc<DebugDumps>>,
+    /// Client version reported by the node at startup, if it could be fetched.
+    pub version: Option<String>,
+    pub adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    /// Moving average of the SSZ size of recent blocks in bytes (0 if unknown).
+    block_size_estimate: Arc<AtomicU64>,
+    /// Number of blocks returned for a later slot than requested, a sign of a fast clock.
+    future_slot_blocks: Arc<AtomicU64>,
+    /// Whether the node turned out not to support block v3, so v2 is used instead.
+    v3_unsupported: Arc<AtomicBool>,
+}
+
+/// Timeout for block requests to a node without an adaptive timeout.
+fn fixed_timeout(config: &NodeConfig) -> Duration {
+    config
+        .timeout_secs
+        .map_or(REQUEST_TIMEOUT, Duration::from_secs)
+}
+
+/// Append `query_params` to the query of `url`.
+///
+/// `BeaconNodeHttpClient` keeps the query of its base URL when building request URLs, and adds
+/// its own parameters after it.
+pub fn with_query_params(
+    url: &SensitiveUrl,
+    query_params: &BTreeMap<String, String>,
+) -> Result<SensitiveUrl, String> {
+    if query_params.is_empty() {
+        return Ok(url.clone());
+    }
+    let mut full = url.full.clone();
+    full.query_pairs_mut().extend_pairs(query_params);
+    SensitiveUrl::parse(full.as_str()).map_err(|e| format!("Invalid URL: {:?}", e))
+}
+
+/// Check that the response to a request for SSZ has an SSZ body, according to `headers`.
+///
+/// Some nodes ignore the `Accept` header and respond with JSON, which would otherwise only show
+/// up as an obscure decoding error. A missing `Content-Type` is let through. The error counts as
+/// an SSZ decoding error, so it's retried over JSON with `ssz_fallback`.
+pub fn check_ssz_content_type(headers: &HeaderMap) -> Result<(), eth2::Error> {
+    let Some(content_type) = headers.get(CONTENT_TYPE) else {
+        return Ok(());
+    };
+    let content_type = content_type.to_str().unwrap_or_default();
+    if content_type.starts_with(SSZ_CONTENT_TYPE) {
+        return Ok(());
+    }
+    Err(eth2::Error::InvalidSsz(ssz::DecodeError::BytesInvalid(
+        format!("requested SSZ but the node responded with Content-Type {content_type:?}"),
+    )))
+}
+
+/// Whether `error` means that the requested endpoint doesn't exist on the node.
+fn is_endpoint_not_found(error: &eth2::Error) -> bool {
+    matches!(
+        error.status().map(|status| status.as_u16()),
+        Some(404 | 405)
+    )
+}
+
+/// Error for a block from a later slot than the requested `slot`, or `None` if it isn't.
+pub fn future_slot_error<E: EthSpec>(slot: Slot, block: &BlindedBeaconBlock<E>) -> Option<String> {
+    (block.slot() > slot).then(|| {
+        format!(
+            "node returned future slot {} (requested {})",
+            block.slot(),
+            slot
+        )
+    })
+}
+
+/// A block returned by a node, with its metadata if requested from the v3 API and a summary of
+/// its blobs if returned in full.
+pub type BlockResponse<E> = (
+    BlindedBeaconBlock<E>,
+    Option<ProduceBlockV3Metadata>,
+    Option<BlobSummary>,
+);
+
+/// A block returned by the v3 API, which always comes with metadata.
+pub type V3BlockResponse<E> = (
+    BlindedBeaconBlock<E>,
+    ProduceBlockV3Metadata,
+    Option<BlobSummary>,
+);
+
+/// Summary of the blobs returned alongside a full block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobSummary {
+    /// Number of blobs returned.
+    pub count: usize,
+    /// KZG commitments of the block, copied from its body.
+    pub kzg_commitments: Vec<KzgCommitment>,
+}
+
+impl BlobSummary {
+    /// Summary of the blobs in `block_contents`, or `None` prior to Deneb.
+    pub fn new<E: EthSpec>(block_contents: &FullBlockContents<E>) -> Option<Self> {
+        match block_contents {
+            FullBlockContents::BlockContents(contents) => Some(Self {
+                count: contents.blobs.len(),
+                kzg_commitments: contents
+                    .block
+                    .body()
+                    .blob_kzg_commitments()
+                    .map_or_else(|_| vec![], |commitments| commitments.to_vec()),
+            }),
+            FullBlockContents::Block(_) => None,
+        }
+    }
+}
+
+/// Ways in which `block` differs from the unsigned proposal requested for `slot` with
+/// `randao_reveal`.
+pub fn unsigned_proposal_anomalies<E: EthSpec>(
+    spec: &ChainSpec,
+    slot: Slot,
+    randao_reveal: &SignatureBytes,
+    block: &BlindedBeaconBlock<E>,
+    metadata: Option<&ProduceBlockV3Metadata>,
+) -> Vec<String> {
+    let mut anomalies = vec![];
+    if block.slot() != slot {
+        anomalies.push(format!("block is for slot {}", block.slot()));
+    }
+    let expected_fork = spec.fork_name_at_slot::<E>(slot);
+    if let Err(e) = block.fork_name(spec) {
+        anomalies.push(format!("block structure doesn't match its fork: {e:?}"));
+    }
+    if let Some(metadata) = metadata {
+        if metadata.consensus_version != expected_fork {
+            anomalies.push(format!(
+                "consensus version is {} instead of {}",
+                metadata.consensus_version, expected_fork
+            ));
+        }
+    }
+    if SignatureBytes::from(block.body().randao_reveal().clone()) != *randao_reveal {
+        anomalies.push("randao_reveal is not the one requested".to_string());
+    }
+    anomalies
+}
+
+/// RANDAO reveal of the canonical block at `slot`, if it has been published.
+///
+/// Fetched once per slot and shared by all nodes, so that their blocks are built on the same
+/// reveal.
+pub async fn canonical_randao_reveal<E: EthSpec>(
+    canonical_bn: &BeaconNodeHttpClient,
+    slot: Slot,
+    timeout: Duration,
+) -> Option<SignatureBytes> {
+    let request = canonical_bn.get_beacon_blocks::<E>(BlockId::Slot(slot));
+    match tokio::time::timeout(timeout, request).await {
+        Ok(Ok(Some(response))) => Some(
+            response
+                .data
+                .message()
+                .body()
+                .randao_reveal()
+                .clone()
+                .into(),
+        ),
+        // Not published yet, or the slot was skipped.
+        Ok(Ok(None)) => {
+            info!(
+                %slot,
+                "canonical block not yet available, using infinity RANDAO reveal"
+            );
+            None
+        }
+        Ok(Err(e)) => {
+            warn!(
+                %slot,
+                "unable to fetch canonical RANDAO reveal, using infinity: {:?}", e
+            );
+            None
+        }
+        Err(_) => {
+            warn!(
+                %slot,
+                "timed out fetching canonical RANDAO reveal after {}ms, using infinity",
+                timeout.as_millis()
+            );
+            None
+        }
+    }
 }
 
 impl Node {
-    pub fn new(config: Arc<NodeConfig>, spec: Arc<ChainSpec>) -> Result<Self, String> {
-        let url = SensitiveUrl::parse(&config.url).map_err(|e| format!("Invalid URL: {:?}", e))?;
-        let client = BeaconNodeHttpClient::new(url, Timeouts::set_all(Duration::from_secs(6)));
+    pub fn new(
+        config: Arc<NodeConfig>,
+        spec: Arc<ChainSpec>,
+        debug_dumps: Option<Arc<DebugDumps>>,
+        adaptive_timeout: Option<AdaptiveTimeoutConfig>,
+    ) -> Result<Self, String> {
+        let url = node_url(&config.url, config.path_prefix.as_deref())?;
+        let http_client = reqwest::Client::builder()
+            .default_headers(config.header_map()?)
+            .build()
+            .map_err(|e| format!("Unable to build HTTP client for {}: {}", config.name, e))?;
+        // The HTTP client's timeout must not cut off requests before `request_timeout`.
+        let timeout = std::cmp::max(
+            fixed_timeout(&config),
+            adaptive_timeout.map_or(Duration::ZERO, |adaptive| {
+                Duration::from_millis(adaptive.max_ms)
+            }),
+        );
+        let block_url = with_query_params(&url, &config.query_params)?;
+        let block_client = BeaconNodeHttpClient::from_components(
+            block_url,
+            http_client.clone(),
+            Timeouts::set_all(timeout),
+        );
+        let client = BeaconNodeHttpClient::from_components(
+            url,
+            http_client.clone(),
+            Timeouts::set_all(timeout),
+        );
         Ok(Self {
             config,
             client,
+            block_client,
+            http_client,
+            http_timeout: timeout,
             spec,
+            debug_dumps,
+            version: None,
+            adaptive_timeout,
+            block_size_estimate: Arc::new(AtomicU64::new(0)),
+            future_slot_blocks: Arc::new(AtomicU64::new(0)),
+            v3_unsupported: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Timeout for a block request, based on the size of recent blocks if configured.
+    pub fn request_timeout(&self) -> Duration {
+        let Some(adaptive) = self.adaptive_timeout else {
+            return fixed_timeout(&self.config);
+        };
+        let size_kib = self.block_size_estimate.load(Ordering::Relaxed) as f64 / 1024.0;
+        let timeout_ms = adaptive.base_ms as f64 + adaptive.ms_per_kib * size_kib;
+        Duration::from_millis(std::cmp::min(timeout_ms as u64, adaptive.max_ms))
+    }
+
+    /// Update the block size estimate with a newly received block of `size` bytes.
+    pub fn record_block_size(&self, size: usize) {
+        let size = size as u64;
+        let estimate = self.block_size_estimate.load(Ordering::Relaxed);
+        let new_estimate = if estimate == 0 {
+            size
+        } else {
+            estimate - estimate / 4 + size / 4
+        };
+        self.block_size_estimate
+            .store(new_estimate, Ordering::Relaxed);
+    }
+
+    /// Raise the block size estimate after a request timed out, so that the next request is
+    /// allowed `max_ms`. Later blocks bring the estimate back down gradually.
+    pub fn record_timeout(&self) {
+        let Some(adaptive) = self.adaptive_timeout else {
+            return;
+        };
+        if adaptive.ms_per_kib > 0.0 {
+            let max_size_kib =
+                adaptive.max_ms.saturating_sub(adaptive.base_ms) as f64 / adaptive.ms_per_kib;
+            let max_size = (max_size_kib * 1024.0).ceil() as u64;
+            self.block_size_estimate
+                .fetch_max(max_size, Ordering::Relaxed);
+        }
+    }
+
+    /// Fetch the node's client version, which also checks that the node is reachable.
+    pub async fn load_version(&mut self) -> Result<(), String> {
+        let response = self
+            .client
+            .get_node_version()
+            .await
+            .map_err(|e| format!("Unable to fetch version of {}: {:?}", self.config.name, e))?;
+        self.version = Some(response.data.version);
+        Ok(())
+    }
+
+    /// Whether blocks are requested from this node using the v3 API.
+    pub fn uses_v3(&self) -> bool {
+        self.config.v3 && !self.v3_unsupported.load(Ordering::Relaxed)
+    }
+
+    /// Name of the block production API method used for this node.
+    pub fn api_method(&self) -> &'static str {
+        match (self.uses_v3(), self.config.ssz) {
+            (true, true) => "v3 ssz",
+            (true, false) => "v3 json",
+            (false, true) => "v2 ssz",
+            (false, false) => "v2 json",
+        }
+    }
+
     pub async fn get_block_v3_json<E: EthSpec>(
         &self,
         slot: Slot,
         randao_reveal: &SignatureBytes,
         skip_randao_verification: SkipRandaoVerification,
         builder_boost_factor: Option<u64>,
-    ) -> Result<(BlindedBeaconBlock<E>, ProduceBlockV3Metadata), String> {
+    ) -> Result<V3BlockResponse<E>, eth2::Error> {
         let (response, metadata) = self
-            .client
+            .block_client
             .get_validator_blocks_v3_modular::<E>(
                 slot,
                 randao_reveal,
@@ -44,27 +362,35 @@ impl Node {
                 skip_randao_verification,
                 builder_boost_factor,
             )
-            .await
-            .map_err(|e| format!("Error fetching block from {}: {:?}", self.config.url, e))?;
+            .await?;
 
         match response.data {
             ProduceBlockV3Response::Full(block_contents) => {
-                // Throw away the blobs for now.
-                Ok((block_contents.block().to_ref().into(), metadata))
+                let blobs = BlobSummary::new(&block_contents);
+                Ok((block_contents.block().to_ref().into(), metadata, blobs))
             }
-            ProduceBlockV3Response::Blinded(block) => Ok((block, metadata)),
+            // Blobs aren't returned with blinded blocks.
+            ProduceBlockV3Response::Blinded(block) => Ok((block, metadata, None)),
         }
     }
 
+    /// Request a block from the v3 API over SSZ.
+    ///
+    /// The client sets the `Accept` header itself and decodes the body according to the
+    /// `Eth-Consensus-Version` header, so a JSON response shows up as an SSZ decoding error.
+    /// Unlike `get_block_v2_ssz`, the `Content-Type` can't be checked with
+    /// `check_ssz_content_type` because the client reads the headers and decodes the body
+    /// internally, and for the same reason undecodable responses can't be passed to the debug
+    /// dumps.
     pub async fn get_block_v3_ssz<E: EthSpec>(
         &self,
         slot: Slot,
         randao_reveal: &SignatureBytes,
         skip_randao_verification: SkipRandaoVerification,
         builder_boost_factor: Option<u64>,
-    ) -> Result<(BlindedBeaconBlock<E>, ProduceBlockV3Metadata), String> {
+    ) -> Result<V3BlockResponse<E>, eth2::Error> {
         let (response, metadata) = self
-            .client
+            .block_client
             .get_validator_blocks_v3_modular_ssz::<E>(
                 slot,
                 randao_reveal,
@@ -72,55 +398,144 @@ impl Node {
                 skip_randao_verification,
                 builder_boost_factor,
             )
-            .await
-            .map_err(|e| format!("Error fetching block from {}: {:?}", self.config.url, e))?;
+            .await?;
 
         match response {
             ProduceBlockV3Response::Full(block_contents) => {
-                // Throw away the blobs for now.
-                Ok((block_contents.block().to_ref().into(), metadata))
+                let blobs = BlobSummary::new(&block_contents);
+                Ok((block_contents.block().to_ref().into(), metadata, blobs))
             }
-            ProduceBlockV3Response::Blinded(block) => Ok((block, metadata)),
+            // Blobs aren't returned with blinded blocks.
+            ProduceBlockV3Response::Blinded(block) => Ok((block, metadata, None)),
         }
     }
 
-    pub async fn get_block<E: EthSpec>(
+    /// RANDAO reveal to request a block with, and whether the node should skip its verification.
+    ///
+    /// This is `slot_reveal` if one was fetched for the slot (see `canonical_randao_reveal`),
+    /// otherwise the infinity signature.
+    pub fn randao_reveal(
         &self,
-        slot: Slot,
-        builder_boost_factor: Option<u64>,
-    ) -> Result<(BlindedBeaconBlock<E>, Option<ProduceBlockV3Metadata>), String> {
-        let randao_reveal = Signature::infinity().unwrap().into();
+        slot_reveal: Option<&SignatureBytes>,
+    ) -> (SignatureBytes, SkipRandaoVerification) {
+        if let Some(randao_reveal) = slot_reveal {
+            return (randao_reveal.clone(), SkipRandaoVerification::No);
+        }
         let skip_randao_verification = if self.config.skip_randao_verification {
             SkipRandaoVerification::Yes
         } else {
             SkipRandaoVerification::No
         };
-        if self.config.v3 {
-            if self.config.ssz {
-                self.get_block_v3_ssz(
+        (
+            Signature::infinity().unwrap().into(),
+            skip_randao_verification,
+        )
+    }
+
+    pub async fn get_block<E: EthSpec>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        skip_randao_verification: SkipRandaoVerification,
+        builder_boost_factor: Option<u64>,
+    ) -> Result<BlockResponse<E>, String> {
+        if self.uses_v3() {
+            match self
+                .get_block_v3(
                     slot,
-                    &randao_reveal,
+                    randao_reveal,
                     skip_randao_verification,
                     builder_boost_factor,
                 )
                 .await
-            } else {
-                self.get_block_v3_json(
+            {
+                Ok((block, metadata, blobs)) => return Ok((block, Some(metadata), blobs)),
+                Err(e) if self.config.v3_fallback && is_endpoint_not_found(&e) => {
+                    if !self.v3_unsupported.swap(true, Ordering::Relaxed) {
+                        warn!(
+                            node = self.config.name,
+                            "block v3 is unsupported ({:?}), falling back to v2", e
+                        );
+                    }
+                }
+                Err(e) => {
+                    return Err(format!(
+                        "Error fetching block from {}: {:?}",
+                        self.config.url, e
+                    ))
+                }
+            }
+        }
+        self.get_block_v2(slot, randao_reveal, skip_randao_verification)
+            .await
+            .map_err(|e| format!("Error fetching block from {}: {:?}", self.config.url, e))
+    }
+
+    /// Request a block from the v3 API over SSZ or JSON, see `ssz_fallback`.
+    async fn get_block_v3<E: EthSpec>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        skip_randao_verification: SkipRandaoVerification,
+        builder_boost_factor: Option<u64>,
+    ) -> Result<V3BlockResponse<E>, eth2::Error> {
+        if self.config.ssz {
+            match self
+                .get_block_v3_ssz(
                     slot,
-                    &randao_reveal,
+                    randao_reveal,
                     skip_randao_verification,
                     builder_boost_factor,
                 )
                 .await
+            {
+                Err(e) if self.ssz_fallback(slot, &e) => (),
+                result => return result,
             }
-            .map(|(block, metadata)| (block, Some(metadata)))
-        } else if self.config.ssz {
-            self.get_block_v2_ssz(slot, &randao_reveal, skip_randao_verification)
-                .await
-        } else {
-            self.get_block_v2_json(slot, &randao_reveal, skip_randao_verification)
+        }
+        self.get_block_v3_json(
+            slot,
+            randao_reveal,
+            skip_randao_verification,
+            builder_boost_factor,
+        )
+        .await
+    }
+
+    /// Request a block from the v2 API over SSZ or JSON, see `ssz_fallback`.
+    async fn get_block_v2<E: EthSpec>(
+        &self,
+        slot: Slot,
+        randao_reveal: &SignatureBytes,
+        skip_randao_verification: SkipRandaoVerification,
+    ) -> Result<BlockResponse<E>, eth2::Error> {
+        if self.config.ssz {
+            match self
+                .get_block_v2_ssz(slot, randao_reveal, skip_randao_verification)
                 .await
+            {
+                Err(e) if self.ssz_fallback(slot, &e) => (),
+                result => return result,
+            }
         }
+        self.get_block_v2_json(slot, randao_reveal, skip_randao_verification)
+            .await
+    }
+
+    /// Whether to retry an SSZ request that failed with `error` over JSON, logging if so.
+    ///
+    /// Only responses that couldn't be decoded are retried, not network errors or timeouts.
+    fn ssz_fallback(&self, slot: Slot, error: &eth2::Error) -> bool {
+        let fallback = self.config.ssz_fallback && matches!(error, eth2::Error::InvalidSsz(_));
+        if fallback {
+            warn!(
+                %slot,
+                node = self.config.name,
+                "unable to decode SSZ block ({:?}), retrying over JSON",
+                error
+            );
+        }
+        fallback
     }
 
     pub async fn get_block_v2_json<E: EthSpec>(
@@ -128,53 +543,262 @@ impl Node {
         slot: Slot,
         randao_reveal: &SignatureBytes,
         skip_randao_verification: SkipRandaoVerification,
-    ) -> Result<(BlindedBeaconBlock<E>, Option<ProduceBlockV3Metadata>), String> {
+    ) -> Result<BlockResponse<E>, eth2::Error> {
         let block_contents = self
-            .client
+            .block_client
             .get_validator_blocks_modular::<E>(slot, randao_reveal, None, skip_randao_verification)
-            .await
-            .map(|res| res.data)
-            .map_err(|e| format!("Error fetching block from {}: {:?}", self.config.url, e))?;
-        Ok((block_contents.block().to_ref().into(), None))
+            .await?
+            .data;
+        let blobs = BlobSummary::new(&block_contents);
+        Ok((block_contents.block().to_ref().into(), None, blobs))
     }
 
+    /// Request a block from the v2 API over SSZ.
+    ///
+    /// This makes the request itself rather than through `block_client`, so that it can check
+    /// that the response really is SSZ, see `check_ssz_content_type`. Only the URL comes from
+    /// `block_client`.
     pub async fn get_block_v2_ssz<E: EthSpec>(
         &self,
         slot: Slot,
         randao_reveal: &SignatureBytes,
         skip_randao_verification: SkipRandaoVerification,
-    ) -> Result<(BlindedBeaconBlock<E>, Option<ProduceBlockV3Metadata>), String> {
-        let bytes = self
-            .client
-            .get_validator_blocks_modular_ssz::<E>(
-                slot,
-                randao_reveal,
-                None,
-                skip_randao_verification,
-            )
-            .await
-            .map_err(|e| format!("Error fetching block from {}: {:?}", self.config.url, e))?
-            .ok_or_else(|| {
+    ) -> Result<BlockResponse<E>, eth2::Error> {
+        let url = self
+            .block_client
+            .get_validator_blocks_path::<E>(slot, randao_reveal, None, skip_randao_verification)
+            .await?;
+        let response = self
+            .http_client
+            .get(url)
+            .header(ACCEPT, SSZ_CONTENT_TYPE)
+            .timeout(self.http_timeout)
+            .send()
+            .await?;
+        let response = eth2::ok_or_error(response).await?;
+        check_ssz_content_type(response.headers())?;
+        let bytes = response.bytes().await?;
+        let block_contents = match FullBlockContents::from_ssz_bytes(&bytes, &self.spec) {
+            Ok(block_contents) => block_contents,
+            Err(e) => {
+                if let Some(debug_dumps) = &self.debug_dumps {
+                    debug_dumps
+                        .dump(&self.config.name, slot, "ssz", &bytes)
+                        .await;
+                }
+                return Err(eth2::Error::InvalidSsz(e));
+            }
+        };
+        let blobs = BlobSummary::new(&block_contents);
+        Ok((block_contents.block().to_ref().into(), None, blobs))
+    }
+
+    /// Request a block, giving up after `request_timeout`.
+    ///
+    /// `slot_reveal` is the RANDAO reveal shared by all nodes for the slot, if any.
+    pub async fn get_block_with_timeout<E: EthSpec>(
+        &self,
+        slot: Slot,
+        slot_reveal: Option<&SignatureBytes>,
+        builder_boost_factor: Option<u64>,
+    ) -> Result<BlockResponse<E>, String> {
+        let timeout = self.request_timeout();
+        let (randao_reveal, skip_randao_verification) = self.randao_reveal(slot_reveal);
+        let request = self.get_block(
+            slot,
+            &randao_reveal,
+            skip_randao_verification,
+            builder_boost_factor,
+        );
+        let (block, metadata, blobs) =
+            tokio::time::timeout(timeout, request).await.map_err(|_| {
+                self.record_timeout();
                 format!(
-                    "Error fetching block from {}: returned 404",
-                    self.config.url
+                    "request to {} timed out after {}ms",
+                    self.config.name,
+                    timeout.as_millis()
                 )
-            })?;
-        let block_contents = FullBlockContents::from_ssz_bytes(&bytes, &self.spec)
-            .map_err(|e| format!("Error fetching block from {}: {e:?}", self.config.url))?;
-        Ok((block_contents.block().to_ref().into(), None))
+            })??;
+        if self.adaptive_timeout.is_some() {
+            self.record_block_size(block.ssz_bytes_len());
+        }
+        if let Some(error) = future_slot_error(slot, &block) {
+            let count = self.future_slot_blocks.fetch_add(1, Ordering::Relaxed) + 1;
+            warn!(
+                %slot,
+                node = self.config.name,
+                "{}, its clock may be fast ({} future slot blocks so far)",
+                error,
+                count
+            );
+            if self.config.future_slot == FutureSlotHandling::Reject {
+                return Err(error);
+            }
+        }
+        if self.config.check_unsigned_proposal {
+            for anomaly in unsigned_proposal_anomalies(
+                &self.spec,
+                slot,
+                &randao_reveal,
+                &block,
+                metadata.as_ref(),
+            ) {
+                warn!(
+                    %slot,
+                    node = self.config.name,
+                    "unexpected block: {}",
+                    anomaly
+                );
+            }
+        }
+        Ok((block, metadata, blobs))
     }
 
-    pub async fn get_block_with_timeout<E: EthSpec>(
+    /// Request a block, retrying failed requests up to `max_retries` times while there's time left
+    /// in the slot according to `slot_clock`.
+    ///
+    /// Delays between retries follow `backoff`, starting from the node's `retry_backoff_ms` if set. A
+    /// block returned by a retry after the slot has ended is discarded.
+    pub async fn get_block_with_retries<E: EthSpec, S: SlotClock>(
         &self,
         slot: Slot,
+        slot_reveal: Option<&SignatureBytes>,
         builder_boost_factor: Option<u64>,
-    ) -> Result<(BlindedBeaconBlock<E>, Option<ProduceBlockV3Metadata>), String> {
-        tokio::time::timeout(
-            Duration::from_secs(6),
-            self.get_block(slot, builder_boost_factor),
-        )
-        .await
-        .map_err(|_| format!("request to {} timed out after 6s", self.config.name))?
+        slot_clock: &S,
+        backoff: BackoffPolicy,
+    ) -> Result<BlockResponse<E>, String> {
+        let backoff = backoff.with_base(self.config.retry_backoff_ms);
+        let mut attempt = 0;
+        loop {
+            let error = match self
+                .get_block_with_timeout::<E>(slot, slot_reveal, builder_boost_factor)
+                .await
+            {
+                Ok(block_and_metadata) if attempt == 0 => return Ok(block_and_metadata),
+                Ok(block_and_metadata) => {
+                    let current_slot = clock::current_slot(slot_clock)?;
+                    if current_slot != slot {
+                        return Err(format!(
+                            "slot {} expired while retrying (slot now: {})",
+                            slot, current_slot
+                        ));
+                    }
+                    return Ok(block_and_metadata);
+                }
+                Err(e) => e,
+            };
+
+            let retry_delay = backoff.delay(attempt);
+            let time_left = clock::current_slot(slot_clock) == Ok(slot)
+                && clock::time_into_slot(slot_clock)
+                    .is_ok_and(|elapsed| elapsed + retry_delay < slot_clock.slot_duration());
+            if attempt >= self.config.max_retries || !time_left {
+                return Err(error);
+            }
+            attempt += 1;
+            warn!(
+                %slot,
+                node = self.config.name,
+                "request failed: {}, retrying in {}ms ({}/{})",
+                error,
+                retry_delay.as_millis(),
+                attempt,
+                self.config.max_retries
+            );
+            tokio::time::sleep(retry_delay).await;
+        }
+    }
+
+    /// Request a block at each of the configured `sample_offsets_ms` and keep the best one.
+    ///
+    /// The best block is the one with the most attestations, with ties broken by the reported
+    /// consensus block value and then by the earliest offset. Offsets whose request could not
+    /// complete before the end of the slot are skipped. `elapsed` is the time since the start of
+    /// the slot at which this method is called.
+    pub async fn get_best_sampled_block<E: EthSpec>(
+        &self,
+        slot: Slot,
+        slot_reveal: Option<&SignatureBytes>,
+        builder_boost_factor: Option<u64>,
+        elapsed: Duration,
+    ) -> Result<BlockResponse<E>, String> {
+        let slot_duration = Duration::from_secs(self.spec.seconds_per_slot);
+
+        let requests = self.config.sample_offsets_ms.iter().map(|offset_ms| {
+            let offset = Duration::from_millis(*offset_ms);
+            async move {
+                if offset + self.request_timeout() > slot_duration {
+                    return (
+                        offset,
+                        Err(format!("offset {offset_ms}ms would overrun the slot")),
+                    );
+                }
+                tokio::time::sleep(offset.saturating_sub(elapsed)).await;
+                let result = self
+                    .get_block_with_timeout::<E>(slot, slot_reveal, builder_boost_factor)
+                    .await;
+                (offset, result)
+            }
+        });
+
+        let mut samples = vec![];
+        for (offset, result) in join_all(requests).await {
+            match result {
+                Ok(block_and_metadata) => samples.push((offset, block_and_metadata)),
+                Err(e) => warn!(
+                    %slot,
+                    node = self.config.name,
+                    "sample at {}ms failed: {}",
+                    offset.as_millis(),
+                    e
+                ),
+            }
+        }
+
+        if self
+            .config
+            .expect_distinct_blocks
+            .contains(&DistinctBlocks::SampleOffsets)
+        {
+            let roots = samples
+                .iter()
+                .map(|(offset, (block, _, _))| {
+                    (
+                        format!("sample at {}ms", offset.as_millis()),
+                        block.canonical_root(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            log_repeated_roots(slot, &self.config.name, &roots);
+        }
+
+        let earliest_num_attestations = samples
+            .iter()
+            .min_by_key(|(offset, _)| *offset)
+            .map(|(_, (block, _, _))| block.body().attestations().len())
+            .ok_or_else(|| format!("all samples from {} failed", self.config.name))?;
+
+        let (best_offset, (block, metadata, blobs)) = samples
+            .into_iter()
+            .max_by_key(|(offset, (block, metadata, _))| {
+                (
+                    block.body().attestations().len(),
+                    metadata.as_ref().map(|m| m.consensus_block_value),
+                    Reverse(*offset),
+                )
+            })
+            .expect("at least one sample succeeded");
+
+        let num_attestations = block.body().attestations().len();
+        info!(
+            %slot,
+            node = self.config.name,
+            "chose sample at {}ms with {} attestations ({:+} vs earliest)",
+            best_offset.as_millis(),
+            num_attestations,
+            num_attestations as isize - earliest_num_attestations as isize
+        );
+
+        Ok((block, metadata, blobs))
     }
 }
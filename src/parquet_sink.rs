@@ -0,0 +1,137 @@
+//! Output of pairwise distances to Parquet files, for analytics.
+//!
+//! Requires blockdreamer to be built with the `parquet` feature.
+use eth2::types::Slot;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tracing::error;
+
+/// Distance between the blocks of two nodes at one slot, written as one row.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceRecord {
+    pub slot: Slot,
+    pub node_a: String,
+    pub node_b: String,
+    pub distance: usize,
+    pub label_a: String,
+    pub label_b: String,
+}
+
+/// Buffers distance records and writes them to a new Parquet file every `flush_slots` slots.
+pub struct ParquetSink {
+    dir: PathBuf,
+    /// Included in file names, so that runs writing to the same directory don't clash.
+    run_id: String,
+    flush_slots: u64,
+    records: Vec<DistanceRecord>,
+    slots_buffered: u64,
+}
+
+impl ParquetSink {
+    pub fn new(dir: PathBuf, run_id: &str, flush_slots: u64) -> Result<Self, String> {
+        if !cfg!(feature = "parquet") {
+            return Err(format!(
+                "writing Parquet files to {} requires the `parquet` feature",
+                dir.display()
+            ));
+        }
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| format!("unable to create {}: {e}", dir.display()))?;
+        Ok(Self {
+            dir,
+            run_id: run_id.to_string(),
+            flush_slots: std::cmp::max(flush_slots, 1),
+            records: vec![],
+            slots_buffered: 0,
+        })
+    }
+
+    /// Buffer the records for one slot, flushing to disk if enough slots are buffered.
+    pub fn push_slot(&mut self, records: impl IntoIterator<Item = DistanceRecord>) {
+        self.records.extend(records);
+        self.slots_buffered += 1;
+        if self.slots_buffered >= self.flush_slots {
+            if let Err(e) = self.flush() {
+                error!("{e}");
+            }
+        }
+    }
+
+    /// Buffer the distances between `pairs` of nodes at `slot`, see `push_slot`.
+    pub fn push_pairs(
+        &mut self,
+        slot: Slot,
+        pairs: &[(&String, &String, usize)],
+        labels: &HashMap<String, String>,
+    ) {
+        let label = |name: &String| labels.get(name).cloned().unwrap_or_default();
+        self.push_slot(pairs.iter().map(|(name1, name2, distance)| DistanceRecord {
+            slot,
+            node_a: name1.to_string(),
+            node_b: name2.to_string(),
+            distance: *distance,
+            label_a: label(name1),
+            label_b: label(name2),
+        }));
+    }
+
+    /// Write all buffered records to a new file named after the run and the range of slots it
+    /// contains, e.g. `distances-{run_id}-{first}-{last}.parquet`.
+    ///
+    /// Records are discarded on failure, so that a persistent error can't grow the buffer forever.
+    pub fn flush(&mut self) -> Result<(), String> {
+        self.slots_buffered = 0;
+        let records = std::mem::take(&mut self.records);
+        let (Some(first), Some(last)) = (records.first(), records.last()) else {
+            return Ok(());
+        };
+        let path = self.dir.join(format!(
+            "distances-{}-{}-{}.parquet",
+            self.run_id, first.slot, last.slot
+        ));
+        write_file(&path, &records)
+            .map_err(|e| format!("unable to write Parquet file {}: {e}", path.display()))
+    }
+}
+
+#[cfg(feature = "parquet")]
+fn write_file(path: &std::path::Path, records: &[DistanceRecord]) -> Result<(), String> {
+    use arrow::array::{ArrayRef, StringArray, UInt64Array};
+    use arrow::record_batch::RecordBatch;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+
+    let strings = |f: fn(&DistanceRecord) -> &str| -> ArrayRef {
+        Arc::new(records.iter().map(f).map(Some).collect::<StringArray>())
+    };
+    let batch = RecordBatch::try_from_iter([
+        (
+            "slot",
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|record| record.slot.as_u64()),
+            )) as ArrayRef,
+        ),
+        ("node_a", strings(|record| &record.node_a)),
+        ("node_b", strings(|record| &record.node_b)),
+        (
+            "distance",
+            Arc::new(UInt64Array::from_iter_values(
+                records.iter().map(|record| record.distance as u64),
+            )),
+        ),
+        ("label_a", strings(|record| &record.label_a)),
+        ("label_b", strings(|record| &record.label_b)),
+    ])
+    .map_err(|e| e.to_string())?;
+
+    let file = std::fs::File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None).map_err(|e| e.to_string())?;
+    writer.write(&batch).map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_file(_: &std::path::Path, _: &[DistanceRecord]) -> Result<(), String> {
+    Err("the `parquet` feature is disabled".into())
+}
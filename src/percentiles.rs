@@ -0,0 +1,114 @@
+//! Percentiles of each node's distance to canonical over a long window of slots, e.g. a day.
+use eth2::types::Slot;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DistancePercentilesConfig {
+    /// Number of recent slots to compute percentiles over. Default: 7200 (a day on mainnet).
+    ///
+    /// One distance per node is kept in memory for each compared slot in the window.
+    #[serde(default = "default_window_slots")]
+    pub window_slots: u64,
+    /// Log the percentiles every this many slots. Default: 300 (an hour on mainnet).
+    ///
+    /// Set to 0 to only serve them at `/percentiles`.
+    #[serde(default = "default_log_interval_slots")]
+    pub log_interval_slots: u64,
+}
+
+/// Distribution of a node's distances to canonical within the window.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PercentileSummary {
+    pub samples: usize,
+    pub p50: usize,
+    pub p90: usize,
+    pub p99: usize,
+}
+
+impl fmt::Display for PercentileSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "p50 {}, p90 {}, p99 {} ({} samples)",
+            self.p50, self.p90, self.p99, self.samples
+        )
+    }
+}
+
+/// Each node's distances to canonical within the window, shared with the HTTP server.
+#[derive(Debug)]
+pub struct DistancePercentiles {
+    window_slots: u64,
+    /// Distances by node name, oldest first.
+    samples: Mutex<HashMap<String, VecDeque<(Slot, usize)>>>,
+}
+
+impl DistancePercentiles {
+    pub fn new(window_slots: u64) -> Self {
+        Self {
+            window_slots,
+            samples: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record each node's distance to the canonical block at `slot`, and forget distances that
+    /// have left the window.
+    pub fn record(&self, slot: Slot, distances: &[(&str, usize)]) {
+        let mut samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        for (name, distance) in distances {
+            samples
+                .entry(name.to_string())
+                .or_default()
+                .push_back((slot, *distance));
+        }
+        for node_samples in samples.values_mut() {
+            while node_samples
+                .front()
+                .is_some_and(|(sample_slot, _)| *sample_slot + self.window_slots <= slot)
+            {
+                node_samples.pop_front();
+            }
+        }
+        samples.retain(|_, node_samples| !node_samples.is_empty());
+    }
+
+    /// Percentiles of the distances of each node with any in the window.
+    pub fn summary(&self) -> BTreeMap<String, PercentileSummary> {
+        let samples = self.samples.lock().unwrap_or_else(|e| e.into_inner());
+        samples
+            .iter()
+            .filter_map(|(name, node_samples)| {
+                let mut distances = node_samples
+                    .iter()
+                    .map(|(_, distance)| *distance)
+                    .collect::<Vec<_>>();
+                distances.sort_unstable();
+                let summary = PercentileSummary {
+                    samples: distances.len(),
+                    p50: percentile(&distances, 50.0)?,
+                    p90: percentile(&distances, 90.0)?,
+                    p99: percentile(&distances, 99.0)?,
+                };
+                Some((name.clone(), summary))
+            })
+            .collect()
+    }
+}
+
+/// The `p`th percentile of `sorted` by the nearest-rank method, or `None` if it's empty.
+pub fn percentile(sorted: &[usize], p: f64) -> Option<usize> {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
+
+fn default_window_slots() -> u64 {
+    7200
+}
+
+fn default_log_interval_slots() -> u64 {
+    300
+}
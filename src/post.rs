@@ -1,96 +1,308 @@
+use crate::config::{PostKind, PostProtocol};
+use crate::fingerprint::Fingerprint;
+#[cfg(feature = "grpc")]
+use crate::grpc;
+use crate::metrics::Metrics;
 use crate::PostEndpointConfig;
-use eth2::types::{BlindedBeaconBlock, EthSpec, Slot};
-use itertools::multiunzip;
-use reqwest::Client;
+use eth2::types::{BlindedBeaconBlock, EthSpec, Hash256, Slot, Uint256};
+use itertools::{multiunzip, Itertools};
+use reqwest::{
+    header::{ACCEPT, CONTENT_TYPE},
+    Client, RequestBuilder,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+#[cfg(feature = "grpc")]
+use ssz::Encode;
+use std::collections::HashMap;
 use std::path::PathBuf;
 use std::sync::Arc;
 use tokio::fs::{create_dir_all, File};
 use tokio::io::AsyncWriteExt;
+use tracing::{info, warn};
+
+/// The most common of `parents`, if it accounts for at least `quorum` of them.
+///
+/// `quorum` must be greater than 0.5 so that at most one parent can reach it.
+pub fn quorum_parent(parents: &[Hash256], quorum: f64) -> Option<Hash256> {
+    let mut counts = HashMap::new();
+    for parent in parents {
+        *counts.entry(*parent).or_insert(0usize) += 1;
+    }
+    counts
+        .into_iter()
+        .find(|(_, count)| *count as f64 >= quorum * parents.len() as f64)
+        .map(|(parent, _)| parent)
+}
 
 #[derive(Clone)]
 pub struct PostEndpoint {
     pub name: String,
     client: Client,
     url: String,
+    run_id: String,
     results_dir: Option<PathBuf>,
     compare_rewards: bool,
     require_all: bool,
+    skip_on_node_error: bool,
     require_same_parent: bool,
+    same_parent_quorum: Option<f64>,
     extra_data: bool,
+    protocol: PostProtocol,
+    kind: PostKind,
+    min_reward_gwei: Option<u64>,
+    content_type: Option<String>,
+    accept: Option<String>,
+    metrics: Arc<Metrics>,
+    /// Channel reused across posts, for gRPC endpoints.
+    #[cfg(feature = "grpc")]
+    grpc_channel: Option<tonic::transport::Channel>,
 }
 
 #[derive(Clone, Serialize, Deserialize)]
 #[serde(bound = "E: EthSpec")]
 pub struct PostPayload<E: EthSpec> {
+    run_id: String,
     names: Vec<String>,
     labels: Vec<String>,
+    /// Client versions reported by the nodes at startup.
+    versions: Vec<Option<String>>,
     blocks: Vec<BlindedBeaconBlock<E>>,
 }
 
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FingerprintPayload {
+    run_id: String,
+    slot: Slot,
+    names: Vec<String>,
+    labels: Vec<String>,
+    versions: Vec<Option<String>>,
+    fingerprints: Vec<Fingerprint>,
+}
+
 impl PostEndpoint {
-    pub fn new(config: &PostEndpointConfig) -> Arc<Self> {
+    pub fn new(
+        config: &PostEndpointConfig,
+        run_id: &str,
+        metrics: Arc<Metrics>,
+    ) -> Result<Arc<Self>, String> {
+        if config.protocol == PostProtocol::Grpc && !cfg!(feature = "grpc") {
+            return Err(format!(
+                "post endpoint {} uses gRPC but blockdreamer was built without the `grpc` feature",
+                config.name
+            ));
+        }
+        if config.protocol == PostProtocol::Grpc && config.kind == PostKind::Fingerprint {
+            return Err(format!(
+                "post endpoint {} can't send fingerprints over gRPC",
+                config.name
+            ));
+        }
+        if let Some(quorum) = config.same_parent_quorum {
+            if !(quorum > 0.5 && quorum <= 1.0) {
+                return Err(format!(
+                    "same_parent_quorum for post endpoint {} must be in (0.5, 1.0], got {quorum}",
+                    config.name
+                ));
+            }
+            if config.require_same_parent {
+                return Err(format!(
+                    "same_parent_quorum for post endpoint {} conflicts with require_same_parent",
+                    config.name
+                ));
+            }
+        }
         let client = Client::new();
-        let name = config.url.clone();
-        let url = config.url.clone();
-        Arc::new(Self {
-            name,
+        #[cfg(feature = "grpc")]
+        let grpc_channel = (config.protocol == PostProtocol::Grpc)
+            .then(|| grpc::channel(&config.url))
+            .transpose()
+            .map_err(|e| format!("post endpoint {}: {e}", config.name))?;
+        Ok(Arc::new(Self {
+            name: config.name.clone(),
             client,
-            url,
+            url: config.url.clone(),
+            run_id: run_id.to_string(),
             results_dir: config.results_dir.clone(),
             compare_rewards: config.compare_rewards,
             require_all: config.require_all,
+            skip_on_node_error: config.skip_on_node_error,
             require_same_parent: config.require_same_parent,
+            same_parent_quorum: config.same_parent_quorum,
             extra_data: config.extra_data,
-        })
+            protocol: config.protocol,
+            kind: config.kind,
+            min_reward_gwei: config.min_reward_gwei,
+            content_type: config.content_type.clone(),
+            accept: config.accept.clone(),
+            metrics,
+            #[cfg(feature = "grpc")]
+            grpc_channel,
+        }))
     }
 
+    /// Start a POST request to the endpoint with the configured headers.
+    ///
+    /// The `Content-Type` header must be set before the body, so that reqwest's JSON default
+    /// doesn't get added as well.
+    fn post_request(&self) -> RequestBuilder {
+        let mut request = self.client.post(&self.url);
+        if let Some(content_type) = &self.content_type {
+            request = request.header(CONTENT_TYPE, content_type);
+        }
+        if let Some(accept) = &self.accept {
+            request = request.header(ACCEPT, accept);
+        }
+        request
+    }
+
+    /// Post the blocks for `slot` unless configured to skip them, recording the outcome.
     pub async fn post_blocks<E: EthSpec>(
         &self,
-        names_and_labels: Vec<(String, String)>,
-        opt_blocks: Vec<Option<BlindedBeaconBlock<E>>>,
+        node_info: Vec<(String, String, Option<String>)>,
+        block_results: Arc<Vec<Result<(BlindedBeaconBlock<E>, Option<Uint256>), String>>>,
         slot: Slot,
     ) -> Result<(), String> {
-        let total_nodes = opt_blocks.len();
-        if names_and_labels.len() != opt_blocks.len() {
+        let result = self.try_post_blocks(node_info, block_results, slot).await;
+        let outcome = match result {
+            Ok(true) => "success",
+            Ok(false) => "skipped",
+            Err(_) => "failure",
+        };
+        self.metrics.record_post(&self.name, outcome);
+        result.map(|_| ())
+    }
+
+    /// Post the blocks for `slot`, returning whether they were posted rather than skipped.
+    ///
+    /// `block_results` is shared by all endpoints, and blocks are only copied once they're posted.
+    async fn try_post_blocks<E: EthSpec>(
+        &self,
+        node_info: Vec<(String, String, Option<String>)>,
+        block_results: Arc<Vec<Result<(BlindedBeaconBlock<E>, Option<Uint256>), String>>>,
+        slot: Slot,
+    ) -> Result<bool, String> {
+        let total_nodes = block_results.len();
+        if node_info.len() != block_results.len() {
             return Err(format!(
                 "logic error: mismatched blocks and nodes: {} vs {}",
-                opt_blocks.len(),
-                names_and_labels.len()
+                block_results.len(),
+                node_info.len()
             ));
         }
 
+        if self.skip_on_node_error {
+            let failures = node_info
+                .iter()
+                .zip(block_results.iter())
+                .filter_map(|((name, _, _), result)| {
+                    let e = result.as_ref().err()?;
+                    Some(format!("{name} ({e})"))
+                })
+                .collect::<Vec<_>>();
+            if !failures.is_empty() {
+                info!(
+                    %slot,
+                    endpoint = self.name,
+                    "not posting as some nodes failed: {}",
+                    failures.join(", ")
+                );
+                return Ok(false);
+            }
+        }
+
         // Filter out nodes that failed.
-        let (names, labels, blocks): (Vec<_>, Vec<_>, Vec<_>) = multiunzip(
-            names_and_labels
-                .into_iter()
-                .zip(opt_blocks)
-                .filter_map(|((name, label), opt_block)| Some((name, label, opt_block?))),
-        );
+        let mut succeeded = node_info
+            .into_iter()
+            .zip(block_results.iter())
+            .filter_map(|((name, label, version), result)| {
+                let (block, reward) = result.as_ref().ok()?;
+                Some((name, label, version, block.clone(), *reward))
+            })
+            .collect::<Vec<_>>();
 
-        if self.require_all && blocks.len() != total_nodes {
-            return Err(format!("only got {}/{} blocks", blocks.len(), total_nodes));
+        if self.require_all && succeeded.len() != total_nodes {
+            return Err(format!(
+                "only got {}/{} blocks",
+                succeeded.len(),
+                total_nodes
+            ));
         }
 
         if self.require_same_parent
-            && !blocks
+            && !succeeded
                 .iter()
-                .all(|block| block.parent_root() == blocks[0].parent_root())
+                .map(|(_, _, _, block, _)| block.parent_root())
+                .all_equal()
         {
             return Err(format!("not all blocks build on the same parent"));
         }
 
+        if let Some(quorum) = self.same_parent_quorum {
+            let parents = succeeded
+                .iter()
+                .map(|(_, _, _, block, _)| block.parent_root())
+                .collect::<Vec<_>>();
+            let parent = quorum_parent(&parents, quorum)
+                .ok_or_else(|| format!("no parent is shared by {quorum} of the blocks"))?;
+            succeeded.retain(|(name, _, _, block, _)| {
+                let keep = block.parent_root() == parent;
+                if !keep {
+                    info!(
+                        %slot,
+                        node = name,
+                        endpoint = self.name,
+                        "not posting block, its parent {:?} is not the quorum parent {:?}",
+                        block.parent_root(),
+                        parent
+                    );
+                }
+                keep
+            });
+        }
+
+        let (names, labels, versions, blocks, rewards): (Vec<_>, Vec<_>, Vec<_>, Vec<_>, Vec<_>) =
+            multiunzip(succeeded);
+
+        if let Some(min_reward_gwei) = self.min_reward_gwei {
+            let min_reward = Uint256::from(min_reward_gwei) * Uint256::from(1_000_000_000u64);
+            if !rewards.iter().flatten().any(|reward| *reward >= min_reward) {
+                info!(
+                    %slot,
+                    endpoint = self.name,
+                    "no block reward reaches {} gwei, not posting",
+                    min_reward_gwei
+                );
+                return Ok(false);
+            }
+        }
+
+        if self.protocol == PostProtocol::Grpc {
+            return self
+                .post_blocks_grpc(names, labels, versions, blocks, rewards, slot)
+                .await
+                .map(|()| true);
+        }
+
+        if self.kind == PostKind::Fingerprint {
+            return self
+                .post_fingerprints(names, labels, versions, blocks, rewards, slot)
+                .await
+                .map(|()| true);
+        }
+
         let response = if self.extra_data {
             let payload = PostPayload {
+                run_id: self.run_id.clone(),
                 names: names.clone(),
                 labels: labels.clone(),
+                versions: versions.clone(),
                 blocks,
             };
 
-            self.client.post(&self.url).json(&payload)
+            self.post_request().json(&payload)
         } else {
-            self.client.post(&self.url).json(&blocks)
+            self.post_request().json(&blocks)
         }
         .send()
         .await
@@ -120,27 +332,44 @@ impl PostEndpoint {
         let mut max_reward = 0;
         let mut max_reward_nodes = vec![];
 
-        for ((name, label), result) in names.iter().zip(labels.iter()).zip(response_json) {
+        for (((name, label), version), mut result) in names
+            .iter()
+            .zip(labels.iter())
+            .zip(versions.iter())
+            .zip(response_json)
+        {
             if self.compare_rewards {
-                let reward = result["total"].as_u64().unwrap();
-                let att_reward = result["attestation_rewards"]["total"].as_u64().unwrap();
-                println!("rewards from {name}: {reward} gwei (att: {att_reward} gwei)");
-
-                if reward > max_reward {
-                    max_reward = reward;
-                    max_reward_nodes = vec![name.clone()];
-                } else if reward == max_reward {
-                    max_reward_nodes.push(name.clone());
+                // A malformed entry only rules its node out of the comparison.
+                match (
+                    result["total"].as_u64(),
+                    result["attestation_rewards"]["total"].as_u64(),
+                ) {
+                    (Some(reward), Some(att_reward)) => {
+                        info!(%slot, node = name, "rewards: {reward} gwei (att: {att_reward} gwei)");
+
+                        if reward > max_reward {
+                            max_reward = reward;
+                            max_reward_nodes = vec![name.clone()];
+                        } else if reward == max_reward {
+                            max_reward_nodes.push(name.clone());
+                        }
+                    }
+                    _ => warn!(%slot, node = name, "missing rewards in response: {result}"),
                 }
             }
 
             if let Some(results_dir) = &self.results_dir {
-                // Store results by client label (same format as blockprint training data).
-                let label_dir = results_dir.join(label);
+                // Store results by run and client label (same format as blockprint training data).
+                let label_dir = results_dir.join(&self.run_id).join(label);
                 create_dir_all(&label_dir)
                     .await
                     .map_err(|e| format!("unable to create {}: {}", label_dir.display(), e))?;
 
+                // Record the node's client version alongside the result, where known.
+                if let (Some(version), Some(result)) = (version, result.as_object_mut()) {
+                    result.insert("client_version".into(), version.clone().into());
+                }
+
                 // Name files by node name and slot.
                 let result_path = label_dir.join(format!("{name}_{slot}.json"));
                 let mut f = File::create(&result_path)
@@ -156,9 +385,95 @@ impl PostEndpoint {
         }
 
         if self.compare_rewards {
-            println!("most profitable block from {max_reward_nodes:?}");
+            info!(%slot, "most profitable block from {max_reward_nodes:?}");
+        }
+
+        Ok(true)
+    }
+
+    async fn post_fingerprints<E: EthSpec>(
+        &self,
+        names: Vec<String>,
+        labels: Vec<String>,
+        versions: Vec<Option<String>>,
+        blocks: Vec<BlindedBeaconBlock<E>>,
+        rewards: Vec<Option<Uint256>>,
+        slot: Slot,
+    ) -> Result<(), String> {
+        let payload = FingerprintPayload {
+            run_id: self.run_id.clone(),
+            slot,
+            names,
+            labels,
+            versions,
+            fingerprints: blocks
+                .iter()
+                .zip(rewards)
+                .map(|(block, reward)| Fingerprint::new(block, reward))
+                .collect(),
+        };
+
+        let response = self
+            .post_request()
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| format!("POST error: {}", e))?;
+
+        let response_status = response.status();
+        if !response_status.is_success() {
+            let response_text = response
+                .text()
+                .await
+                .unwrap_or_else(|_| "<body garbled>".into());
+            return Err(format!("status {response_status}: {response_text}"));
         }
 
         Ok(())
     }
+
+    #[cfg(feature = "grpc")]
+    async fn post_blocks_grpc<E: EthSpec>(
+        &self,
+        names: Vec<String>,
+        labels: Vec<String>,
+        versions: Vec<Option<String>>,
+        blocks: Vec<BlindedBeaconBlock<E>>,
+        rewards: Vec<Option<Uint256>>,
+        slot: Slot,
+    ) -> Result<(), String> {
+        let channel = self
+            .grpc_channel
+            .clone()
+            .ok_or("logic error: no gRPC channel")?;
+        let message = grpc::DreamBlocks {
+            run_id: self.run_id.clone(),
+            slot: slot.as_u64(),
+            names,
+            labels,
+            versions: versions
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect(),
+            blocks_ssz: blocks.iter().map(|block| block.as_ssz_bytes()).collect(),
+            reported_rewards: rewards
+                .iter()
+                .map(|reward| reward.map(|reward| reward.to_string()).unwrap_or_default())
+                .collect(),
+        };
+        grpc::post_blocks(channel, message).await
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    async fn post_blocks_grpc<E: EthSpec>(
+        &self,
+        _: Vec<String>,
+        _: Vec<String>,
+        _: Vec<Option<String>>,
+        _: Vec<BlindedBeaconBlock<E>>,
+        _: Vec<Option<Uint256>>,
+        _: Slot,
+    ) -> Result<(), String> {
+        Err("gRPC support requires the `grpc` feature".into())
+    }
 }
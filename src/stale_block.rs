@@ -0,0 +1,34 @@
+//! Detection of nodes returning the same block for requests that should produce different blocks,
+//! which may indicate that the node is serving a cached block.
+use eth2::types::{Hash256, Slot};
+use tracing::warn;
+
+/// Requests whose block root was already returned by an earlier request, as
+/// `(earlier_request, request, root)`.
+///
+/// `roots` holds the root of the block returned for each request to one node for one slot, in the
+/// order the requests were made.
+pub fn repeated_roots(roots: &[(String, Hash256)]) -> Vec<(&str, &str, Hash256)> {
+    roots
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (request, root))| {
+            roots[..i]
+                .iter()
+                .find(|(_, earlier_root)| earlier_root == root)
+                .map(|(earlier_request, _)| (earlier_request.as_str(), request.as_str(), *root))
+        })
+        .collect()
+}
+
+/// Log each request to `name` at `slot` that returned the same block as an earlier request.
+pub fn log_repeated_roots(slot: Slot, name: &str, roots: &[(String, Hash256)]) {
+    for (earlier_request, request, root) in repeated_roots(roots) {
+        warn!(
+            %slot,
+            node = name,
+            "returned the same block {root:?} for {request} as for {earlier_request}, \
+             it may be serving a cached block"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use crate::classify::Classification;
+use eth2::types::Slot;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use tokio::net::unix::pipe;
+use tracing::{error, warn};
+
+/// Compact summary of the classification of one slot, written as a line of JSON.
+#[derive(Debug, Serialize)]
+pub struct SlotSummary<'a> {
+    pub slot: Slot,
+    pub verdict: &'static str,
+    pub label: Option<&'a str>,
+    pub confidence: f64,
+    pub distances: BTreeMap<&'a str, usize>,
+    /// Client version of each compared node, where known.
+    pub versions: BTreeMap<&'a str, &'a str>,
+}
+
+impl<'a> SlotSummary<'a> {
+    pub fn new(
+        slot: Slot,
+        classification: &'a Classification,
+        distances: &[(&'a str, usize)],
+        versions: &BTreeMap<&'a str, &'a str>,
+    ) -> Self {
+        Self {
+            slot,
+            verdict: classification.verdict.kind(),
+            label: classification.verdict.label(),
+            confidence: classification.confidence,
+            distances: distances.iter().copied().collect(),
+            versions: versions.clone(),
+        }
+    }
+}
+
+/// Writer for slot summaries to a named pipe (FIFO) created with `mkfifo`.
+///
+/// Writes never block: summaries are dropped if no reader is attached or the pipe is full.
+pub struct SummaryFifo {
+    path: PathBuf,
+    sender: Option<pipe::Sender>,
+}
+
+impl SummaryFifo {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, sender: None }
+    }
+
+    pub fn write(&mut self, summary: &SlotSummary) {
+        let mut line = match serde_json::to_string(summary) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("unable to serialize slot summary: {e}");
+                return;
+            }
+        };
+        line.push('\n');
+        // Longer writes may be split, interleaving partial lines.
+        if line.len() > libc::PIPE_BUF {
+            warn!(
+                slot = %summary.slot,
+                "slot summary of {} bytes exceeds PIPE_BUF ({}), dropping it",
+                line.len(),
+                libc::PIPE_BUF
+            );
+            return;
+        }
+
+        if self.sender.is_none() {
+            match pipe::OpenOptions::new().open_sender(&self.path) {
+                Ok(sender) => self.sender = Some(sender),
+                // No reader attached.
+                Err(e) if e.raw_os_error() == Some(libc::ENXIO) => return,
+                Err(e) => {
+                    warn!("unable to open {}: {e}", self.path.display());
+                    return;
+                }
+            }
+        }
+        let Some(sender) = &self.sender else {
+            return;
+        };
+
+        // Writes of up to `PIPE_BUF` bytes are atomic, so a line is either written in full or
+        // not at all.
+        match sender.try_write(line.as_bytes()) {
+            Ok(n) if n == line.len() => {}
+            Ok(n) => {
+                error!(
+                    "partial write of slot summary to {} ({n} of {} bytes), reopening",
+                    self.path.display(),
+                    line.len()
+                );
+                self.sender = None;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(_) => {
+                // The reader went away, reopen on the next write.
+                self.sender = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! OpenTelemetry spans for the main loop, exported to an OTLP collector.
+//!
+//! Spans are no-ops unless blockdreamer is built with the `otel` feature and `otlp_endpoint` is
+//! configured.
+use eth2::types::Slot;
+#[cfg(feature = "otel")]
+use opentelemetry::{
+    global,
+    trace::{Span, Status, TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+#[cfg(feature = "otel")]
+use std::time::Instant;
+
+#[cfg(feature = "otel")]
+const TRACER_NAME: &str = "blockdreamer";
+
+/// Start exporting spans to the OTLP collector at `endpoint` (gRPC).
+#[cfg(feature = "otel")]
+pub fn init(endpoint: &str) -> Result<(), String> {
+    use opentelemetry_otlp::WithExportConfig;
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry_sdk::trace::config().with_resource(
+            opentelemetry_sdk::Resource::new(vec![KeyValue::new("service.name", TRACER_NAME)]),
+        ))
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map_err(|e| format!("unable to start OTLP exporter for {endpoint}: {e}"))?;
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init(endpoint: &str) -> Result<(), String> {
+    Err(format!(
+        "exporting traces to {endpoint} requires the `otel` feature"
+    ))
+}
+
+/// Flush any spans that haven't been exported yet.
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    global::shutdown_tracer_provider();
+}
+
+/// Parent span covering all of the work for one slot.
+///
+/// The span ends when this is dropped.
+pub struct SlotTrace {
+    #[cfg(feature = "otel")]
+    cx: Context,
+}
+
+/// Span for one unit of work within a slot, which ends when `finish`ed or dropped.
+pub struct ChildSpan {
+    #[cfg(feature = "otel")]
+    cx: Context,
+    #[cfg(feature = "otel")]
+    start: Instant,
+}
+
+impl SlotTrace {
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn start(slot: Slot) -> Self {
+        #[cfg(feature = "otel")]
+        {
+            let mut span = global::tracer(TRACER_NAME).start("slot");
+            span.set_attribute(KeyValue::new("slot", slot.as_u64() as i64));
+            Self {
+                cx: Context::current_with_span(span),
+            }
+        }
+        #[cfg(not(feature = "otel"))]
+        Self {}
+    }
+
+    /// Start a span for a block request to the node called `name`.
+    pub fn node_request(&self, name: &str) -> ChildSpan {
+        self.child("node_request", name)
+    }
+
+    /// Start a span for the comparison of the canonical block at `prev_slot` to the dream blocks.
+    pub fn canonical_comparison(&self, prev_slot: Slot) -> ChildSpan {
+        self.child("canonical_comparison", &prev_slot.to_string())
+    }
+
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    fn child(&self, span_name: &'static str, subject: &str) -> ChildSpan {
+        #[cfg(feature = "otel")]
+        {
+            let mut span = global::tracer(TRACER_NAME).start_with_context(span_name, &self.cx);
+            span.set_attribute(KeyValue::new(span_name, subject.to_string()));
+            ChildSpan {
+                cx: Context::current_with_span(span),
+                start: Instant::now(),
+            }
+        }
+        #[cfg(not(feature = "otel"))]
+        ChildSpan {}
+    }
+}
+
+impl ChildSpan {
+    /// End the span, recording its latency and whether `result` was successful.
+    #[cfg_attr(not(feature = "otel"), allow(unused_variables))]
+    pub fn finish<T>(self, result: &Result<T, String>) {
+        #[cfg(feature = "otel")]
+        {
+            let span = self.cx.span();
+            span.set_attribute(KeyValue::new(
+                "latency_ms",
+                self.start.elapsed().as_millis() as i64,
+            ));
+            span.set_attribute(KeyValue::new("success", result.is_ok()));
+            if let Err(e) = result {
+                span.set_status(Status::error(e.clone()));
+            }
+            span.end();
+        }
+    }
+}
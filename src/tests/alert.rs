@@ -0,0 +1,92 @@
+use crate::alert::{AlertWebhookConfig, Alerter};
+use crate::classify::{Classification, Verdict};
+use eth2::types::Slot;
+use std::time::{Duration, Instant};
+
+fn alerter(too_close_to_call_slots: u64, distance_threshold: Option<usize>) -> Alerter {
+    Alerter::new(AlertWebhookConfig {
+        url: "http://localhost:9000".into(),
+        too_close_to_call_slots,
+        distance_threshold,
+        min_interval_secs: 60,
+    })
+    .unwrap()
+}
+
+fn too_close_to_call() -> Classification {
+    Classification {
+        verdict: Verdict::TooCloseToCall {
+            name: "lighthouse".into(),
+            distance: 10,
+            second_name: "teku".into(),
+            second_distance: 11,
+        },
+        confidence: 0.0,
+    }
+}
+
+fn two_closest_match() -> Classification {
+    Classification {
+        verdict: Verdict::TwoClosestMatch {
+            label: "Lighthouse".into(),
+            distance: 0,
+        },
+        confidence: 1.0,
+    }
+}
+
+#[test]
+fn too_close_to_call_streak() {
+    let mut alerter = alerter(3, None);
+    let now = Instant::now();
+
+    assert!(alerter
+        .check(Slot::new(1), &too_close_to_call(), &[], now)
+        .is_empty());
+    assert!(alerter
+        .check(Slot::new(2), &too_close_to_call(), &[], now)
+        .is_empty());
+    // A clear verdict resets the streak.
+    assert!(alerter
+        .check(Slot::new(3), &two_closest_match(), &[], now)
+        .is_empty());
+    for slot in 4..6 {
+        assert!(alerter
+            .check(Slot::new(slot), &too_close_to_call(), &[], now)
+            .is_empty());
+    }
+    assert_eq!(
+        alerter
+            .check(Slot::new(6), &too_close_to_call(), &[], now)
+            .len(),
+        1
+    );
+    // Rate limited until the interval has passed.
+    assert!(alerter
+        .check(Slot::new(7), &too_close_to_call(), &[], now)
+        .is_empty());
+    let later = now + Duration::from_secs(60);
+    assert_eq!(
+        alerter
+            .check(Slot::new(8), &too_close_to_call(), &[], later)
+            .len(),
+        1
+    );
+}
+
+#[test]
+fn distance_threshold_per_node() {
+    let mut alerter = alerter(0, Some(100));
+    let now = Instant::now();
+    let distances = [("lighthouse", 5), ("teku", 101), ("prysm", 200)];
+
+    let alerts = alerter.check(Slot::new(1), &two_closest_match(), &distances, now);
+    assert_eq!(alerts.len(), 2);
+    assert!(alerts[0].contains("teku"), "{alerts:?}");
+    assert!(alerts[1].contains("prysm"), "{alerts:?}");
+
+    let distances = [("lighthouse", 150), ("teku", 101)];
+    let alerts = alerter.check(Slot::new(2), &two_closest_match(), &distances, now);
+    assert_eq!(alerts.len(), 1);
+    assert!(alerts[0].contains("lighthouse"), "{alerts:?}");
+}
@@ -0,0 +1,84 @@
+use crate::backoff::BackoffPolicy;
+use std::time::Duration;
+
+#[test]
+fn delays_increase_until_capped() {
+    let policy = BackoffPolicy {
+        base_ms: 100,
+        multiplier: 3.0,
+        max_ms: 2_000,
+        jitter: 0.0,
+    };
+    let delays = (0..6)
+        .map(|retry| policy.delay(retry).as_millis())
+        .collect::<Vec<_>>();
+    assert_eq!(delays, [100, 300, 900, 2_000, 2_000, 2_000]);
+
+    // Huge retry counts don't overflow.
+    assert_eq!(policy.delay(u32::MAX), Duration::from_millis(2_000));
+}
+
+#[test]
+fn jitter_within_bounds() {
+    let policy = BackoffPolicy {
+        base_ms: 1_000,
+        multiplier: 2.0,
+        max_ms: 60_000,
+        jitter: 0.25,
+    };
+    assert_eq!(policy.jittered_delay(0, 0.0), Duration::from_millis(750));
+    assert_eq!(policy.jittered_delay(0, 0.5), Duration::from_millis(1_000));
+    assert_eq!(policy.jittered_delay(1, 0.75), Duration::from_millis(2_250));
+
+    for retry in 0..4 {
+        let nominal = 1_000 * 2u128.pow(retry);
+        for _ in 0..100 {
+            let delay = policy.delay(retry).as_millis();
+            assert!(
+                delay >= nominal * 3 / 4 && delay <= nominal * 5 / 4,
+                "{delay}"
+            );
+        }
+    }
+}
+
+#[test]
+fn jitter_respects_cap() {
+    let policy = BackoffPolicy {
+        base_ms: 1_000,
+        multiplier: 2.0,
+        max_ms: 1_000,
+        jitter: 0.5,
+    };
+    assert_eq!(policy.jittered_delay(3, 0.99), Duration::from_millis(1_000));
+}
+
+#[test]
+fn base_overridden_only_if_set() {
+    let policy = BackoffPolicy::default();
+    assert_eq!(policy.with_base(None), policy);
+    assert_eq!(
+        policy.with_base(Some(100)).delay(1),
+        Duration::from_millis(200)
+    );
+}
+
+#[test]
+fn invalid_policy_rejected() {
+    assert_eq!(BackoffPolicy::default().validate(), Ok(()));
+    for multiplier in [0.5, -2.0, f64::NAN, f64::INFINITY] {
+        let policy = BackoffPolicy {
+            multiplier,
+            ..BackoffPolicy::default()
+        };
+        assert!(policy.validate().is_err(), "{multiplier}");
+    }
+    for jitter in [-0.1, 1.5, f64::NAN] {
+        let policy = BackoffPolicy {
+            jitter,
+            ..BackoffPolicy::default()
+        };
+        let err = policy.validate().unwrap_err();
+        assert!(err.contains("jitter"), "{err}");
+    }
+}
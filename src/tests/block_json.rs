@@ -0,0 +1,21 @@
+use crate::block_json::BlockJsonDir;
+use eth2::types::{BlindedBeaconBlock, ChainSpec, MainnetEthSpec, Slot};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn block_round_trips() {
+    let dir = std::env::temp_dir().join(format!("blockdreamer-{}", Uuid::new_v4()));
+    let block_json = BlockJsonDir::new(dir.clone());
+    let mut block = BlindedBeaconBlock::<MainnetEthSpec>::empty(&ChainSpec::mainnet());
+    *block.slot_mut() = Slot::new(42);
+
+    block_json.save("lighthouse", Slot::new(42), &block).await;
+
+    let path = block_json.path("lighthouse", Slot::new(42));
+    assert_eq!(path, dir.join("lighthouse_42.json"));
+    let json = std::fs::read_to_string(&path).unwrap();
+    assert!(json.contains('\n'), "not pretty-printed: {json}");
+    let decoded: BlindedBeaconBlock<MainnetEthSpec> = serde_json::from_str(&json).unwrap();
+    assert_eq!(decoded, block);
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -0,0 +1,27 @@
+use crate::circuit_breaker::CircuitBreaker;
+
+#[test]
+fn opens_at_threshold_and_closes_on_success() {
+    let mut breaker = CircuitBreaker::new(Some(2));
+    assert!(!breaker.record_failure());
+    assert!(!breaker.is_open());
+    assert!(breaker.record_failure());
+    assert!(breaker.is_open());
+
+    // Further failures don't re-trip the breaker.
+    assert!(!breaker.record_failure());
+    assert!(breaker.is_open());
+
+    assert!(breaker.record_success());
+    assert!(!breaker.is_open());
+    assert!(!breaker.record_success());
+}
+
+#[test]
+fn no_threshold_never_opens() {
+    let mut breaker = CircuitBreaker::new(None);
+    for _ in 0..100 {
+        assert!(!breaker.record_failure());
+    }
+    assert!(!breaker.is_open());
+}
@@ -0,0 +1,58 @@
+use crate::classify::{classify, Verdict};
+use std::collections::HashMap;
+
+fn labels() -> HashMap<String, String> {
+    [
+        ("lh-1", "Lighthouse"),
+        ("lh-2", "Lighthouse"),
+        ("teku", "Teku"),
+    ]
+    .into_iter()
+    .map(|(name, label)| (name.to_string(), label.to_string()))
+    .collect()
+}
+
+#[test]
+fn two_closest_match() {
+    let classification = classify(
+        &[("lh-1", 3), ("lh-2", 4), ("teku", 5)],
+        &labels(),
+        2.0,
+        1.0,
+    )
+    .unwrap();
+    assert_eq!(
+        classification.verdict,
+        Verdict::TwoClosestMatch {
+            label: "Lighthouse".into(),
+            distance: 3
+        }
+    );
+}
+
+#[test]
+fn missing_label_is_an_error() {
+    // Node removed from the config after its block was stored.
+    let result = classify(&[("prysm", 1), ("teku", 5)], &labels(), 2.0, 1.0);
+    assert_eq!(result.unwrap_err(), "no label for node prysm");
+}
+
+#[test]
+fn no_distances_is_an_error() {
+    assert!(classify(&[], &labels(), 2.0, 1.0).is_err());
+}
+
+#[test]
+fn significance_ratio() {
+    let distances = [("teku", 10), ("lh-1", 15)];
+    let classification = classify(&distances, &labels(), 1.5, 1.0).unwrap();
+    assert!(matches!(
+        classification.verdict,
+        Verdict::SignificantlyCloser { .. }
+    ));
+    let classification = classify(&distances, &labels(), 2.0, 1.0).unwrap();
+    assert!(matches!(
+        classification.verdict,
+        Verdict::TooCloseToCall { .. }
+    ));
+}
@@ -0,0 +1,39 @@
+use crate::clock::{current_slot, time_into_slot, time_until_offset, ManualTicker, Ticker};
+use eth2::types::Slot;
+use slot_clock::{ManualSlotClock, SlotClock};
+use std::time::Duration;
+
+const GENESIS: Duration = Duration::from_secs(1_000);
+const SLOT_DURATION: Duration = Duration::from_secs(12);
+
+#[tokio::test]
+async fn manual_ticker_advances_on_demand() {
+    let ticker = ManualTicker::new(ManualSlotClock::new(Slot::new(0), GENESIS, SLOT_DURATION));
+    let slot_clock = ticker.slot_clock(Slot::new(0), Duration::ZERO, Duration::ZERO);
+    slot_clock.set_slot(5);
+
+    // No tick is pending until the ticker is advanced.
+    let pending = tokio::time::timeout(Duration::from_millis(10), ticker.tick(&slot_clock)).await;
+    assert!(pending.is_err());
+
+    ticker.advance_slot();
+    ticker.tick(&slot_clock).await;
+    assert_eq!(current_slot(&slot_clock), Ok(Slot::new(6)));
+    assert_eq!(time_into_slot(&slot_clock), Ok(Duration::ZERO));
+}
+
+#[test]
+fn wait_until_request_offset() {
+    let slot_clock = ManualSlotClock::new(Slot::new(0), GENESIS, SLOT_DURATION);
+    let offset = Duration::from_secs(4);
+
+    slot_clock.set_current_time(GENESIS + SLOT_DURATION * 2 + Duration::from_millis(500));
+    assert_eq!(
+        time_until_offset(&slot_clock, offset),
+        Ok(Duration::from_millis(3_500))
+    );
+
+    // Already past the offset, e.g. after a slow loop iteration.
+    slot_clock.set_current_time(GENESIS + SLOT_DURATION * 2 + Duration::from_secs(5));
+    assert_eq!(time_until_offset(&slot_clock, offset), Ok(Duration::ZERO));
+}
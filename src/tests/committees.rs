@@ -0,0 +1,33 @@
+use crate::committees::validator_differences;
+use eth2::types::{AttestationData, Checkpoint, Epoch, Hash256, Slot};
+use std::collections::{BTreeSet, HashMap};
+
+fn data(index: u64) -> AttestationData {
+    let checkpoint = Checkpoint {
+        epoch: Epoch::new(0),
+        root: Hash256::zero(),
+    };
+    AttestationData {
+        slot: Slot::new(1),
+        index,
+        beacon_block_root: Hash256::zero(),
+        source: checkpoint,
+        target: checkpoint,
+    }
+}
+
+#[test]
+fn differences_only_for_matched_data() {
+    let left = HashMap::from([
+        (data(0), BTreeSet::from([1, 2, 3])),
+        (data(1), BTreeSet::from([10, 11])),
+    ]);
+    let right = HashMap::from([
+        (data(0), BTreeSet::from([2, 3, 4, 5])),
+        (data(2), BTreeSet::from([20])),
+    ]);
+
+    let (only_left, only_right) = validator_differences(&left, &right);
+    assert_eq!(only_left, BTreeSet::from([1]));
+    assert_eq!(only_right, BTreeSet::from([4, 5]));
+}
@@ -0,0 +1,239 @@
+use crate::config::{AdaptiveTimeoutConfig, Config, DivergenceAggregation, RequestHeaders};
+use crate::distance::CompareScope;
+use crate::percentiles::DistancePercentilesConfig;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Config where the canonical node is also one of the dreaming nodes (modulo a trailing slash).
+const CANONICAL_OVERLAP_CONFIG: &str = r#"
+network = "mainnet"
+canonical_bn = "http://localhost:5052/"
+
+[[nodes]]
+name = "lighthouse"
+label = "Lighthouse"
+url = "http://localhost:5052"
+
+[[nodes]]
+name = "teku"
+label = "Teku"
+url = "http://localhost:5051"
+"#;
+
+/// Temporary file that is removed when dropped, even if an assertion fails.
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(name: &str, contents: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("blockdreamer-{name}-{}.toml", Uuid::new_v4()));
+        std::fs::write(&path, contents).unwrap();
+        Self(path)
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn canonical_overlap_warns_by_default() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn canonical_overlap_rejected() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.reject_canonical_node = true;
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("lighthouse"), "{err}");
+}
+
+#[test]
+fn canonical_overlap_ignores_disabled_nodes() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.reject_canonical_node = true;
+    std::sync::Arc::get_mut(&mut config.nodes[0])
+        .unwrap()
+        .enabled = false;
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn compare_builder_boost_rejects_boost_factors() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.compare_builder_boost = true;
+    assert_eq!(config.validate(), Ok(()));
+
+    std::sync::Arc::get_mut(&mut config.nodes[1])
+        .unwrap()
+        .builder_boost_factor = Some(100);
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("teku"), "{err}");
+}
+
+#[test]
+fn divergence_aggregation() {
+    let distances = [4, 1, 10, 3];
+    assert_eq!(DivergenceAggregation::Mean.aggregate(&distances), Some(4.5));
+    assert_eq!(
+        DivergenceAggregation::Median.aggregate(&distances),
+        Some(3.5)
+    );
+    assert_eq!(DivergenceAggregation::Max.aggregate(&distances), Some(10.0));
+    assert_eq!(
+        DivergenceAggregation::Median.aggregate(&[4, 1, 10]),
+        Some(4.0)
+    );
+    assert_eq!(DivergenceAggregation::Mean.aggregate(&[]), None);
+}
+
+#[test]
+fn sensitive_headers_are_redacted() {
+    let headers = RequestHeaders(
+        [
+            ("Authorization", "Bearer hunter2"),
+            ("X-Api-Key", "hunter3"),
+            ("X-Client-Hint", "lighthouse"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect(),
+    );
+    let debug = format!("{headers:?}");
+    assert!(!debug.contains("hunter"), "{debug}");
+    assert!(debug.contains("lighthouse"), "{debug}");
+    assert_eq!(headers.header_map().unwrap().len(), 3);
+}
+
+#[test]
+fn consensus_only_scope_rejects_execution_weight() {
+    let config =
+        format!("{CANONICAL_OVERLAP_CONFIG}\n[distance]\ncompare_scope = \"consensus_only\"\n");
+    let mut config: Config = toml::from_str(&config).unwrap();
+    assert_eq!(config.distance.compare_scope, CompareScope::ConsensusOnly);
+    assert_eq!(config.validate(), Ok(()));
+
+    config.distance.execution_weight = 0.5;
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("consensus_only"), "{err}");
+}
+
+#[test]
+fn canonicalize_rejects_max_attestations() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.distance.canonicalize = true;
+    assert_eq!(config.validate(), Ok(()));
+
+    config.distance.max_attestations = 64;
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("canonicalize"), "{err}");
+}
+
+#[test]
+fn node_timeout_conflicts_with_adaptive_timeout() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    Arc::get_mut(&mut config.nodes[1]).unwrap().timeout_secs = Some(10);
+    assert_eq!(config.validate(), Ok(()));
+
+    config.adaptive_timeout = Some(AdaptiveTimeoutConfig {
+        base_ms: 1000,
+        ms_per_kib: 10.0,
+        max_ms: 6000,
+    });
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("adaptive_timeout"), "{err}");
+
+    config.adaptive_timeout = None;
+    Arc::get_mut(&mut config.nodes[1]).unwrap().timeout_secs = Some(0);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn invalid_adaptive_timeout_rejected() {
+    let valid = AdaptiveTimeoutConfig {
+        base_ms: 1000,
+        ms_per_kib: 10.0,
+        max_ms: 6000,
+    };
+    assert_eq!(valid.validate(), Ok(()));
+    for ms_per_kib in [-1.0, f64::NAN, f64::INFINITY] {
+        let adaptive = AdaptiveTimeoutConfig {
+            ms_per_kib,
+            ..valid
+        };
+        assert!(adaptive.validate().is_err(), "{ms_per_kib}");
+    }
+    let adaptive = AdaptiveTimeoutConfig {
+        base_ms: 7000,
+        ..valid
+    };
+    let err = adaptive.validate().unwrap_err();
+    assert!(err.contains("max_ms"), "{err}");
+}
+
+#[test]
+fn run_id_defaults_to_network() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    assert_eq!(config.default_run_id(), "mainnet");
+    config.network = None;
+    config.network_dir = Some("/etc/blockdreamer/holesky".into());
+    assert_eq!(config.default_run_id(), "holesky");
+
+    config.run_id = Some("run".into());
+    config.random_run_id = true;
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("random_run_id"), "{err}");
+}
+
+#[test]
+fn empty_percentile_window_rejected() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.distance_percentiles = Some(DistancePercentilesConfig {
+        window_slots: 0,
+        log_interval_slots: 300,
+    });
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("window_slots"), "{err}");
+}
+
+#[test]
+fn labels_file_supplements_and_overrides_labels() {
+    let labels_file = TempFile::new(
+        "labels",
+        "lighthouse = \"Lighthouse\"\nteku = \"Teku-v2\"\n",
+    );
+
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    config.labels_file = Some(labels_file.0.clone());
+    Arc::get_mut(&mut config.nodes[0]).unwrap().label = String::new();
+    let err = config.apply_labels_file().unwrap_err();
+    assert!(err.contains("teku"), "{err}");
+    // The conflict leaves the other labels untouched.
+    assert_eq!(config.nodes[0].label, "");
+
+    config.labels_file_overrides = true;
+    config.apply_labels_file().unwrap();
+    assert_eq!(config.nodes[0].label, "Lighthouse");
+    assert_eq!(config.nodes[1].label, "Teku-v2");
+    assert_eq!(config.validate(), Ok(()));
+}
+
+#[test]
+fn node_without_label_rejected() {
+    let mut config: Config = toml::from_str(CANONICAL_OVERLAP_CONFIG).unwrap();
+    Arc::get_mut(&mut config.nodes[1]).unwrap().label = String::new();
+    let err = config.validate().unwrap_err();
+    assert!(err.contains("teku"), "{err}");
+}
+
+#[test]
+fn malformed_config_file_is_an_error() {
+    let config_file = TempFile::new("config", "network = \n");
+    let err = Config::from_file(&config_file.0).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,72 @@
+use crate::distance::{majority_attestations, EpochDistribution};
+use eth2::types::{
+    AggregateSignature, Attestation, AttestationData, BitList, Checkpoint, Epoch, Hash256,
+    MainnetEthSpec, Slot,
+};
+
+type E = MainnetEthSpec;
+
+fn attestation(index: u64, bits: &[usize]) -> Attestation<E> {
+    let checkpoint = Checkpoint {
+        epoch: Epoch::new(0),
+        root: Hash256::zero(),
+    };
+    let mut aggregation_bits = BitList::with_capacity(8).unwrap();
+    for bit in bits {
+        aggregation_bits.set(*bit, true).unwrap();
+    }
+    Attestation {
+        aggregation_bits,
+        data: AttestationData {
+            slot: Slot::new(1),
+            index,
+            beacon_block_root: Hash256::zero(),
+            source: checkpoint,
+            target: checkpoint,
+        },
+        signature: AggregateSignature::empty(),
+    }
+}
+
+#[test]
+fn majority_of_three() {
+    let a = attestation(0, &[0, 1]);
+    let b = attestation(1, &[2]);
+    let c = attestation(2, &[3, 4]);
+
+    let set1 = vec![a.clone(), b.clone()];
+    let set2 = vec![b.clone(), a.clone(), c.clone()];
+    let set3 = vec![c.clone()];
+
+    let consensus = majority_attestations::<E>(&[&set1, &set2, &set3]);
+    assert_eq!(consensus, vec![a, b, c]);
+}
+
+#[test]
+fn duplicates_within_a_block_count_once() {
+    let a = attestation(0, &[0]);
+    let b = attestation(0, &[1]);
+
+    let set1 = vec![a.clone(), a.clone()];
+    let set2 = vec![b.clone()];
+
+    assert_eq!(majority_attestations::<E>(&[&set1, &set2]), vec![]);
+}
+
+#[test]
+fn epoch_distribution_distance() {
+    let a = attestation(0, &[0]);
+    let b = attestation(1, &[1]);
+    let mut newer = attestation(2, &[2]);
+    newer.data.target.epoch = Epoch::new(1);
+
+    let distribution1 = EpochDistribution::new::<E>(&[a.clone(), b]);
+    let distribution2 = EpochDistribution::new::<E>(&[a, newer]);
+    assert_eq!(distribution2.target.get(&1), Some(&1));
+    assert_eq!(distribution2.source.get(&0), Some(&2));
+
+    // Only the target epochs differ: one attestation moves from epoch 0 to epoch 1.
+    assert_eq!(distribution1.distance(&distribution2), 2);
+    assert_eq!(distribution2.distance(&distribution1), 2);
+    assert_eq!(distribution1.distance(&distribution1), 0);
+}
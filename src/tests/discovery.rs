@@ -0,0 +1,118 @@
+use crate::config::Node as NodeConfig;
+use crate::discovery::CatalogEntry;
+use crate::metrics::Metrics;
+use crate::node::Node;
+use crate::reconcile_discovered_nodes;
+use eth2::types::ChainSpec;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+const CATALOG: &str = r#"[
+    {
+        "ID": "40e4a748-2192-161a-0510-9bf59fe950b5",
+        "Node": "node-1",
+        "Address": "10.0.0.1",
+        "ServiceID": "lighthouse-1",
+        "ServiceName": "beacon-node",
+        "ServiceAddress": "",
+        "ServicePort": 5052,
+        "ServiceTags": ["label=Lighthouse", "v3"]
+    },
+    {
+        "Node": "node-2",
+        "Address": "10.0.0.2",
+        "ServiceID": "teku-1",
+        "ServiceAddress": "10.1.0.2",
+        "ServicePort": 5051,
+        "ServiceTags": ["label=Teku"]
+    },
+    {
+        "Node": "node-3",
+        "Address": "10.0.0.3",
+        "ServiceID": "unlabelled",
+        "ServicePort": 5052
+    }
+]"#;
+
+#[test]
+fn catalog_entries_to_node_configs() {
+    let entries: Vec<CatalogEntry> = serde_json::from_str(CATALOG).unwrap();
+
+    let lighthouse = entries[0].node_config().unwrap();
+    assert_eq!(lighthouse.name, "lighthouse-1");
+    assert_eq!(lighthouse.label, "Lighthouse");
+    assert_eq!(lighthouse.url, "http://10.0.0.1:5052");
+    assert!(lighthouse.v3);
+    assert!(lighthouse.ssz);
+    assert!(lighthouse.enabled);
+
+    // The service address takes precedence over the node's address.
+    let teku = entries[1].node_config().unwrap();
+    assert_eq!(teku.url, "http://10.1.0.2:5051");
+    assert!(!teku.v3);
+
+    assert!(entries[2].node_config().is_err());
+}
+
+fn node_config(name: &str, label: &str) -> NodeConfig {
+    // Nothing listens on port 1, so fetching the version fails straight away.
+    toml::from_str(&format!(
+        r#"
+        name = "{name}"
+        label = "{label}"
+        url = "http://127.0.0.1:1"
+        "#
+    ))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn discovered_nodes_are_reconciled() {
+    let spec = Arc::new(ChainSpec::mainnet());
+    let metrics = Metrics::new().unwrap();
+    let new_node = |config| Node::new(config, spec.clone(), None, None);
+    let static_names = HashSet::from(["static".to_string()]);
+    let mut nodes = vec![new_node(Arc::new(node_config("static", "Static"))).unwrap()];
+    let mut labels = HashMap::from([("static".to_string(), "Static".to_string())]);
+    let names = |nodes: &[Node]| {
+        nodes
+            .iter()
+            .map(|node| node.config.name.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let discovered = vec![
+        node_config("a", "A"),
+        node_config("b", "B"),
+        // Static nodes can't be replaced by discovered ones.
+        node_config("static", "Other"),
+    ];
+    reconcile_discovered_nodes(
+        &mut nodes,
+        &mut labels,
+        &static_names,
+        &metrics,
+        discovered,
+        new_node,
+    )
+    .await;
+    assert_eq!(names(&nodes), ["static", "a", "b"]);
+    assert_eq!(labels["static"], "Static");
+    assert_eq!(labels["b"], "B");
+
+    // `a` went away and `b` was relabelled.
+    let discovered = vec![node_config("b", "B2")];
+    reconcile_discovered_nodes(
+        &mut nodes,
+        &mut labels,
+        &static_names,
+        &metrics,
+        discovered,
+        new_node,
+    )
+    .await;
+    assert_eq!(names(&nodes), ["static", "b"]);
+    assert_eq!(nodes[1].config.label, "B2");
+    assert_eq!(labels.len(), 2);
+    assert_eq!(labels["b"], "B2");
+}
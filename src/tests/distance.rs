@@ -0,0 +1,316 @@
+use crate::distance::{
+    canonicalized_block, CompareScope, Distance, DistanceBreakdown, DistanceParams, ExecutionDelta,
+};
+use eth2::types::{
+    Address, AggregateSignature, Attestation, AttestationData, AttesterSlashing, BeaconBlock,
+    BeaconBlockAltair, BeaconBlockBase, BeaconBlockBellatrix, BeaconBlockCapella, BitList,
+    BlindedPayload, BlsToExecutionChange, ChainSpec, Checkpoint, Epoch, Hash256,
+    IndexedAttestation, MainnetEthSpec, PublicKeyBytes, Signature, SignedBlsToExecutionChange,
+    SignedVoluntaryExit, Slot, VoluntaryExit,
+};
+
+type E = MainnetEthSpec;
+type Block = BeaconBlock<E, BlindedPayload<E>>;
+
+fn base_block() -> Block {
+    BeaconBlock::Base(BeaconBlockBase::empty(&ChainSpec::mainnet()))
+}
+
+fn altair_block(sync_bits: &[usize]) -> Block {
+    let mut block = BeaconBlock::Altair(BeaconBlockAltair::empty(&ChainSpec::mainnet()));
+    let mut body = block.body_mut();
+    let sync_aggregate = body.sync_aggregate_mut().unwrap();
+    for i in sync_bits {
+        sync_aggregate.sync_committee_bits.set(*i, true).unwrap();
+    }
+    block
+}
+
+fn bellatrix_block(transactions_root: u8, gas_used: u64, extra_data: &[u8]) -> Block {
+    let mut inner = BeaconBlockBellatrix::empty(&ChainSpec::mainnet());
+    let header = &mut inner.body.execution_payload.execution_payload_header;
+    header.transactions_root = Hash256::repeat_byte(transactions_root);
+    header.gas_used = gas_used;
+    header.extra_data = extra_data.to_vec().into();
+    BeaconBlock::Bellatrix(inner)
+}
+
+fn base_block_with_exits(validators: &[u64]) -> Block {
+    let mut inner = BeaconBlockBase::empty(&ChainSpec::mainnet());
+    inner.body.voluntary_exits = validators
+        .iter()
+        .map(|validator_index| SignedVoluntaryExit {
+            message: VoluntaryExit {
+                epoch: Epoch::new(0),
+                validator_index: *validator_index,
+            },
+            signature: Signature::empty(),
+        })
+        .collect::<Vec<_>>()
+        .into();
+    BeaconBlock::Base(inner)
+}
+
+fn capella_block_with_bls_changes(validators: &[u64]) -> Block {
+    let mut inner = BeaconBlockCapella::empty(&ChainSpec::mainnet());
+    inner.body.bls_to_execution_changes = validators
+        .iter()
+        .map(|validator_index| SignedBlsToExecutionChange {
+            message: BlsToExecutionChange {
+                validator_index: *validator_index,
+                from_bls_pubkey: PublicKeyBytes::empty(),
+                to_execution_address: Address::zero(),
+            },
+            signature: Signature::empty(),
+        })
+        .collect::<Vec<_>>()
+        .into();
+    BeaconBlock::Capella(inner)
+}
+
+/// An attestation to `slot` by the first member of committee `index`.
+fn attestation(slot: u64, index: u64) -> Attestation<E> {
+    let checkpoint = Checkpoint {
+        epoch: Epoch::new(0),
+        root: Hash256::zero(),
+    };
+    let mut aggregation_bits = BitList::with_capacity(4).unwrap();
+    aggregation_bits.set(0, true).unwrap();
+    Attestation {
+        aggregation_bits,
+        data: AttestationData {
+            slot: Slot::new(slot),
+            index,
+            beacon_block_root: Hash256::zero(),
+            source: checkpoint,
+            target: checkpoint,
+        },
+        signature: AggregateSignature::empty(),
+    }
+}
+
+fn base_block_with_attestations(slot: u64, attestations: Vec<Attestation<E>>) -> Block {
+    let mut inner = BeaconBlockBase::empty(&ChainSpec::mainnet());
+    inner.slot = Slot::new(slot);
+    inner.body.attestations = attestations.into();
+    BeaconBlock::Base(inner)
+}
+
+fn attester_slashing(indices_1: &[u64], indices_2: &[u64]) -> AttesterSlashing<E> {
+    let attestation = |indices: &[u64], root: u8| {
+        let checkpoint = Checkpoint {
+            epoch: Epoch::new(0),
+            root: Hash256::zero(),
+        };
+        IndexedAttestation {
+            attesting_indices: indices.to_vec().into(),
+            data: AttestationData {
+                slot: Slot::new(1),
+                index: 0,
+                beacon_block_root: Hash256::repeat_byte(root),
+                source: checkpoint,
+                target: checkpoint,
+            },
+            signature: AggregateSignature::empty(),
+        }
+    };
+    AttesterSlashing {
+        attestation_1: attestation(indices_1, 1),
+        attestation_2: attestation(indices_2, 2),
+    }
+}
+
+#[test]
+fn sync_aggregate_distance_is_opt_in() {
+    let (block1, block2) = (altair_block(&[0, 1, 2]), altair_block(&[1, 2, 3, 4]));
+    let params = DistanceParams::default();
+    let delta = block1.delta(&block2, &params).unwrap();
+    assert_eq!(delta.sync_aggregate, Some(3));
+    assert_eq!(block1.distance(&block2, &params), Some(0));
+
+    let params = DistanceParams {
+        sync_weight: 2.0,
+        ..DistanceParams::default()
+    };
+    assert_eq!(block1.distance(&block2, &params), Some(6));
+}
+
+#[test]
+fn no_sync_aggregate_distance_before_altair() {
+    let params = DistanceParams {
+        sync_weight: 1.0,
+        ..DistanceParams::default()
+    };
+    let (base, altair) = (base_block(), altair_block(&[0, 1]));
+    assert_eq!(base.delta(&base, &params).unwrap().sync_aggregate, None);
+    assert_eq!(base.delta(&altair, &params).unwrap().sync_aggregate, None);
+    assert_eq!(base.distance(&base, &params), Some(0));
+}
+
+#[test]
+fn execution_delta() {
+    let params = DistanceParams {
+        execution_weight: 1.0,
+        ..DistanceParams::default()
+    };
+    let block1 = bellatrix_block(1, 1_000_000, b"abcd");
+    let block2 = bellatrix_block(2, 1_250_000, b"abxdef");
+    let expected = ExecutionDelta {
+        transactions_root_mismatch: true,
+        gas_used_diff: 250_000,
+        // One differing byte plus two extra ones.
+        extra_data_distance: 3,
+        distance: 1 + 2 + 3,
+    };
+    let delta = block1.delta(&block2, &params).unwrap();
+    assert_eq!(delta.execution, Some(expected));
+    assert_eq!(block1.distance(&block2, &params), Some(6));
+
+    let delta = block1.delta(&block1, &params).unwrap();
+    assert_eq!(delta.execution.unwrap().distance, 0);
+}
+
+#[test]
+fn no_execution_delta_for_consensus_only() {
+    let params = DistanceParams {
+        compare_scope: CompareScope::ConsensusOnly,
+        ..DistanceParams::default()
+    };
+    let block1 = bellatrix_block(1, 1_000_000, b"abcd");
+    let block2 = bellatrix_block(2, 2_000_000, b"");
+    assert_eq!(block1.delta(&block2, &params).unwrap().execution, None);
+    assert_eq!(block1.distance(&block2, &params), Some(0));
+}
+
+#[test]
+fn breakdown_sums_to_distance() {
+    let params = DistanceParams {
+        execution_weight: 2.0,
+        sync_weight: 1.0,
+        ..DistanceParams::default()
+    };
+    let mut block1 = bellatrix_block(1, 1_000_000, b"abcd");
+    block1
+        .body_mut()
+        .sync_aggregate_mut()
+        .unwrap()
+        .sync_committee_bits
+        .set(0, true)
+        .unwrap();
+    let block2 = bellatrix_block(2, 1_250_000, b"abxdef");
+
+    let breakdown = block1
+        .delta(&block2, &params)
+        .unwrap()
+        .breakdown::<E>(&params);
+    assert_eq!(
+        breakdown,
+        DistanceBreakdown {
+            attestations: 0,
+            sync_aggregate: 1,
+            execution: 12,
+            operations: 0,
+        }
+    );
+    assert_eq!(block1.distance(&block2, &params), Some(breakdown.total()));
+}
+
+#[test]
+fn committee_index_can_be_ignored() {
+    let block1 = base_block_with_attestations(10, vec![attestation(9, 0)]);
+    let block2 = base_block_with_attestations(10, vec![attestation(9, 3)]);
+    let params = DistanceParams::default();
+    assert_ne!(block1.distance(&block2, &params), Some(0));
+
+    let params = DistanceParams {
+        ignore_committee_index: true,
+        ..DistanceParams::default()
+    };
+    assert_eq!(block1.distance(&block2, &params), Some(0));
+}
+
+#[test]
+fn recent_attestations_can_be_excluded() {
+    let block1 = base_block_with_attestations(10, vec![attestation(5, 0), attestation(9, 0)]);
+    let block2 = base_block_with_attestations(10, vec![attestation(5, 0)]);
+    let params = DistanceParams::default();
+    assert_ne!(block1.distance(&block2, &params), Some(0));
+
+    // The attestation from slot 9 is too recent to compare, the one from slot 5 isn't.
+    let params = DistanceParams {
+        exclude_recent_attestations: 2,
+        ..DistanceParams::default()
+    };
+    assert_eq!(block1.distance(&block2, &params), Some(0));
+    let block3 = base_block_with_attestations(10, vec![]);
+    assert_ne!(block1.distance(&block3, &params), Some(0));
+}
+
+#[test]
+fn attester_slashings_match_on_slashed_validators() {
+    let params = DistanceParams::default();
+    // Both slash validators 2 and 3, from different aggregates.
+    let slashings1 = [attester_slashing(&[1, 2, 3], &[2, 3, 4])];
+    let slashings2 = [attester_slashing(&[2, 3], &[2, 3, 5])];
+    assert_eq!((&slashings1[..]).delta(&&slashings2[..], &params), Some(0));
+
+    let slashings3 = [attester_slashing(&[6], &[6])];
+    assert_eq!((&slashings1[..]).delta(&&slashings3[..], &params), Some(2));
+}
+
+#[test]
+fn operations_distance_scales_with_indel_cost() {
+    let (block1, block2) = (
+        base_block_with_exits(&[1, 2]),
+        base_block_with_exits(&[2, 3]),
+    );
+    for indel_cost in [1, 5] {
+        let params = DistanceParams {
+            indel_cost,
+            ..DistanceParams::default()
+        };
+        let delta = block1.delta(&block2, &params).unwrap();
+        assert_eq!(delta.operations.voluntary_exits, 2);
+        assert_eq!(delta.breakdown::<E>(&params).operations, 2 * indel_cost);
+    }
+}
+
+#[test]
+fn no_bls_changes_before_capella() {
+    let params = DistanceParams::default();
+    let (block1, block2) = (bellatrix_block(0, 0, b""), bellatrix_block(0, 0, b""));
+    let delta = block1.delta(&block2, &params).unwrap();
+    assert_eq!(delta.operations.bls_to_execution_changes, 0);
+
+    let (block1, block2) = (
+        capella_block_with_bls_changes(&[1]),
+        capella_block_with_bls_changes(&[]),
+    );
+    let delta = block1.delta(&block2, &params).unwrap();
+    assert_eq!(delta.operations.bls_to_execution_changes, 1);
+}
+
+#[test]
+fn permuted_bodies_are_identical_once_canonicalized() {
+    let params = DistanceParams {
+        canonicalize: true,
+        ..DistanceParams::default()
+    };
+    let (block1, block2) = (
+        base_block_with_exits(&[1, 2, 3]),
+        base_block_with_exits(&[3, 1, 2]),
+    );
+    assert_ne!(block1.canonical_root(), block2.canonical_root());
+
+    let (block1, block2) = (
+        canonicalized_block(&block1, &params),
+        canonicalized_block(&block2, &params),
+    );
+    assert_eq!(block1.canonical_root(), block2.canonical_root());
+    assert_eq!(block1.distance(&block2, &params), Some(0));
+
+    // Blocks are left alone unless configured.
+    let block3 = base_block_with_exits(&[3, 1, 2]);
+    let unchanged = canonicalized_block(&block3, &DistanceParams::default());
+    assert_eq!(unchanged.canonical_root(), block3.canonical_root());
+}
@@ -0,0 +1,66 @@
+use crate::distance::DistanceParams;
+use crate::distance_expr::{DistanceComponents, DistanceExpression};
+
+fn components() -> DistanceComponents {
+    DistanceComponents {
+        att: 100,
+        sync: 10,
+        payload: 3,
+        slashings: 1,
+        deposits: 0,
+        exits: 2,
+        bls_changes: 0,
+        ops: 384,
+    }
+}
+
+fn evaluate(source: &str) -> usize {
+    DistanceExpression::parse(source)
+        .unwrap()
+        .evaluate(&components())
+}
+
+#[test]
+fn precedence_and_parentheses() {
+    assert_eq!(evaluate("att + 2 * sync"), 120);
+    assert_eq!(evaluate("(att + 2) * sync"), 1020);
+    assert_eq!(evaluate("att - sync - payload"), 87);
+    assert_eq!(evaluate("att / 4 / 5"), 5);
+    assert_eq!(evaluate("-sync + att"), 90);
+}
+
+#[test]
+fn functions_rounding_and_clamping() {
+    assert_eq!(evaluate("max(ops, 1000 * slashings) + min(exits, 1)"), 1001);
+    assert_eq!(evaluate("0.25 * payload"), 1);
+    assert_eq!(evaluate("sync - att"), 0);
+    assert_eq!(evaluate("att / deposits"), 0);
+}
+
+#[test]
+fn invalid_expressions() {
+    for source in [
+        "", "att +", "2 * (att", "att sync", "min(att)", "att % 2", "1..2",
+    ] {
+        assert!(DistanceExpression::parse(source).is_err(), "{source:?}");
+    }
+    let err = DistanceExpression::parse("att + attestations").unwrap_err();
+    assert!(err.contains("unknown variable `attestations`"), "{err}");
+    assert!(err.contains("bls_changes"), "{err}");
+}
+
+#[test]
+fn expression_in_distance_params() {
+    let params: DistanceParams = toml::from_str(r#"expression = "att + 4 * sync""#).unwrap();
+    assert_eq!(params.expression.unwrap().evaluate(&components()), 140);
+    assert!(toml::from_str::<DistanceParams>(r#"expression = "att + bogus""#).is_err());
+}
+
+#[test]
+fn deep_nesting_rejected() {
+    let nested = |depth: usize| format!("{}att{}", "(".repeat(depth), ")".repeat(depth));
+    assert_eq!(evaluate(&nested(16)), 100);
+    let err = DistanceExpression::parse(&nested(100)).unwrap_err();
+    assert!(err.contains("nested more than"), "{err}");
+    assert!(DistanceExpression::parse(&format!("{}att", "-".repeat(100))).is_err());
+}
@@ -0,0 +1,18 @@
+use crate::fingerprint::Fingerprint;
+use eth2::types::{BeaconBlock, BlindedPayload, ChainSpec, MainnetEthSpec, Uint256};
+
+type E = MainnetEthSpec;
+
+#[test]
+fn empty_block_fingerprint() {
+    let spec = ChainSpec::mainnet();
+    let block = BeaconBlock::<E, BlindedPayload<E>>::empty(&spec);
+    let fingerprint = Fingerprint::new(&block, Some(Uint256::from(1234u64)));
+
+    assert_eq!(fingerprint.num_attestations, 0);
+    assert_eq!(fingerprint.total_set_bits, 0);
+    assert_eq!(fingerprint.parent_root, block.parent_root());
+    assert_eq!(fingerprint.block_root, block.canonical_root());
+    assert_eq!(fingerprint.sync_aggregate_participation, None);
+    assert_eq!(fingerprint.reported_reward.as_deref(), Some("1234"));
+}
@@ -0,0 +1,35 @@
+use crate::distance::fork_mismatch;
+use eth2::types::{
+    BeaconBlock, BeaconBlockAltair, BeaconBlockBase, BlindedPayload, ChainSpec, Epoch, ForkName,
+    MainnetEthSpec, Slot,
+};
+
+type E = MainnetEthSpec;
+
+#[test]
+fn blocks_either_side_of_a_fork_boundary() {
+    let mut spec = ChainSpec::mainnet();
+    spec.altair_fork_epoch = Some(Epoch::new(1));
+
+    let mut last_base = BeaconBlock::Base(BeaconBlockBase::<E, BlindedPayload<E>>::empty(&spec));
+    *last_base.slot_mut() = Slot::new(31);
+    let mut first_altair =
+        BeaconBlock::Altair(BeaconBlockAltair::<E, BlindedPayload<E>>::empty(&spec));
+    *first_altair.slot_mut() = Slot::new(32);
+    let mut second_altair = first_altair.clone();
+    *second_altair.slot_mut() = Slot::new(33);
+
+    assert_eq!(
+        fork_mismatch(&last_base, &first_altair, &spec),
+        Some((ForkName::Base, ForkName::Altair))
+    );
+    assert_eq!(fork_mismatch(&first_altair, &second_altair, &spec), None);
+
+    // An Altair block produced by a node that thinks the fork has already happened.
+    let mut early_altair = first_altair.clone();
+    *early_altair.slot_mut() = Slot::new(31);
+    assert_eq!(
+        fork_mismatch(&early_altair, &last_base, &spec),
+        Some((ForkName::Altair, ForkName::Base))
+    );
+}
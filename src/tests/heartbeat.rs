@@ -0,0 +1,34 @@
+use crate::heartbeat::Heartbeat;
+use std::time::Duration;
+
+#[test]
+fn not_ready_before_genesis() {
+    let heartbeat = Heartbeat::default();
+    heartbeat.record_slot();
+    heartbeat.record_block();
+    assert!(heartbeat.readiness().is_err());
+}
+
+#[test]
+fn ready_after_recent_progress() {
+    let heartbeat = Heartbeat::default();
+    heartbeat.genesis_resolved(Duration::from_secs(36));
+    assert!(heartbeat.readiness().is_err());
+
+    heartbeat.record_slot();
+    assert!(heartbeat.readiness().is_err());
+
+    heartbeat.record_block();
+    assert_eq!(heartbeat.readiness(), Ok(()));
+}
+
+#[test]
+fn stalled_loop_is_not_ready() {
+    let heartbeat = Heartbeat::default();
+    heartbeat.genesis_resolved(Duration::from_secs(36));
+    heartbeat.record_slot();
+    heartbeat.record_block();
+
+    let far_future = u64::MAX / 2;
+    assert!(heartbeat.readiness_at(far_future).is_err());
+}
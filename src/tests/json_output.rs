@@ -0,0 +1,61 @@
+use crate::json_output::JsonOutput;
+use eth2::types::Slot;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl SharedBuffer {
+    fn lines(&self) -> Vec<serde_json::Value> {
+        let buffer = self.0.lock().unwrap();
+        std::str::from_utf8(&buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
+
+fn slot_json(slot: u64) -> serde_json::Value {
+    serde_json::to_value(Slot::new(slot)).unwrap()
+}
+
+#[test]
+fn slots_are_written_once_canonical_is_compared() {
+    let buffer = SharedBuffer::default();
+    let mut output = JsonOutput::with_writer(Box::new(buffer.clone()));
+    let (a, b) = ("a".to_string(), "b".to_string());
+
+    output.set_pairs(Slot::new(10), &[(&a, &b, 5)]);
+    output.write_through(Slot::new(9));
+    assert!(buffer.lines().is_empty());
+
+    output.set_canonical(Slot::new(10), &[("a", 1), ("b", 4)]);
+    output.set_pairs(Slot::new(11), &[(&a, &b, 0)]);
+    output.write_through(Slot::new(10));
+    let lines = buffer.lines();
+    assert_eq!(lines.len(), 1);
+    assert_eq!(lines[0]["slot"], slot_json(10));
+    assert_eq!(lines[0]["distances"][0]["a"], "a");
+    assert_eq!(lines[0]["distances"][0]["distance"], 5);
+    assert_eq!(lines[0]["canonical"][1]["node"], "b");
+    assert_eq!(lines[0]["canonical"][1]["distance"], 4);
+
+    // Slots without a canonical comparison are written at shutdown.
+    output.write_all();
+    let lines = buffer.lines();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[1]["slot"], slot_json(11));
+    assert_eq!(lines[1]["canonical"], serde_json::json!([]));
+}
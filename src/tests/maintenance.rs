@@ -0,0 +1,52 @@
+use crate::maintenance::{active_window, MaintenanceWindow};
+use chrono::{DateTime, Utc};
+
+fn time(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time)
+        .unwrap()
+        .with_timezone(&Utc)
+}
+
+#[test]
+fn one_off_window() {
+    let window = MaintenanceWindow::parse("2024-03-05T02:00:00Z/2024-03-05T04:00:00Z").unwrap();
+    assert!(!window.contains(time("2024-03-05T01:59:59Z")));
+    assert!(window.contains(time("2024-03-05T02:00:00Z")));
+    assert!(!window.contains(time("2024-03-05T04:00:00Z")));
+    assert!(!window.contains(time("2024-03-06T03:00:00Z")));
+}
+
+#[test]
+fn recurring_window_spanning_midnight() {
+    // 2024-03-05 is a Tuesday.
+    let window = MaintenanceWindow::parse("Tue,Thu 23:30-00:30").unwrap();
+    assert!(window.contains(time("2024-03-05T23:45:00Z")));
+    assert!(window.contains(time("2024-03-06T00:15:00Z")));
+    assert!(!window.contains(time("2024-03-05T00:15:00Z")));
+    assert!(!window.contains(time("2024-03-06T23:45:00Z")));
+    assert!(window.contains(time("2024-03-07T23:45:00Z")));
+}
+
+#[test]
+fn active_window_of_several() {
+    let windows: Vec<MaintenanceWindow> = ["Mon 10:00-11:00", "02:00-02:30"]
+        .into_iter()
+        .map(|window| MaintenanceWindow::parse(window).unwrap())
+        .collect();
+    let active = active_window(&windows, time("2024-03-07T02:10:00Z")).unwrap();
+    assert_eq!(active.to_string(), "02:00-02:30");
+    assert_eq!(active_window(&windows, time("2024-03-07T10:10:00Z")), None);
+}
+
+#[test]
+fn invalid_windows() {
+    for window in [
+        "02:00",
+        "02:00-02:00",
+        "25:00-26:00",
+        "Funday 02:00-03:00",
+        "2024-03-05T04:00:00Z/2024-03-05T02:00:00Z",
+    ] {
+        assert!(MaintenanceWindow::parse(window).is_err(), "{window}");
+    }
+}
@@ -0,0 +1,36 @@
+use crate::matrix::{DistanceMatrix, LatestMatrix};
+use eth2::types::Slot;
+use std::collections::HashMap;
+
+#[test]
+fn matrix_is_symmetric() {
+    let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+    let labels = HashMap::from([(a.clone(), "Lighthouse".to_string())]);
+    let matrix = DistanceMatrix::new(
+        Slot::new(10),
+        &labels,
+        &[(&a, &b, 3), (&a, &c, 5), (&b, &c, 0)],
+        None,
+    );
+
+    assert_eq!(matrix.distances["a"]["b"], 3);
+    assert_eq!(matrix.distances["b"]["a"], 3);
+    assert_eq!(matrix.distances["c"]["a"], 5);
+    assert_eq!(matrix.distances["c"]["b"], 0);
+    assert!(!matrix.distances["a"].contains_key("a"));
+    assert_eq!(matrix.labels["a"], "Lighthouse");
+}
+
+#[test]
+fn latest_matrix_starts_empty() {
+    let latest = LatestMatrix::default();
+    assert!(latest.get().is_none());
+
+    latest.set(DistanceMatrix::new(
+        Slot::new(1),
+        &HashMap::new(),
+        &[],
+        None,
+    ));
+    assert_eq!(latest.get().unwrap().slot, Slot::new(1));
+}
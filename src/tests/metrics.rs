@@ -0,0 +1,47 @@
+use crate::classify::{Classification, Verdict};
+use crate::metrics::Metrics;
+
+#[test]
+fn canonical_distance_is_labelled_by_client_and_version() {
+    let metrics = Metrics::new().unwrap();
+    let labels = ["lh-1", "Lighthouse", "Lighthouse/v4.5.0"];
+    metrics.set_canonical_distance(&labels, 7);
+
+    let encoded = metrics.encode().unwrap();
+    assert!(encoded.contains(
+        r#"blockdreamer_canonical_distance{client="Lighthouse",node="lh-1",version="Lighthouse/v4.5.0"} 7"#
+    ));
+
+    metrics.remove_node(&labels);
+    assert!(!metrics.encode().unwrap().contains("lh-1"));
+}
+
+#[test]
+fn request_and_classification_counts() {
+    let metrics = Metrics::new().unwrap();
+    let labels = ["lh-1", "Lighthouse", "Lighthouse/v4.5.0"];
+    metrics.record_block_request(&labels, true);
+    metrics.record_block_request(&labels, true);
+    metrics.record_block_request(&labels, false);
+    metrics.record_classification(&Classification {
+        verdict: Verdict::TwoClosestMatch {
+            label: "Lighthouse".into(),
+            distance: 3,
+        },
+        confidence: 1.0,
+    });
+
+    let encoded = metrics.encode().unwrap();
+    assert!(encoded.contains(
+        r#"blockdreamer_block_requests_total{client="Lighthouse",node="lh-1",outcome="success",version="Lighthouse/v4.5.0"} 2"#
+    ));
+    assert!(encoded.contains(
+        r#"blockdreamer_block_requests_total{client="Lighthouse",node="lh-1",outcome="failure",version="Lighthouse/v4.5.0"} 1"#
+    ));
+    assert!(encoded.contains(
+        r#"blockdreamer_classifications_total{label="Lighthouse",verdict="two_closest_match"} 1"#
+    ));
+
+    metrics.remove_node(&labels);
+    assert!(!metrics.encode().unwrap().contains("lh-1"));
+}
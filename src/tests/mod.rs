@@ -1,2 +1,28 @@
 #![cfg(test)]
+mod alert;
+mod backoff;
+mod block_json;
+mod circuit_breaker;
+mod classify;
+mod clock;
+mod committees;
+mod config;
+mod consensus;
+mod discovery;
+mod distance;
+mod distance_expr;
+mod fingerprint;
+mod fork_mismatch;
+mod heartbeat;
+mod json_output;
+mod maintenance;
+mod matrix;
+mod metrics;
+mod node;
+mod parquet_sink;
+mod percentiles;
+mod post;
 mod proptest_distance;
+mod run;
+mod stale_block;
+mod verdict_socket;
@@ -0,0 +1,464 @@
+use crate::backoff::BackoffPolicy;
+use crate::config::{node_url, AdaptiveTimeoutConfig, FutureSlotHandling, Node as NodeConfig};
+use crate::node::{
+    canonical_randao_reveal, future_slot_error, unsigned_proposal_anomalies, with_query_params,
+    BlobSummary, Node,
+};
+use eth2::types::{
+    BeaconBlock, BeaconBlockCapella, BeaconBlockDeneb, BlindedBeaconBlock, Blob, BlockContents,
+    ChainSpec, FullBlockContents, KzgCommitment, KzgProof, MainnetEthSpec, Signature,
+    SignatureBytes, SignedBeaconBlock, SkipRandaoVerification, Slot,
+};
+use eth2::{BeaconNodeHttpClient, Timeouts};
+use sensitive_url::SensitiveUrl;
+use slot_clock::{ManualSlotClock, SlotClock};
+use std::fmt::Display;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use warp::Filter;
+
+/// Config for a node named "test" at `address`, followed by `extra_toml`.
+fn test_node_config(address: impl Display, extra_toml: &str) -> NodeConfig {
+    toml::from_str(&format!(
+        r#"
+        name = "test"
+        label = "Test"
+        url = "http://{address}"
+        {extra_toml}
+        "#
+    ))
+    .unwrap()
+}
+
+/// Node named "test" at `address`, with `extra_toml` added to its config.
+fn test_node(address: impl Display, extra_toml: &str) -> Node {
+    let config = test_node_config(address, extra_toml);
+    Node::new(Arc::new(config), Arc::new(ChainSpec::mainnet()), None, None).unwrap()
+}
+
+/// Push the path segments for block production onto `base`, as `BeaconNodeHttpClient` does.
+fn block_production_url(base: &str, path_prefix: Option<&str>) -> String {
+    let mut url = node_url(base, path_prefix).unwrap().full;
+    url.path_segments_mut()
+        .unwrap()
+        .extend(["eth", "v3", "validator", "blocks", "1"]);
+    url.to_string()
+}
+
+#[test]
+fn no_path_prefix() {
+    assert_eq!(
+        block_production_url("http://localhost:5052", None),
+        "http://localhost:5052/eth/v3/validator/blocks/1"
+    );
+}
+
+#[test]
+fn path_prefix() {
+    for prefix in ["beacon", "/beacon", "/beacon/"] {
+        assert_eq!(
+            block_production_url("http://localhost:5052", Some(prefix)),
+            "http://localhost:5052/beacon/eth/v3/validator/blocks/1"
+        );
+    }
+}
+
+#[test]
+fn path_prefix_appends_to_existing_path() {
+    assert_eq!(
+        block_production_url("http://gateway:8080/api/", Some("/beacon/")),
+        "http://gateway:8080/api/beacon/eth/v3/validator/blocks/1"
+    );
+}
+
+#[test]
+fn query_params_precede_client_params() {
+    let config = test_node_config(
+        "localhost:5052",
+        r#"
+        [query_params]
+        debug_mode = "1"
+        "#,
+    );
+    assert_eq!(config.check_query_params(), Ok(()));
+
+    let base = node_url(&config.url, None).unwrap();
+    let mut url = with_query_params(&base, &config.query_params).unwrap().full;
+    url.path_segments_mut()
+        .unwrap()
+        .extend(["eth", "v3", "validator", "blocks", "1"]);
+    url.query_pairs_mut().append_pair("randao_reveal", "0x00");
+    assert_eq!(
+        url.as_str(),
+        "http://localhost:5052/eth/v3/validator/blocks/1?debug_mode=1&randao_reveal=0x00"
+    );
+}
+
+#[test]
+fn invalid_query_params_are_rejected() {
+    for name in ["", "a b", "a&b", "randao_reveal", "builder_boost_factor"] {
+        let mut config = test_node_config("localhost:5052", "");
+        config
+            .query_params
+            .insert(name.to_string(), "1".to_string());
+        assert!(config.check_query_params().is_err(), "{name:?}");
+    }
+}
+
+#[test]
+fn node_timeout() {
+    for (extra_toml, expected) in [("", 6), ("timeout_secs = 3", 3)] {
+        let node = test_node("localhost:5052", extra_toml);
+        assert_eq!(node.request_timeout(), Duration::from_secs(expected));
+    }
+}
+
+#[test]
+fn adaptive_timeout_follows_block_size() {
+    let config = test_node_config("localhost:5052", "");
+    let adaptive = AdaptiveTimeoutConfig {
+        base_ms: 1000,
+        ms_per_kib: 10.0,
+        max_ms: 6000,
+    };
+    let node = Node::new(
+        Arc::new(config),
+        Arc::new(ChainSpec::mainnet()),
+        None,
+        Some(adaptive),
+    )
+    .unwrap();
+    let timeout_ms = || node.request_timeout().as_millis();
+    assert_eq!(timeout_ms(), 1000);
+
+    node.record_block_size(100 * 1024);
+    assert_eq!(timeout_ms(), 2000);
+    // Moving average of 100 KiB and 300 KiB.
+    node.record_block_size(300 * 1024);
+    assert_eq!(timeout_ms(), 2500);
+
+    // A timeout allows the next request up to `max_ms`, decaying as blocks arrive.
+    node.record_timeout();
+    assert_eq!(timeout_ms(), 6000);
+    node.record_block_size(0);
+    assert_eq!(timeout_ms(), 4750);
+
+    node.record_block_size(10 * 1024 * 1024);
+    assert_eq!(timeout_ms(), 6000);
+}
+
+#[tokio::test]
+async fn failed_requests_are_retried() {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let route = warp::any().map({
+        let requests = requests.clone();
+        move || {
+            requests.fetch_add(1, Ordering::Relaxed);
+            warp::reply::with_status("error", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let node = test_node(
+        address,
+        r#"
+        max_retries = 2
+        retry_backoff_ms = 1
+        "#,
+    );
+    let slot_duration = Duration::from_secs(12);
+    let slot_clock = ManualSlotClock::new(Slot::new(0), Duration::ZERO, slot_duration);
+    slot_clock.set_slot(1);
+
+    let result = node
+        .get_block_with_retries::<MainnetEthSpec, _>(
+            Slot::new(1),
+            None,
+            None,
+            &slot_clock,
+            BackoffPolicy::default(),
+        )
+        .await;
+    assert!(result.is_err());
+    assert_eq!(requests.load(Ordering::Relaxed), 3);
+
+    // No retries once the slot has ended.
+    requests.store(0, Ordering::Relaxed);
+    slot_clock.set_current_time(slot_duration * 2 - Duration::from_millis(1));
+    let result = node
+        .get_block_with_retries::<MainnetEthSpec, _>(
+            Slot::new(1),
+            None,
+            None,
+            &slot_clock,
+            BackoffPolicy::default(),
+        )
+        .await;
+    assert!(result.is_err());
+    assert_eq!(requests.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn v3_falls_back_to_v2_when_not_found() {
+    let v3_requests = Arc::new(AtomicUsize::new(0));
+    let v2_requests = Arc::new(AtomicUsize::new(0));
+    let counter = |requests: &Arc<AtomicUsize>, status| {
+        let requests = requests.clone();
+        move |_: u64| {
+            requests.fetch_add(1, Ordering::Relaxed);
+            warp::reply::with_status("error", status)
+        }
+    };
+    let v3 = warp::path!("eth" / "v3" / "validator" / "blocks" / u64)
+        .map(counter(&v3_requests, warp::http::StatusCode::NOT_FOUND));
+    // Fails too, but shows that the fallback was taken.
+    let v2 = warp::path!("eth" / "v2" / "validator" / "blocks" / u64).map(counter(
+        &v2_requests,
+        warp::http::StatusCode::INTERNAL_SERVER_ERROR,
+    ));
+    let (address, server) = warp::serve(v3.or(v2)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let node = test_node(
+        address,
+        r#"
+        v3 = true
+        v3_fallback = true
+        ssz = false
+        "#,
+    );
+    assert!(node.uses_v3());
+
+    for _ in 0..2 {
+        let result = node
+            .get_block_with_timeout::<MainnetEthSpec>(Slot::new(1), None, None)
+            .await;
+        assert!(result.is_err());
+    }
+    assert!(!node.uses_v3());
+    assert_eq!(v3_requests.load(Ordering::Relaxed), 1);
+    assert_eq!(v2_requests.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn undecodable_ssz_falls_back_to_json() {
+    let json_requests = Arc::new(AtomicUsize::new(0));
+    let route = warp::path!("eth" / "v2" / "validator" / "blocks" / u64)
+        .and(warp::header::optional::<String>("accept"))
+        .map({
+            let json_requests = json_requests.clone();
+            move |_: u64, accept: Option<String>| {
+                if accept.is_some_and(|accept| accept.contains("octet-stream")) {
+                    warp::http::Response::new(vec![1, 2, 3])
+                } else {
+                    // Fails too, but shows that the fallback was taken.
+                    json_requests.fetch_add(1, Ordering::Relaxed);
+                    let mut response = warp::http::Response::new(vec![]);
+                    *response.status_mut() = warp::http::StatusCode::INTERNAL_SERVER_ERROR;
+                    response
+                }
+            }
+        });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    for (ssz_fallback, expected_json_requests) in [(false, 0), (true, 1)] {
+        json_requests.store(0, Ordering::Relaxed);
+        let node = test_node(address, &format!("ssz_fallback = {ssz_fallback}"));
+        let result = node
+            .get_block_with_timeout::<MainnetEthSpec>(Slot::new(1), None, None)
+            .await;
+        assert!(result.is_err());
+        assert_eq!(
+            json_requests.load(Ordering::Relaxed),
+            expected_json_requests
+        );
+    }
+}
+
+#[tokio::test]
+async fn json_response_to_ssz_request_is_an_error() {
+    let route = warp::path!("eth" / "v2" / "validator" / "blocks" / u64)
+        .and(warp::header::<String>("accept"))
+        .map(|_: u64, accept: String| {
+            assert_eq!(accept, "application/octet-stream");
+            warp::reply::json(&serde_json::json!({ "version": "deneb", "data": {} }))
+        });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let node = test_node(address, "");
+    let err = node
+        .get_block_with_timeout::<MainnetEthSpec>(Slot::new(1), None, None)
+        .await
+        .unwrap_err();
+    assert!(err.contains("Content-Type"), "{err}");
+    assert!(err.contains("application/json"), "{err}");
+}
+
+#[tokio::test]
+async fn load_version_reports_unreachable_node() {
+    let route = warp::any()
+        .map(|| warp::reply::with_status("error", warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let mut node = test_node(address, "");
+    let err = node.load_version().await.unwrap_err();
+    assert!(err.contains("test"), "{err}");
+    assert_eq!(node.version, None);
+}
+
+#[tokio::test]
+async fn request_headers_are_attached() {
+    // Echo the routing header back as the node version.
+    let route = warp::path!("eth" / "v1" / "node" / "version")
+        .and(warp::header::<String>("x-client-hint"))
+        .map(|hint: String| warp::reply::json(&serde_json::json!({ "data": { "version": hint } })));
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let node = test_node(
+        address,
+        r#"
+        [headers]
+        X-Client-Hint = "lighthouse"
+        "#,
+    );
+
+    let version = node.client.get_node_version().await.unwrap().data.version;
+    assert_eq!(version, "lighthouse");
+}
+
+#[tokio::test]
+async fn auth_token_is_attached() {
+    let route = warp::path!("eth" / "v1" / "node" / "version")
+        .and(warp::header::<String>("authorization"))
+        .map(|auth: String| warp::reply::json(&serde_json::json!({ "data": { "version": auth } })));
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let node = test_node(address, r#"auth_token = "hunter2""#);
+    assert!(!format!("{:?}", node.config).contains("hunter2"));
+
+    let version = node.client.get_node_version().await.unwrap().data.version;
+    assert_eq!(version, "Bearer hunter2");
+}
+
+#[test]
+fn auth_token_conflicts_with_authorization_header() {
+    let config = test_node_config(
+        "localhost:5052",
+        r#"
+        auth_token = "hunter2"
+
+        [headers]
+        Authorization = "Basic aHVudGVyMg=="
+        "#,
+    );
+    assert!(config.header_map().is_err());
+}
+
+#[test]
+fn future_slot_block_is_an_error() {
+    let spec = ChainSpec::mainnet();
+    let mut block = BlindedBeaconBlock::<MainnetEthSpec>::empty(&spec);
+    *block.slot_mut() = Slot::new(11);
+
+    let error = future_slot_error(Slot::new(10), &block).unwrap();
+    assert!(error.contains("future slot 11"), "{error}");
+    assert_eq!(future_slot_error(Slot::new(11), &block), None);
+    // Past slots are only flagged by `check_unsigned_proposal`.
+    assert_eq!(future_slot_error(Slot::new(12), &block), None);
+
+    let config = test_node_config("localhost:5052", "");
+    assert_eq!(config.future_slot, FutureSlotHandling::Reject);
+}
+
+#[test]
+fn unexpected_randao_reveal_is_an_anomaly() {
+    let spec = ChainSpec::mainnet();
+    let block = BlindedBeaconBlock::<MainnetEthSpec>::empty(&spec);
+    let block_reveal = SignatureBytes::from(block.body().randao_reveal().clone());
+    let infinity = SignatureBytes::from(Signature::infinity().unwrap());
+    assert_ne!(block_reveal, infinity);
+
+    let anomalies = unsigned_proposal_anomalies(&spec, Slot::new(0), &infinity, &block, None);
+    assert_eq!(anomalies, vec!["randao_reveal is not the one requested"]);
+    let anomalies = unsigned_proposal_anomalies(&spec, Slot::new(0), &block_reveal, &block, None);
+    assert!(anomalies.is_empty(), "{anomalies:?}");
+}
+
+#[test]
+fn blobs_are_only_summarized_for_block_contents() {
+    type E = MainnetEthSpec;
+    let spec = ChainSpec::mainnet();
+    let block =
+        FullBlockContents::<E>::Block(BeaconBlock::Capella(BeaconBlockCapella::empty(&spec)));
+    assert_eq!(BlobSummary::new(&block), None);
+
+    let commitments = vec![KzgCommitment::empty_for_testing(); 2];
+    let mut inner = BeaconBlockDeneb::<E>::empty(&spec);
+    inner.body.blob_kzg_commitments = commitments.clone().into();
+    let block_contents = FullBlockContents::BlockContents(BlockContents {
+        block: BeaconBlock::Deneb(inner),
+        kzg_proofs: vec![KzgProof::empty(); 2].into(),
+        blobs: vec![Blob::<E>::default(); 2].into(),
+    });
+    let summary = BlobSummary::new(&block_contents).unwrap();
+    assert_eq!(summary.count, 2);
+    assert_eq!(summary.kzg_commitments, commitments);
+}
+
+#[tokio::test]
+async fn canonical_randao_reveal_is_shared() {
+    let spec = ChainSpec::mainnet();
+    let block = SignedBeaconBlock::from_block(
+        BeaconBlock::<MainnetEthSpec>::empty(&spec),
+        Signature::empty(),
+    );
+    let expected = SignatureBytes::from(block.message().body().randao_reveal().clone());
+    let route = warp::path!("eth" / "v2" / "beacon" / "blocks" / u64).map(move |slot: u64| {
+        if slot == 1 {
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "version": "phase0", "data": block })),
+                warp::http::StatusCode::OK,
+            )
+        } else {
+            warp::reply::with_status(
+                warp::reply::json(&serde_json::json!({ "code": 404, "message": "not found" })),
+                warp::http::StatusCode::NOT_FOUND,
+            )
+        }
+    });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    let canonical_bn = BeaconNodeHttpClient::new(
+        SensitiveUrl::parse(&format!("http://{address}")).unwrap(),
+        Timeouts::set_all(Duration::from_secs(1)),
+    );
+
+    let node = test_node("localhost:5052", "skip_randao_verification = true");
+    let timeout = Duration::from_secs(1);
+
+    // The canonical reveal is verified, even if the node would otherwise skip verification.
+    let reveal =
+        canonical_randao_reveal::<MainnetEthSpec>(&canonical_bn, Slot::new(1), timeout).await;
+    assert_eq!(reveal.as_ref(), Some(&expected));
+    let (randao_reveal, skip) = node.randao_reveal(reveal.as_ref());
+    assert_eq!(randao_reveal, expected);
+    assert!(matches!(skip, SkipRandaoVerification::No));
+
+    // Unpublished blocks fall back to the infinity signature.
+    let reveal =
+        canonical_randao_reveal::<MainnetEthSpec>(&canonical_bn, Slot::new(2), timeout).await;
+    assert_eq!(reveal, None);
+    let (randao_reveal, skip) = node.randao_reveal(reveal.as_ref());
+    assert_eq!(
+        randao_reveal,
+        SignatureBytes::from(Signature::infinity().unwrap())
+    );
+    assert!(matches!(skip, SkipRandaoVerification::Yes));
+}
@@ -0,0 +1,40 @@
+use crate::parquet_sink::ParquetSink;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("blockdreamer-parquet-{}", Uuid::new_v4()))
+}
+
+#[cfg(not(feature = "parquet"))]
+#[test]
+fn parquet_feature_is_required() {
+    let err = ParquetSink::new(temp_dir(), "run", 1).err().unwrap();
+    assert!(err.contains("`parquet` feature"), "{err}");
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn distances_are_written_per_run_and_slot_range() {
+    use eth2::types::Slot;
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use std::collections::HashMap;
+
+    let dir = temp_dir();
+    let mut sink = ParquetSink::new(dir.clone(), "run", 2).unwrap();
+    let (a, b, c) = ("a".to_string(), "b".to_string(), "c".to_string());
+    let labels = HashMap::from([(a.clone(), "A".to_string()), (b.clone(), "B".to_string())]);
+
+    sink.push_pairs(Slot::new(10), &[(&a, &b, 3), (&a, &c, 5)], &labels);
+    // All nodes agree at the next slot.
+    sink.push_pairs(Slot::new(11), &[(&a, &b, 0)], &labels);
+
+    let path = dir.join("distances-run-10-11.parquet");
+    let reader = SerializedFileReader::new(std::fs::File::open(&path).unwrap()).unwrap();
+    assert_eq!(reader.metadata().file_metadata().num_rows(), 3);
+
+    // Nothing buffered, so nothing to write.
+    sink.flush().unwrap();
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,29 @@
+use crate::percentiles::{percentile, DistancePercentiles, PercentileSummary};
+use eth2::types::Slot;
+
+#[test]
+fn nearest_rank_percentile() {
+    let sorted = (1..=100).collect::<Vec<usize>>();
+    assert_eq!(percentile(&sorted, 50.0), Some(50));
+    assert_eq!(percentile(&sorted, 99.0), Some(99));
+    assert_eq!(percentile(&[7], 90.0), Some(7));
+    assert_eq!(percentile(&[], 50.0), None);
+}
+
+#[test]
+fn old_distances_leave_the_window() {
+    let percentiles = DistancePercentiles::new(10);
+    for slot in 0..20 {
+        percentiles.record(Slot::new(slot), &[("lighthouse", slot as usize)]);
+    }
+    // Only slots 10..20 remain.
+    assert_eq!(
+        percentiles.summary()["lighthouse"],
+        PercentileSummary {
+            samples: 10,
+            p50: 14,
+            p90: 18,
+            p99: 19,
+        }
+    );
+}
@@ -0,0 +1,104 @@
+use crate::config::PostEndpointConfig;
+use crate::metrics::Metrics;
+use crate::post::{quorum_parent, PostEndpoint};
+use eth2::types::{BlindedBeaconBlock, ChainSpec, Hash256, MainnetEthSpec, Slot};
+use std::sync::Arc;
+use warp::Filter;
+
+#[test]
+fn quorum_parent_drops_lagging_node() {
+    let head = Hash256::repeat_byte(1);
+    let lagging = Hash256::repeat_byte(2);
+    assert_eq!(quorum_parent(&[head, lagging, head], 0.51), Some(head));
+    assert_eq!(quorum_parent(&[head, lagging, head, lagging], 0.51), None);
+    assert_eq!(quorum_parent(&[head, lagging, head], 1.0), None);
+    assert_eq!(quorum_parent(&[], 0.51), None);
+}
+
+#[test]
+fn same_parent_quorum_must_be_a_majority() {
+    for (quorum, valid) in [(0.5, false), (0.51, true), (1.0, true), (1.5, false)] {
+        let config: PostEndpointConfig = toml::from_str(&format!(
+            r#"
+            name = "blockgauge"
+            url = "http://localhost:5052"
+            same_parent_quorum = {quorum}
+            "#
+        ))
+        .unwrap();
+        let metrics = Arc::new(Metrics::new().unwrap());
+        assert_eq!(
+            PostEndpoint::new(&config, "run", metrics).is_ok(),
+            valid,
+            "{quorum}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn skipped_post_is_counted() {
+    let config: PostEndpointConfig = toml::from_str(
+        r#"
+        name = "blockgauge"
+        url = "http://localhost:5052"
+        skip_on_node_error = true
+        "#,
+    )
+    .unwrap();
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let endpoint = PostEndpoint::new(&config, "run", metrics.clone()).unwrap();
+
+    let node_info = vec![("lh-1".to_string(), "Lighthouse".to_string(), None)];
+    endpoint
+        .post_blocks::<MainnetEthSpec>(node_info, vec![Err("timeout".into())], Slot::new(1))
+        .await
+        .unwrap();
+
+    assert!(metrics
+        .encode()
+        .unwrap()
+        .contains(r#"blockdreamer_posts_total{endpoint="blockgauge",outcome="skipped"} 1"#));
+}
+
+#[tokio::test]
+async fn missing_rewards_do_not_fail_the_post() {
+    // Rewards for the first block only.
+    let route = warp::post().map(|| {
+        warp::reply::json(&serde_json::json!([
+            {"total": 10, "attestation_rewards": {"total": 8}},
+            {},
+        ]))
+    });
+    let (address, server) = warp::serve(route).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let config: PostEndpointConfig = toml::from_str(&format!(
+        r#"
+        name = "blockgauge"
+        url = "http://{address}"
+        compare_rewards = true
+        "#
+    ))
+    .unwrap();
+    let metrics = Arc::new(Metrics::new().unwrap());
+    let endpoint = PostEndpoint::new(&config, "run", metrics.clone()).unwrap();
+
+    let block = BlindedBeaconBlock::<MainnetEthSpec>::empty(&ChainSpec::mainnet());
+    let node_info = vec![
+        ("lh-1".to_string(), "Lighthouse".to_string(), None),
+        ("teku-1".to_string(), "Teku".to_string(), None),
+    ];
+    endpoint
+        .post_blocks(
+            node_info,
+            Arc::new(vec![Ok((block.clone(), None)), Ok((block, None))]),
+            Slot::new(1),
+        )
+        .await
+        .unwrap();
+
+    assert!(metrics
+        .encode()
+        .unwrap()
+        .contains(r#"blockdreamer_posts_total{endpoint="blockgauge",outcome="success"} 1"#));
+}
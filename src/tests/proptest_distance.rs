@@ -1,4 +1,4 @@
-use crate::distance::Distance;
+use crate::distance::{leading_attestations, sort_attestations, Distance, DistanceParams};
 use eth2::types::{
     AggregateSignature, Attestation, AttestationData, BitList, Checkpoint, EthSpec, Hash256,
     MainnetEthSpec, Slot, Unsigned,
@@ -84,10 +84,12 @@ proptest! {
         atts1 in arb_attestations(),
         atts2 in arb_attestations(),
     ) {
+        let params = DistanceParams::default();
+
         // Symmetry.
-        let distance = atts1.as_slice().distance(&atts2.as_slice())
+        let distance = atts1.as_slice().distance(&atts2.as_slice(), &params)
             .expect("distance is always defined");
-        let distance_rev = atts2.as_slice().distance(&atts1.as_slice())
+        let distance_rev = atts2.as_slice().distance(&atts1.as_slice(), &params)
             .expect("distance is always defined");
         assert_eq!(distance, distance_rev);
 
@@ -105,10 +107,80 @@ proptest! {
         y in arb_attestations(),
         z in arb_attestations()
     ) {
-        let x_y = x.as_slice().distance(&y.as_slice()).unwrap();
-        let y_z = y.as_slice().distance(&z.as_slice()).unwrap();
-        let x_z = x.as_slice().distance(&z.as_slice()).unwrap();
+        let params = DistanceParams::default();
+        let x_y = x.as_slice().distance(&y.as_slice(), &params).unwrap();
+        let y_z = y.as_slice().distance(&z.as_slice(), &params).unwrap();
+        let x_z = x.as_slice().distance(&z.as_slice(), &params).unwrap();
 
         assert!(x_z <= x_y + y_z);
     }
+
+    #[test]
+    fn sorted_attestations_ignore_client_order(atts in arb_attestations()) {
+        let params = DistanceParams::default();
+        let mut sorted = atts.clone();
+        let mut reverse_sorted = atts.into_iter().rev().collect::<Vec<_>>();
+        sort_attestations(&mut sorted);
+        sort_attestations(&mut reverse_sorted);
+
+        let distance = sorted.as_slice().distance(&reverse_sorted.as_slice(), &params).unwrap();
+        assert_eq!(distance, 0);
+    }
+
+    #[test]
+    fn truncated_attestations_ignore_tail(
+        atts in arb_attestations(),
+        tail1 in arb_attestations(),
+        tail2 in arb_attestations(),
+    ) {
+        prop_assume!(!atts.is_empty());
+        let params = DistanceParams::default();
+        let max = atts.len();
+        let atts1 = atts.iter().chain(&tail1).cloned().collect::<Vec<_>>();
+        let atts2 = atts.iter().chain(&tail2).cloned().collect::<Vec<_>>();
+
+        let truncated1 = leading_attestations(&atts1, max);
+        let truncated2 = leading_attestations(&atts2, max);
+        assert_eq!(truncated1.len(), max);
+        assert_eq!(truncated1.distance(&truncated2, &params), Some(0));
+
+        // 0 disables truncation.
+        assert_eq!(leading_attestations(&atts1, 0).len(), atts1.len());
+    }
+
+    #[test]
+    fn distance_within_max_distance(
+        atts1 in arb_attestations(),
+        atts2 in arb_attestations(),
+    ) {
+        let params = DistanceParams::default();
+        let (atts1, atts2) = (atts1.as_slice(), atts2.as_slice());
+        let distance = atts1.distance(&atts2, &params).unwrap();
+        let max_distance = atts1.max_distance(&atts2, &params).unwrap();
+        assert!(distance <= max_distance, "{distance} > {max_distance}");
+
+        let normalized = atts1.normalized_distance(&atts2, &params).unwrap();
+        assert!((0.0..=1.0).contains(&normalized));
+        assert_eq!(atts1.normalized_distance(&atts1, &params), Some(0.0));
+    }
+
+    #[test]
+    fn insertions_cost_indel_cost(
+        atts in arb_attestations(),
+        indel_cost in 0..256usize,
+    ) {
+        let params = DistanceParams {
+            indel_cost,
+            ..DistanceParams::default()
+        };
+        let num_set_bits = atts
+            .iter()
+            .map(|att| att.aggregation_bits.num_set_bits())
+            .sum::<usize>();
+        let empty: &[Attestation<E>] = &[];
+        assert_eq!(
+            atts.as_slice().distance(&empty, &params),
+            Some(num_set_bits + atts.len() * indel_cost)
+        );
+    }
 }
@@ -0,0 +1,154 @@
+use crate::clock::ManualTicker;
+use crate::config::Config;
+use crate::{collapse_identical_blocks, run, Monitoring, E};
+use eth2::types::{BlindedBeaconBlock, ChainSpec, Slot};
+use slot_clock::ManualSlotClock;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+use warp::Filter;
+
+const GENESIS: Duration = Duration::from_secs(1_000);
+const SLOT_DURATION: Duration = Duration::from_secs(12);
+
+/// Start a node that fails every request, which is enough to drive the loop.
+///
+/// Returns its address and the number of block requests made to it.
+fn start_failing_node() -> (SocketAddr, Arc<AtomicUsize>) {
+    let block_requests = Arc::new(AtomicUsize::new(0));
+    let blocks = warp::path!("eth" / "v2" / "validator" / "blocks" / u64).map({
+        let block_requests = block_requests.clone();
+        move |_: u64| {
+            block_requests.fetch_add(1, Ordering::Relaxed);
+            warp::reply::with_status("error", warp::http::StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    });
+    let other = warp::any()
+        .map(|| warp::reply::with_status("error", warp::http::StatusCode::INTERNAL_SERVER_ERROR));
+    let (address, server) = warp::serve(blocks.or(other)).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+    (address, block_requests)
+}
+
+/// Run the main loop against the node at `address`, driven by `ticker`.
+fn spawn_run(
+    address: SocketAddr,
+    ticker: ManualTicker,
+    shutdown_signal: Arc<AtomicBool>,
+) -> JoinHandle<Result<(), String>> {
+    let config: Config = toml::from_str(&format!(
+        r#"
+        network = "mainnet"
+        canonical_bn = "http://{address}"
+
+        [[nodes]]
+        name = "test"
+        label = "Test"
+        url = "http://{address}"
+        ssz = false
+        "#
+    ))
+    .unwrap();
+    let monitoring = Monitoring::start(&config).unwrap();
+    tokio::spawn(run(
+        shutdown_signal,
+        ticker,
+        config,
+        Arc::new(ChainSpec::mainnet()),
+        GENESIS.as_secs(),
+        monitoring,
+        false,
+    ))
+}
+
+async fn wait_for_requests(block_requests: &AtomicUsize, expected_requests: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while block_requests.load(Ordering::Relaxed) < expected_requests {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+}
+
+/// Stop the loop, which exits after the slot it is woken up for.
+async fn shut_down(
+    ticker: &ManualTicker,
+    shutdown_signal: &AtomicBool,
+    handle: JoinHandle<Result<(), String>>,
+) {
+    shutdown_signal.store(true, Ordering::Relaxed);
+    ticker.advance_slot();
+    let result = tokio::time::timeout(Duration::from_secs(5), handle)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(result, Ok(()));
+}
+
+#[tokio::test]
+async fn blocks_are_requested_each_slot() {
+    let (address, block_requests) = start_failing_node();
+    let ticker = ManualTicker::new(ManualSlotClock::new(Slot::new(0), GENESIS, SLOT_DURATION));
+    let shutdown_signal = Arc::new(AtomicBool::new(false));
+    let handle = spawn_run(address, ticker.clone(), shutdown_signal.clone());
+
+    for expected_requests in 1..=2 {
+        ticker.advance_slot();
+        wait_for_requests(&block_requests, expected_requests).await;
+    }
+
+    shut_down(&ticker, &shutdown_signal, handle).await;
+}
+
+#[tokio::test]
+async fn slots_are_skipped_while_the_clock_reads_before_genesis() {
+    let (address, block_requests) = start_failing_node();
+    let slot_clock = ManualSlotClock::new(Slot::new(0), GENESIS, SLOT_DURATION);
+    let ticker = ManualTicker::new(slot_clock.clone());
+    let shutdown_signal = Arc::new(AtomicBool::new(false));
+    let handle = spawn_run(address, ticker.clone(), shutdown_signal.clone());
+
+    // The clock steps back past genesis mid-run, e.g. after an NTP step.
+    slot_clock.set_current_time(GENESIS - Duration::from_secs(5));
+    ticker.wake();
+    ticker.wake();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(block_requests.load(Ordering::Relaxed), 0);
+    assert!(!handle.is_finished());
+
+    // Requests resume once the clock is corrected.
+    slot_clock.set_current_time(GENESIS + SLOT_DURATION);
+    ticker.advance_slot();
+    wait_for_requests(&block_requests, 1).await;
+
+    shut_down(&ticker, &shutdown_signal, handle).await;
+}
+
+#[test]
+fn collapsed_entries_list_their_members() {
+    let block = BlindedBeaconBlock::<E>::empty(&ChainSpec::mainnet());
+    let mut other_block = block.clone();
+    *other_block.slot_mut() = Slot::new(1);
+    // Node names may contain `+` themselves.
+    let dream_blocks = HashMap::from([
+        ("lh+1".to_string(), block.clone()),
+        ("lh+2".to_string(), block),
+        ("teku".to_string(), other_block),
+    ]);
+    let labels = HashMap::from([
+        ("lh+1".to_string(), "Lighthouse".to_string()),
+        ("lh+2".to_string(), "Lighthouse".to_string()),
+        ("teku".to_string(), "Teku".to_string()),
+    ]);
+
+    let (entries, entry_labels, entry_members) =
+        collapse_identical_blocks(Slot::new(0), &dream_blocks, &labels);
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entry_members["lh+1+lh+2"], vec!["lh+1", "lh+2"]);
+    assert_eq!(entry_members["teku"], vec!["teku"]);
+    assert_eq!(entry_labels["lh+1+lh+2"], "Lighthouse");
+}
@@ -0,0 +1,22 @@
+use crate::stale_block::repeated_roots;
+use eth2::types::Hash256;
+
+#[test]
+fn repeated_roots_reports_first_earlier_request() {
+    let a = Hash256::repeat_byte(1);
+    let b = Hash256::repeat_byte(2);
+    let roots = [
+        ("sample at 0ms".to_string(), a),
+        ("sample at 1000ms".to_string(), b),
+        ("sample at 2000ms".to_string(), a),
+        ("sample at 3000ms".to_string(), a),
+    ];
+    assert_eq!(
+        repeated_roots(&roots),
+        vec![
+            ("sample at 0ms", "sample at 2000ms", a),
+            ("sample at 0ms", "sample at 3000ms", a),
+        ]
+    );
+    assert!(repeated_roots(&roots[..2]).is_empty());
+}
@@ -0,0 +1,48 @@
+use crate::matrix::VerdictSummary;
+use crate::verdict_socket::VerdictSocket;
+use eth2::types::Slot;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio::net::UnixStream;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn verdicts_are_streamed_to_clients() {
+    let path = std::env::temp_dir().join(format!("blockdreamer-{}.sock", Uuid::new_v4()));
+    let socket = VerdictSocket::bind(path.clone()).unwrap();
+
+    let mut clients = vec![];
+    for _ in 0..2 {
+        let stream = UnixStream::connect(&path).await.unwrap();
+        clients.push(BufReader::new(stream).lines());
+    }
+    while socket.num_clients() < 2 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    socket.send(&VerdictSummary {
+        slot: Slot::new(100),
+        verdict: "two_closest_match",
+        label: Some("Lighthouse".into()),
+        confidence: 0.9,
+        distances: BTreeMap::from([("lighthouse".to_string(), 3)]),
+        versions: BTreeMap::from([("lighthouse".to_string(), "Lighthouse/v5.1.0".to_string())]),
+    });
+    for client in &mut clients {
+        let line = client.next_line().await.unwrap().unwrap();
+        let verdict: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(verdict["label"], "Lighthouse");
+        assert_eq!(verdict["distances"]["lighthouse"], 3);
+    }
+
+    drop(socket);
+    assert!(!path.exists());
+    for client in &mut clients {
+        let line = tokio::time::timeout(Duration::from_secs(5), client.next_line())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(line, None);
+    }
+}
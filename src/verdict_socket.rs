@@ -0,0 +1,102 @@
+//! Streaming of verdicts to co-located processes over a Unix domain socket.
+use crate::matrix::VerdictSummary;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use tracing::{error, warn};
+
+/// Number of verdicts buffered for each client before the oldest are dropped.
+const CLIENT_BUFFER: usize = 64;
+
+/// Delay before accepting again after a failure, e.g. when out of file descriptors.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Socket that streams each verdict as a line of JSON to all connected clients.
+///
+/// When this is dropped, the socket file is removed and clients are disconnected.
+pub struct VerdictSocket {
+    path: PathBuf,
+    sender: broadcast::Sender<String>,
+    /// Task accepting clients, which holds a sender of its own to subscribe them with.
+    accept_task: JoinHandle<()>,
+}
+
+impl VerdictSocket {
+    /// Bind to `path`, replacing a stale socket left behind by a previous run.
+    pub fn bind(path: PathBuf) -> Result<Self, String> {
+        if std::fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_socket()) {
+            std::fs::remove_file(&path)
+                .map_err(|e| format!("unable to remove stale socket {}: {e}", path.display()))?;
+        }
+        let listener = UnixListener::bind(&path)
+            .map_err(|e| format!("unable to bind verdict socket {}: {e}", path.display()))?;
+        let (sender, _) = broadcast::channel(CLIENT_BUFFER);
+        let accept_task = tokio::spawn(accept_clients(listener, sender.clone()));
+        Ok(Self {
+            path,
+            sender,
+            accept_task,
+        })
+    }
+
+    /// Number of clients currently connected.
+    pub fn num_clients(&self) -> usize {
+        self.sender.receiver_count()
+    }
+
+    pub fn send(&self, summary: &VerdictSummary) {
+        let mut line = match serde_json::to_string(summary) {
+            Ok(line) => line,
+            Err(e) => {
+                error!("unable to serialize verdict: {e}");
+                return;
+            }
+        };
+        line.push('\n');
+        // Fails only if no clients are connected.
+        let _ = self.sender.send(line);
+    }
+}
+
+impl Drop for VerdictSocket {
+    fn drop(&mut self) {
+        // Dropping the last sender closes the channel, which ends each client's stream.
+        self.accept_task.abort();
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn accept_clients(listener: UnixListener, sender: broadcast::Sender<String>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(stream_to_client(stream, sender.subscribe()));
+            }
+            Err(e) => {
+                warn!("unable to accept verdict socket client: {e}");
+                tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Write verdicts to a client until it disconnects.
+async fn stream_to_client(mut stream: UnixStream, mut receiver: broadcast::Receiver<String>) {
+    loop {
+        match receiver.recv().await {
+            Ok(line) => {
+                if stream.write_all(line.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("verdict socket client is lagging, skipped {skipped} verdicts");
+            }
+            Err(RecvError::Closed) => return,
+        }
+    }
+}